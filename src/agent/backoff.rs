@@ -0,0 +1,83 @@
+use super::config::ReconnectPolicy;
+use rand::Rng;
+use std::time::Duration;
+
+/// Delays between reconnect attempts: doubling from the policy's delay up to
+/// its cap, each spread by the jitter so agents cut off together don't all
+/// come back at the same moment.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    // Delays handed out since the last reset
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(policy: &ReconnectPolicy) -> Self {
+        Self {
+            initial: policy.delay(),
+            max: policy.max_delay().max(policy.delay()),
+            jitter: policy.jitter.clamp(0.0, 1.0),
+            attempt: 0,
+        }
+    }
+
+    /// Back to the initial delay, e.g. once connected again.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt);
+        let delay = self.initial.saturating_mul(factor).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        let spread = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        delay.mul_f64(1.0 + spread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> ReconnectPolicy {
+        ReconnectPolicy {
+            delay_secs: 5,
+            max_delay_secs: 60,
+            jitter,
+            max_attempts: None,
+        }
+    }
+
+    #[test]
+    fn doubles_up_to_the_cap_and_resets() {
+        let mut backoff = Backoff::new(&policy(0.0));
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40, 60, 60]);
+
+        // Long outages can't overflow the doubling
+        for _ in 0..100 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), Duration::from_secs(60));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction() {
+        let mut backoff = Backoff::new(&policy(0.2));
+        for _ in 0..50 {
+            backoff.reset();
+            let delay = backoff.next_delay().as_secs_f64();
+            assert!((4.0..=6.0).contains(&delay), "{}", delay);
+        }
+    }
+}
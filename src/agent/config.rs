@@ -0,0 +1,474 @@
+use crate::heartbeat::HeartbeatConfig;
+use crate::protocol::Metric;
+use crate::tls::ClientTls;
+use clap::Parser;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, io};
+
+// Picked up from the working directory when no --config is given
+const DEFAULT_CONFIG_PATH: &str = "agent.toml";
+
+/// Command line of the agent. Every flag can also be set through the
+/// matching `SRD_*` environment variable; both override the config file.
+#[derive(Debug, Parser)]
+#[command(about = "Cloud server monitoring agent")]
+pub struct Cli {
+    /// TOML config file
+    #[arg(long, env = "SRD_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// IP address of the central server
+    #[arg(long, env = "SRD_IP")]
+    pub ip: Option<String>,
+    /// Port of the central server
+    #[arg(long, env = "SRD_PORT")]
+    pub port: Option<u16>,
+    /// ID for this server instance
+    #[arg(long, env = "SRD_SERVER_ID")]
+    pub server_id: Option<u32>,
+    /// Human readable name for this server instance, defaults to the hostname
+    #[arg(long, env = "SRD_SERVER_NAME")]
+    pub server_name: Option<String>,
+    /// Persistent UUID for this server instance
+    #[arg(long, env = "SRD_SERVER_UUID")]
+    pub server_uuid: Option<String>,
+    /// Milliseconds between samples
+    #[arg(long, env = "SRD_INTERVAL_MS")]
+    pub interval_ms: Option<u64>,
+    /// Comma separated metrics to send (cpu,ip,network,fs,memory)
+    #[arg(long, env = "SRD_METRICS", value_delimiter = ',')]
+    pub metrics: Option<Vec<String>>,
+    /// Send random values instead of real measurements
+    #[arg(long, env = "SRD_SIMULATE")]
+    pub simulate: bool,
+    /// Seconds to wait before the first reconnect, doubled on every failure
+    #[arg(long, env = "SRD_RECONNECT_DELAY")]
+    pub reconnect_delay: Option<u64>,
+    /// Longest wait between reconnects in seconds
+    #[arg(long, env = "SRD_MAX_RECONNECT_DELAY")]
+    pub max_reconnect_delay: Option<u64>,
+    /// Give up after this many failed connection attempts in a row
+    #[arg(long, env = "SRD_MAX_RECONNECT_ATTEMPTS")]
+    pub max_reconnect_attempts: Option<u32>,
+    /// Milliseconds between heartbeats, 0 disables them
+    #[arg(long, env = "SRD_HEARTBEAT_INTERVAL_MS")]
+    pub heartbeat_interval_ms: Option<u64>,
+    /// Reconnect after hearing nothing from the collector for this many milliseconds
+    #[arg(long, env = "SRD_PEER_TIMEOUT_MS")]
+    pub peer_timeout_ms: Option<u64>,
+    /// Seconds idle before TCP keepalive probes start, 0 disables them
+    #[arg(long, env = "SRD_KEEPALIVE_SECS")]
+    pub keepalive_secs: Option<u64>,
+    /// Base name of the files samples are kept in while the collector is
+    /// unreachable, segments get a number appended
+    #[arg(long, env = "SRD_SPOOL_PATH")]
+    pub spool_path: Option<PathBuf>,
+    /// Most samples kept in the spool, 0 disables it
+    #[arg(long, env = "SRD_SPOOL_MAX_RECORDS")]
+    pub spool_max_records: Option<usize>,
+    /// Connect with TLS, trusting collector certificates signed by this PEM CA
+    #[arg(long, env = "SRD_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+    /// Name the collector's certificate must be issued to, defaults to --ip
+    #[arg(long, env = "SRD_TLS_SERVER_NAME")]
+    pub tls_server_name: Option<String>,
+    /// Client certificate (PEM) to prove this agent to the collector with
+    #[arg(long, env = "SRD_TLS_CLIENT_CERT", requires = "tls_client_key")]
+    pub tls_client_cert: Option<PathBuf>,
+    /// Private key (PEM) of the client certificate
+    #[arg(long, env = "SRD_TLS_CLIENT_KEY", requires = "tls_client_cert")]
+    pub tls_client_key: Option<PathBuf>,
+    /// Pre-shared token the collector knows this agent by
+    #[arg(long, env = "SRD_TOKEN")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectPolicy {
+    // First delay, doubled after every failure up to max_delay_secs
+    pub delay_secs: u64,
+    pub max_delay_secs: u64,
+    // Each delay is moved randomly by up to this fraction of it
+    pub jitter: f64,
+    // None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            delay_secs: 5,
+            max_delay_secs: 300,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay_secs)
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.max_delay_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpoolPolicy {
+    pub path: PathBuf,
+    // Zero disables spooling, samples taken while disconnected are dropped
+    pub max_records: usize,
+}
+
+impl Default for SpoolPolicy {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("agent_spool.log"),
+            max_records: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatPolicy {
+    // Zero disables heartbeats, and with them the dead peer timeout
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    // Zero disables TCP keepalive
+    pub keepalive_secs: u64,
+}
+
+impl Default for HeartbeatPolicy {
+    fn default() -> Self {
+        let defaults = HeartbeatConfig::default();
+        Self {
+            interval_ms: defaults.interval.as_millis() as u64,
+            timeout_ms: defaults.timeout.as_millis() as u64,
+            keepalive_secs: defaults.keepalive.as_secs(),
+        }
+    }
+}
+
+impl HeartbeatPolicy {
+    pub fn config(&self) -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_millis(self.interval_ms),
+            timeout: Duration::from_millis(self.timeout_ms),
+            keepalive: Duration::from_secs(self.keepalive_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsPolicy {
+    pub ca: PathBuf,
+    pub server_name: Option<String>,
+    // Both or neither
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsPolicy {
+    pub fn client(&self) -> ClientTls {
+        ClientTls {
+            ca: self.ca.clone(),
+            server_name: self.server_name.clone(),
+            client_cert: self.client_cert.clone().zip(self.client_key.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    pub ip: String,
+    pub port: u16,
+    pub server_id: u32,
+    pub server_name: Option<String>,
+    pub server_uuid: Option<String>,
+    pub interval_ms: u64,
+    pub metrics: Vec<String>,
+    pub simulate: bool,
+    pub reconnect: ReconnectPolicy,
+    pub heartbeat: HeartbeatPolicy,
+    pub spool: SpoolPolicy,
+    // None connects in plaintext
+    pub tls: Option<TlsPolicy>,
+    // Sent in the handshake to collectors that only accept known agents
+    pub token: Option<String>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            ip: String::from("127.0.0.1"),
+            port: 8888,
+            server_id: 0,
+            server_name: None,
+            server_uuid: None,
+            interval_ms: 1000,
+            metrics: Metric::ALL.iter().map(|m| m.name().to_string()).collect(),
+            simulate: false,
+            reconnect: ReconnectPolicy::default(),
+            heartbeat: HeartbeatPolicy::default(),
+            spool: SpoolPolicy::default(),
+            tls: None,
+            token: None,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Build the configuration from defaults, the config file, then
+    /// environment variables and command line flags.
+    pub fn load() -> io::Result<Self> {
+        Self::load_from(Cli::parse())
+    }
+
+    fn load_from(cli: Cli) -> io::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Self::default(),
+        };
+        config.apply(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Zero intervals and delays would have the agent spin, sending or
+    // spooling as fast as it can. Unknown metrics would fail every sample.
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |message: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, message.to_string()));
+
+        if let Some(Metric::Other(name)) = self.metrics().into_iter().find(|m| matches!(m, Metric::Other(_))) {
+            let known: Vec<&str> = Metric::ALL.iter().map(|m| m.name()).collect();
+            return invalid(&format!("unknown metric \"{}\", expected some of {}", name, known.join(", ")));
+        }
+        if self.interval_ms == 0 {
+            return invalid("interval_ms has to be above 0");
+        }
+        if self.reconnect.delay_secs == 0 {
+            return invalid("reconnect.delay_secs has to be above 0");
+        }
+        if self.reconnect.max_delay_secs < self.reconnect.delay_secs {
+            return invalid("reconnect.max_delay_secs can't be below reconnect.delay_secs");
+        }
+        if !(0.0..=1.0).contains(&self.reconnect.jitter) {
+            return invalid("reconnect.jitter has to be between 0 and 1");
+        }
+        if let Some(tls) = &self.tls
+            && tls.client_cert.is_some() != tls.client_key.is_some()
+        {
+            return invalid("tls.client_cert and tls.client_key have to be set together");
+        }
+        self.heartbeat.config().validate()
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    fn apply(&mut self, cli: Cli) -> io::Result<()> {
+        if let Some(ip) = cli.ip {
+            self.ip = ip;
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(server_id) = cli.server_id {
+            self.server_id = server_id;
+        }
+        if cli.server_name.is_some() {
+            self.server_name = cli.server_name;
+        }
+        if cli.server_uuid.is_some() {
+            self.server_uuid = cli.server_uuid;
+        }
+        if let Some(interval_ms) = cli.interval_ms {
+            self.interval_ms = interval_ms;
+        }
+        if let Some(metrics) = cli.metrics {
+            self.metrics = metrics;
+        }
+        self.simulate |= cli.simulate;
+        if let Some(delay) = cli.reconnect_delay {
+            self.reconnect.delay_secs = delay;
+        }
+        if let Some(max_delay) = cli.max_reconnect_delay {
+            self.reconnect.max_delay_secs = max_delay;
+        }
+        if cli.max_reconnect_attempts.is_some() {
+            self.reconnect.max_attempts = cli.max_reconnect_attempts;
+        }
+        if let Some(interval_ms) = cli.heartbeat_interval_ms {
+            self.heartbeat.interval_ms = interval_ms;
+        }
+        if let Some(timeout_ms) = cli.peer_timeout_ms {
+            self.heartbeat.timeout_ms = timeout_ms;
+        }
+        if let Some(keepalive_secs) = cli.keepalive_secs {
+            self.heartbeat.keepalive_secs = keepalive_secs;
+        }
+        if let Some(path) = cli.spool_path {
+            self.spool.path = path;
+        }
+        if let Some(max_records) = cli.spool_max_records {
+            self.spool.max_records = max_records;
+        }
+        if let Some(ca) = cli.tls_ca {
+            match &mut self.tls {
+                Some(tls) => tls.ca = ca,
+                None => {
+                    self.tls = Some(TlsPolicy {
+                        ca,
+                        server_name: None,
+                        client_cert: None,
+                        client_key: None,
+                    })
+                }
+            }
+        }
+        match &mut self.tls {
+            Some(tls) => {
+                if cli.tls_server_name.is_some() {
+                    tls.server_name = cli.tls_server_name;
+                }
+                if cli.tls_client_cert.is_some() {
+                    tls.client_cert = cli.tls_client_cert;
+                    tls.client_key = cli.tls_client_key;
+                }
+            }
+            // Would otherwise connect in plaintext without a word
+            None if cli.tls_server_name.is_some() || cli.tls_client_cert.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--tls-server-name and --tls-client-cert need --tls-ca or a [tls] section",
+                ));
+            }
+            None => {}
+        }
+        if cli.token.is_some() {
+            self.token = cli.token;
+        }
+        Ok(())
+    }
+
+    pub fn server_address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn metrics(&self) -> Vec<Metric> {
+        self.metrics.iter().map(|name| Metric::from_name(name.trim())).collect()
+    }
+
+    /// Name announced to the collector, falling back to the hostname and
+    /// then to the numeric id.
+    pub fn display_name(&self) -> String {
+        self.server_name
+            .clone()
+            .or_else(|| {
+                fs::read_to_string("/proc/sys/kernel/hostname")
+                    .ok()
+                    .map(|h| h.trim().to_string())
+                    .filter(|h| !h.is_empty())
+            })
+            .unwrap_or_else(|| format!("server{}", self.server_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_reason(config: &AgentConfig) -> String {
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        error.to_string()
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let path = std::env::temp_dir().join(format!("srd-agent-config-{}.toml", std::process::id()));
+        fs::write(&path, "port = 1000\ninterval_ms = 2000\nserver_id = 3\n").unwrap();
+        // No other test parses a command line, so nothing else sees these
+        unsafe {
+            std::env::set_var("SRD_INTERVAL_MS", "3000");
+            std::env::set_var("SRD_SERVER_ID", "5");
+        }
+
+        let cli = Cli::try_parse_from(["server", "--config", path.to_str().unwrap(), "--server-id", "7"]).unwrap();
+        let config = AgentConfig::load_from(cli);
+        unsafe {
+            std::env::remove_var("SRD_INTERVAL_MS");
+            std::env::remove_var("SRD_SERVER_ID");
+        }
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.port, 1000);
+        assert_eq!(config.interval_ms, 3000);
+        assert_eq!(config.server_id, 7);
+        // Untouched by all three
+        assert_eq!(config.ip, AgentConfig::default().ip);
+    }
+
+    #[test]
+    fn the_default_config_is_valid() {
+        AgentConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_metric_names_are_rejected() {
+        let mut config = AgentConfig {
+            metrics: vec![String::from("cpu"), String::from(" memory ")],
+            ..AgentConfig::default()
+        };
+        config.validate().unwrap();
+
+        config.metrics.push(String::from("disk"));
+        assert!(invalid_reason(&config).contains("unknown metric \"disk\""));
+    }
+
+    #[test]
+    fn settings_that_would_spin_or_mislead_are_rejected() {
+        let config = AgentConfig {
+            interval_ms: 0,
+            ..AgentConfig::default()
+        };
+        assert!(invalid_reason(&config).contains("interval_ms"));
+
+        let mut config = AgentConfig::default();
+        config.reconnect.max_delay_secs = config.reconnect.delay_secs - 1;
+        assert!(invalid_reason(&config).contains("max_delay_secs"));
+
+        let mut config = AgentConfig::default();
+        config.reconnect.jitter = 1.5;
+        assert!(invalid_reason(&config).contains("jitter"));
+
+        let config = AgentConfig {
+            tls: Some(TlsPolicy {
+                ca: PathBuf::from("ca.pem"),
+                server_name: None,
+                client_cert: Some(PathBuf::from("agent.pem")),
+                client_key: None,
+            }),
+            ..AgentConfig::default()
+        };
+        assert!(invalid_reason(&config).contains("client_key"));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::protocol::Metric;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+// Link capacity assumed for interfaces that don't report a speed (virtual NICs)
+const DEFAULT_LINK_MBPS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NetCounters {
+    bytes: u64,
+    capacity_bps: u64,
+    at: Instant,
+}
+
+/// Samples real utilization of the host from `/proc` and `statvfs`.
+///
+/// CPU and network are rates, so they are computed against the previous
+/// call; the first sample is relative to boot.
+pub struct SystemSampler {
+    fs_path: CString,
+    last_cpu: Option<CpuTimes>,
+    last_net: Option<NetCounters>,
+}
+
+impl Default for SystemSampler {
+    fn default() -> Self {
+        Self::new("/")
+    }
+}
+
+impl SystemSampler {
+    pub fn new(fs_path: &str) -> Self {
+        let mut sampler = Self {
+            fs_path: CString::new(fs_path).unwrap_or_else(|_| CString::from(c"/")),
+            last_cpu: None,
+            last_net: None,
+        };
+
+        // Prime the rate counters so the first real sample is a delta
+        sampler.last_cpu = read_cpu_times().ok();
+        sampler.last_net = read_net_counters().ok();
+
+        sampler
+    }
+
+    /// Utilization in percent of a single metric.
+    pub fn sample(&mut self, metric: &Metric) -> io::Result<f64> {
+        let value = match metric {
+            Metric::Cpu => self.cpu_percent()?,
+            Metric::Memory => memory_percent()?,
+            Metric::Fs => fs_percent(&self.fs_path)?,
+            Metric::Network => self.network_percent()?,
+            Metric::Ip => ip_percent()?,
+            Metric::Other(name) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unknown metric {}", name)));
+            }
+        };
+
+        Ok(value.clamp(0.0, 100.0))
+    }
+
+    fn cpu_percent(&mut self) -> io::Result<f64> {
+        let now = read_cpu_times()?;
+        let prev = self.last_cpu.replace(now).unwrap_or(CpuTimes { busy: 0, total: 0 });
+
+        let total = now.total.saturating_sub(prev.total);
+        let busy = now.busy.saturating_sub(prev.busy);
+        if total == 0 {
+            return Ok(0.0);
+        }
+        Ok(busy as f64 * 100.0 / total as f64)
+    }
+
+    fn network_percent(&mut self) -> io::Result<f64> {
+        let now = read_net_counters()?;
+        let Some(prev) = self.last_net.replace(now) else {
+            return Ok(0.0);
+        };
+
+        let elapsed = now.at.duration_since(prev.at).as_secs_f64();
+        if elapsed <= 0.0 || now.capacity_bps == 0 {
+            return Ok(0.0);
+        }
+        let bits_per_sec = now.bytes.saturating_sub(prev.bytes) as f64 * 8.0 / elapsed;
+        Ok(bits_per_sec * 100.0 / now.capacity_bps as f64)
+    }
+}
+
+fn read_cpu_times() -> io::Result<CpuTimes> {
+    parse_cpu_times(&fs::read_to_string("/proc/stat")?)
+}
+
+fn parse_cpu_times(stat: &str) -> io::Result<CpuTimes> {
+    let line = stat
+        .lines()
+        .find(|l| l.starts_with("cpu "))
+        .ok_or_else(|| invalid("missing cpu line in /proc/stat"))?;
+
+    // user nice system idle iowait irq softirq steal ...
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|v| v.parse().unwrap_or(0))
+        .collect();
+    if fields.len() < 4 {
+        return Err(invalid("short cpu line in /proc/stat"));
+    }
+
+    // guest time is already included in user/nice
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+    Ok(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+fn memory_percent() -> io::Result<f64> {
+    parse_memory_percent(&fs::read_to_string("/proc/meminfo")?)
+}
+
+fn parse_memory_percent(meminfo: &str) -> io::Result<f64> {
+    let field = |name: &str| -> Option<u64> {
+        meminfo
+            .lines()
+            .find(|l| l.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()
+    };
+
+    let total = field("MemTotal:").ok_or_else(|| invalid("missing MemTotal in /proc/meminfo"))?;
+    let available = field("MemAvailable:")
+        .or_else(|| Some(field("MemFree:")? + field("Buffers:")? + field("Cached:")?))
+        .ok_or_else(|| invalid("missing MemAvailable in /proc/meminfo"))?;
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(total.saturating_sub(available) as f64 * 100.0 / total as f64)
+}
+
+fn fs_percent(path: &CString) -> io::Result<f64> {
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is a valid NUL terminated string and stat is a valid out pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Same calculation as df: used / (used + available to unprivileged users)
+    let used = (stat.f_blocks - stat.f_bfree) as f64;
+    let usable = used + stat.f_bavail as f64;
+    if usable == 0.0 {
+        return Ok(0.0);
+    }
+    Ok(used * 100.0 / usable)
+}
+
+fn read_net_counters() -> io::Result<NetCounters> {
+    let dev = fs::read_to_string("/proc/net/dev")?;
+    let mut bytes = 0;
+    let mut capacity_bps = 0;
+
+    for (iface, transferred) in parse_net_dev(&dev) {
+        bytes += transferred;
+
+        let speed_mbps = fs::read_to_string(format!("/sys/class/net/{}/speed", iface))
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .filter(|s| *s > 0)
+            .map(|s| s as u64)
+            .unwrap_or(DEFAULT_LINK_MBPS);
+        capacity_bps += speed_mbps * 1_000_000;
+    }
+
+    Ok(NetCounters {
+        bytes,
+        capacity_bps,
+        at: Instant::now(),
+    })
+}
+
+// Bytes received plus sent by every interface but loopback
+fn parse_net_dev(dev: &str) -> Vec<(&str, u64)> {
+    // Two header lines, then "iface: rx_bytes ... (8 rx fields) tx_bytes ..."
+    dev.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (iface, counters) = line.split_once(':')?;
+            let iface = iface.trim();
+            if iface == "lo" {
+                return None;
+            }
+
+            let fields: Vec<u64> = counters
+                .split_whitespace()
+                .map(|v| v.parse().unwrap_or(0))
+                .collect();
+            if fields.len() < 9 {
+                return None;
+            }
+            Some((iface, fields[0] + fields[8]))
+        })
+        .collect()
+}
+
+// Share of the local port range taken by TCP sockets, which is what runs out
+// first when a host opens too many connections
+fn ip_percent() -> io::Result<f64> {
+    let in_use = parse_tcp_in_use(&fs::read_to_string("/proc/net/sockstat")?)?;
+    let available = parse_port_range(&fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range")?)?;
+    Ok(in_use as f64 * 100.0 / available as f64)
+}
+
+// Sockets holding a local port: open plus in TIME_WAIT
+fn parse_tcp_in_use(sockstat: &str) -> io::Result<u64> {
+    let tcp = sockstat
+        .lines()
+        .find(|l| l.starts_with("TCP:"))
+        .ok_or_else(|| invalid("missing TCP line in /proc/net/sockstat"))?;
+
+    let tokens: Vec<&str> = tcp.split_whitespace().collect();
+    let count = |name: &str| -> u64 {
+        tokens
+            .iter()
+            .position(|t| *t == name)
+            .and_then(|i| tokens.get(i + 1))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    };
+    Ok(count("inuse") + count("tw"))
+}
+
+// Number of ports in the local port range
+fn parse_port_range(range: &str) -> io::Result<u64> {
+    let ports: Vec<u64> = range
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    match ports.as_slice() {
+        [low, high] if high >= low => Ok(high - low + 1),
+        _ => Err(invalid("bad ip_local_port_range")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "\
+cpu  4705 150 1120 16250 520 20 35 7 100 0
+cpu0 2350 75 560 8125 260 10 17 3 50 0
+intr 1462898
+ctxt 3442870
+";
+
+    const MEMINFO: &str = "\
+MemTotal:       16000000 kB
+MemFree:         2000000 kB
+MemAvailable:    4000000 kB
+Buffers:          500000 kB
+Cached:          3000000 kB
+";
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 147046929   22038    0    0    0     0          0         0 147046929   22038    0    0    0     0       0          0
+  eth0: 1000   10    0    0    0     0          0         0 500   5    0    0    0     0       0          0
+ wlan0:2000   20    0    0    0     0          0         0 3000   30    0    0    0     0       0          0
+  bad0: 12 13
+";
+
+    #[test]
+    fn cpu_times_leave_out_idle_and_iowait() {
+        let times = parse_cpu_times(STAT).unwrap();
+        // guest (100) is already counted in user and isn't added again
+        assert_eq!(times.total, 4705 + 150 + 1120 + 16250 + 520 + 20 + 35 + 7);
+        assert_eq!(times.busy, times.total - 16250 - 520);
+
+        assert!(parse_cpu_times("cpu0 1 2 3 4\n").is_err());
+        assert!(parse_cpu_times("cpu  1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn memory_in_use_is_what_isnt_available() {
+        assert_eq!(parse_memory_percent(MEMINFO).unwrap(), 75.0);
+
+        // Kernels before 3.14 have no MemAvailable
+        let old = MEMINFO.replace("MemAvailable:    4000000 kB\n", "");
+        assert_eq!(parse_memory_percent(&old).unwrap(), 65.625);
+
+        assert!(parse_memory_percent("MemFree: 1 kB\n").is_err());
+        assert_eq!(parse_memory_percent("MemTotal: 0 kB\nMemAvailable: 0 kB\n").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn network_counts_both_directions_without_loopback() {
+        assert_eq!(parse_net_dev(NET_DEV), [("eth0", 1500), ("wlan0", 5000)]);
+        assert!(parse_net_dev("").is_empty());
+    }
+
+    #[test]
+    fn ports_in_use_include_time_wait() {
+        let sockstat = "sockets: used 180\nTCP: inuse 40 orphan 0 tw 60 alloc 45 mem 3\nUDP: inuse 2 mem 1\n";
+        assert_eq!(parse_tcp_in_use(sockstat).unwrap(), 100);
+        assert!(parse_tcp_in_use("UDP: inuse 2 mem 1\n").is_err());
+
+        assert_eq!(parse_port_range("32768\t60999\n").unwrap(), 28232);
+        assert!(parse_port_range("60999 32768").is_err());
+        assert!(parse_port_range("").is_err());
+    }
+}
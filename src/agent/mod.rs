@@ -0,0 +1,5 @@
+pub mod backoff;
+pub mod config;
+pub mod metrics;
+pub mod spool;
+pub mod transport;
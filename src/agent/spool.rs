@@ -0,0 +1,352 @@
+use crate::protocol::{parse_frame, Frame, MetricRecord};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Lines written to the collector at once while replaying
+const REPLAY_BATCH: usize = 500;
+// The spool is kept in this many segments when full
+const SEGMENTS: usize = 10;
+
+/// Samples taken while the collector is unreachable, one `METRIC` line each
+/// with its original timestamp, oldest first.
+///
+/// The lines live in numbered segment files next to `path`, e.g.
+/// `agent_spool.log.3`, each holding at most a tenth of `max_records`.
+/// Once full, the oldest segment is deleted to make room, so a long outage
+/// keeps its most recent data without rewriting the rest.
+pub struct Spool {
+    path: PathBuf,
+    max_records: usize,
+    // Number and line count of every segment, oldest first
+    segments: VecDeque<(u64, usize)>,
+    // Append handle of the newest segment
+    file: Option<File>,
+    len: usize,
+}
+
+impl Spool {
+    /// Open the spool at `path`, keeping what a previous run left behind.
+    pub fn open(path: &Path, max_records: usize) -> io::Result<Self> {
+        let mut spool = Self {
+            path: path.to_path_buf(),
+            max_records,
+            segments: VecDeque::new(),
+            file: None,
+            len: 0,
+        };
+
+        let mut numbers = spool.existing_segments()?;
+        numbers.sort_unstable();
+        for number in numbers {
+            let segment_path = spool.segment_path(number);
+            let (lines, complete) = read_complete_lines(&segment_path)?;
+            // A line torn by a crash mid-write is cut off, appending after
+            // it would glue the next sample to it
+            if complete < fs::metadata(&segment_path)?.len() {
+                println!("Dropping a torn sample at the end of {}", segment_path.display());
+                OpenOptions::new().write(true).open(&segment_path)?.set_len(complete)?;
+            }
+            spool.segments.push_back((number, lines.len()));
+            spool.len += lines.len();
+        }
+
+        spool.enforce_limit()?;
+        Ok(spool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, records: &[MetricRecord]) -> io::Result<()> {
+        let mut records = records.iter().peekable();
+        while records.peek().is_some() {
+            let room = match self.segments.back() {
+                Some(&(_, lines)) if lines < self.segment_records() => self.segment_records() - lines,
+                _ => {
+                    self.start_segment()?;
+                    self.segment_records()
+                }
+            };
+            let file = match &mut self.file {
+                Some(file) => file,
+                None => {
+                    let number = self.segments.back().map_or(0, |segment| segment.0);
+                    self.file.insert(OpenOptions::new().create(true).append(true).open(self.segment_path(number))?)
+                }
+            };
+
+            let batch: Vec<&MetricRecord> = records.by_ref().take(room).collect();
+            let lines: String = batch.iter().map(|r| format!("{}\n", r)).collect();
+            file.write_all(lines.as_bytes())?;
+            if let Some(segment) = self.segments.back_mut() {
+                segment.1 += batch.len();
+            }
+            self.len += batch.len();
+        }
+
+        self.enforce_limit()
+    }
+
+    /// Hand the spooled lines to `send` in order, in batches. Whatever
+    /// wasn't sent when `send` fails stays spooled for the next attempt.
+    pub fn replay(&mut self, mut send: impl FnMut(&str) -> io::Result<()>) -> io::Result<usize> {
+        // The newest segment may be rewritten or deleted below
+        self.file = None;
+
+        let mut sent = 0;
+        while let Some(&(number, _)) = self.segments.front() {
+            let segment_path = self.segment_path(number);
+            let (lines, _) = read_complete_lines(&segment_path)?;
+            let lines: Vec<String> = lines
+                .into_iter()
+                .filter(|line| matches!(parse_frame(line), Some(Frame::Metric(_))))
+                .collect();
+
+            let mut sent_here = 0;
+            for batch in lines.chunks(REPLAY_BATCH) {
+                let message: String = batch.iter().map(|line| format!("{}\n", line)).collect();
+                if let Err(e) = send(&message) {
+                    self.keep_unsent(&lines[sent_here..])?;
+                    return Err(e);
+                }
+                sent_here += batch.len();
+            }
+
+            remove_if_present(&segment_path)?;
+            if let Some((_, count)) = self.segments.pop_front() {
+                self.len -= count;
+            }
+            sent += sent_here;
+        }
+
+        Ok(sent)
+    }
+
+    // Replace the oldest segment with the lines of it not sent yet, through
+    // a temporary file so a crash leaves either the old or the new one
+    fn keep_unsent(&mut self, lines: &[String]) -> io::Result<()> {
+        let Some(segment) = self.segments.front_mut() else {
+            return Ok(());
+        };
+        let segment_path = PathBuf::from(format!("{}.{}", self.path.display(), segment.0));
+
+        let tmp = segment_path.with_extension("tmp");
+        let contents: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &segment_path)?;
+
+        self.len = self.len - segment.1 + lines.len();
+        segment.1 = lines.len();
+        Ok(())
+    }
+
+    fn enforce_limit(&mut self) -> io::Result<()> {
+        let mut dropped = 0;
+        while self.len > self.max_records {
+            let Some((number, count)) = self.segments.pop_front() else {
+                break;
+            };
+            if self.segments.is_empty() {
+                self.file = None;
+            }
+            remove_if_present(&self.segment_path(number))?;
+            self.len -= count;
+            dropped += count;
+        }
+
+        if dropped > 0 {
+            println!("Spool full, dropped the {} oldest samples", dropped);
+        }
+        Ok(())
+    }
+
+    fn segment_records(&self) -> usize {
+        (self.max_records / SEGMENTS).max(1)
+    }
+
+    fn start_segment(&mut self) -> io::Result<()> {
+        let number = self.segments.back().map_or(0, |segment| segment.0 + 1);
+        self.file = Some(OpenOptions::new().create(true).append(true).open(self.segment_path(number))?);
+        self.segments.push_back((number, 0));
+        Ok(())
+    }
+
+    fn segment_path(&self, number: u64) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), number))
+    }
+
+    // Numbers of the segment files next to `path`
+    fn existing_segments(&self) -> io::Result<Vec<u64>> {
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let Some(prefix) = self.path.file_name().and_then(|name| name.to_str()).map(|name| format!("{}.", name)) else {
+            return Ok(Vec::new());
+        };
+
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut numbers = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(number) = name.to_str().and_then(|name| name.strip_prefix(&prefix)).and_then(|n| n.parse().ok()) {
+                numbers.push(number);
+            }
+        }
+        Ok(numbers)
+    }
+}
+
+// The newline terminated lines of a file and the length they take up, a
+// torn last line isn't one of them
+fn read_complete_lines(path: &Path) -> io::Result<(Vec<String>, u64)> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+
+    let complete = contents.iter().rposition(|&byte| byte == b'\n').map_or(0, |last| last + 1);
+    let lines = String::from_utf8_lossy(&contents[..complete]).lines().map(String::from).collect();
+    Ok((lines, complete as u64))
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Metric;
+    use chrono::{DateTime, Utc};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-spool-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn record(second: i64) -> MetricRecord {
+        MetricRecord {
+            timestamp: DateTime::<Utc>::from_timestamp(1_700_000_000 + second, 0).unwrap(),
+            server_id: 1,
+            metric: Metric::Cpu,
+            value: second as f64,
+            unit: String::from("%"),
+        }
+    }
+
+    fn records(seconds: std::ops::Range<i64>) -> Vec<MetricRecord> {
+        seconds.map(record).collect()
+    }
+
+    // Values of the replayed lines, in the order they were sent
+    fn replay_values(spool: &mut Spool) -> Vec<f64> {
+        let mut values = Vec::new();
+        spool
+            .replay(|message| {
+                for line in message.lines() {
+                    let Some(Frame::Metric(record)) = parse_frame(line) else {
+                        panic!("not a sample: {}", line);
+                    };
+                    values.push(record.value);
+                }
+                Ok(())
+            })
+            .unwrap();
+        values
+    }
+
+    #[test]
+    fn a_full_spool_drops_its_oldest_segment() {
+        let dir = TempDir::new("overflow");
+        let path = dir.0.join("spool.log");
+        let mut spool = Spool::open(&path, 20).unwrap();
+
+        spool.push(&records(0..15)).unwrap();
+        spool.push(&records(15..21)).unwrap();
+        assert_eq!(spool.len(), 19);
+        assert!(!dir.0.join("spool.log.0").exists());
+
+        // What was left survives a restart
+        drop(spool);
+        let mut spool = Spool::open(&path, 20).unwrap();
+        assert_eq!(spool.len(), 19);
+        assert_eq!(replay_values(&mut spool), (2..21).map(|v| v as f64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_torn_last_line_is_not_counted_or_replayed() {
+        let dir = TempDir::new("torn");
+        let path = dir.0.join("spool.log");
+        let mut spool = Spool::open(&path, 100).unwrap();
+        spool.push(&records(0..3)).unwrap();
+        drop(spool);
+
+        let torn = format!("{}", record(3));
+        let mut file = OpenOptions::new().append(true).open(dir.0.join("spool.log.0")).unwrap();
+        file.write_all(&torn.as_bytes()[..torn.len() - 8]).unwrap();
+        drop(file);
+
+        let mut spool = Spool::open(&path, 100).unwrap();
+        assert_eq!(spool.len(), 3);
+        spool.push(&records(4..5)).unwrap();
+        assert_eq!(replay_values(&mut spool), [0.0, 1.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn replaying_clears_the_spool_and_a_failure_keeps_the_rest() {
+        let dir = TempDir::new("replay");
+        let path = dir.0.join("spool.log");
+        let mut spool = Spool::open(&path, 10_000).unwrap();
+        spool.push(&records(0..1200)).unwrap();
+
+        // The first batch goes through, the second fails
+        let mut sent = 0;
+        let result = spool.replay(|message| {
+            if sent > 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone"));
+            }
+            sent += message.lines().count();
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(spool.len(), 1200 - REPLAY_BATCH);
+
+        let values = replay_values(&mut spool);
+        assert_eq!(values.first(), Some(&(REPLAY_BATCH as f64)));
+        assert_eq!(values.len(), 1200 - REPLAY_BATCH);
+        assert!(spool.is_empty());
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 0);
+
+        // Pushing again after a replay starts a new segment
+        spool.push(&records(0..2)).unwrap();
+        assert_eq!(Spool::open(&path, 10_000).unwrap().len(), 2);
+    }
+}
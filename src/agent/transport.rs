@@ -0,0 +1,146 @@
+use crate::tls::{tls_error, ClientTls};
+use rustls::ClientConnection;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Longest wait for the collector's side of the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The agent's connection to the collector, plain or TLS.
+///
+/// Clones share the connection, so one thread can read while another writes
+/// as with a cloned `TcpStream`.
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Arc<TlsConnection>),
+}
+
+pub struct TlsConnection {
+    session: Mutex<ClientConnection>,
+    socket: TcpStream,
+}
+
+impl Connection {
+    /// Connect and, with TLS, complete the handshake before returning.
+    pub fn connect(address: &str, tls: Option<&ClientTls>) -> io::Result<Self> {
+        let mut socket = TcpStream::connect(address)?;
+        let Some(tls) = tls else {
+            return Ok(Connection::Plain(socket));
+        };
+
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let mut session = ClientConnection::new(tls.config()?, tls.server_name(host)?).map_err(tls_error)?;
+        socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        while session.is_handshaking() {
+            session.complete_io(&mut socket)?;
+        }
+        socket.set_read_timeout(None)?;
+
+        Ok(Connection::Tls(Arc::new(TlsConnection {
+            session: Mutex::new(session),
+            socket,
+        })))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Connection::Plain(socket) => socket.try_clone().map(Connection::Plain),
+            Connection::Tls(tls) => Ok(Connection::Tls(Arc::clone(tls))),
+        }
+    }
+
+    /// The underlying socket, for timeouts and socket options.
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            Connection::Plain(socket) => socket,
+            Connection::Tls(tls) => &tls.socket,
+        }
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        if let Connection::Tls(tls) = self {
+            // Best effort, the collector may be gone already
+            let mut session = tls.session.lock().unwrap();
+            session.send_close_notify();
+            let _ = session.write_tls(&mut &tls.socket);
+        }
+        self.socket().shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let tls = match self {
+            Connection::Plain(socket) => return socket.read(buf),
+            Connection::Tls(tls) => tls,
+        };
+
+        let mut encrypted = [0u8; 4096];
+        loop {
+            {
+                let mut session = tls.session.lock().unwrap();
+                match session.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+            }
+
+            // Block on the socket without holding the session, so writes go on
+            let n = (&tls.socket).read(&mut encrypted)?;
+
+            let mut session = tls.session.lock().unwrap();
+            if n == 0 {
+                // Closed without close_notify, the reader reports it as an error
+                session.read_tls(&mut io::empty())?;
+                session.process_new_packets().map_err(tls_error)?;
+            }
+            let mut received = &encrypted[..n];
+            while !received.is_empty() {
+                session.read_tls(&mut received)?;
+                session.process_new_packets().map_err(tls_error)?;
+            }
+            // e.g. key updates the collector asked for
+            while session.wants_write() {
+                session.write_tls(&mut &tls.socket)?;
+            }
+            if n == 0 {
+                return match session.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+                    result => result,
+                };
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let tls = match self {
+            Connection::Plain(socket) => return socket.write(buf),
+            Connection::Tls(tls) => tls,
+        };
+
+        let mut session = tls.session.lock().unwrap();
+        let n = session.writer().write(buf)?;
+        while session.wants_write() {
+            session.write_tls(&mut &tls.socket)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(socket) => socket.flush(),
+            Connection::Tls(tls) => {
+                let mut session = tls.session.lock().unwrap();
+                while session.wants_write() {
+                    session.write_tls(&mut &tls.socket)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Threshold alerts on incoming metrics.
+//!
+//! Rules are read from `alerts.toml`:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "high-cpu"
+//! metric = "cpu"
+//! servers = ["web-*", "3"]   # labels, label prefixes or ids, all when omitted
+//! comparator = ">"           # >, >=, <, <=
+//! threshold = 90.0
+//! duration_secs = 30         # has to hold this long before firing
+//! hysteresis = 5.0           # resolves once back at 85 or below
+//! ```
+//!
+//! Alerts are also delivered to the sinks declared in the same file, see
+//! [`sinks`].
+//!
+//! The engine keeps one state per rule and server and is driven by the
+//! timestamps of the samples, so replayed data alerts the same way live
+//! data did. An alert firing for a server that goes down ends as stale,
+//! there is no sample left to resolve it.
+
+pub mod rules;
+pub mod sinks;
+
+use crate::protocol::Metric;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rules::{AlertRule, Comparator};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertState {
+    Firing,
+    Resolved,
+    // The server went down while firing
+    Stale,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertState::Firing => write!(f, "FIRING"),
+            AlertState::Resolved => write!(f, "RESOLVED"),
+            AlertState::Stale => write!(f, "STALE"),
+        }
+    }
+}
+
+/// A rule starting or stopping to fire for one server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub state: AlertState,
+    pub server_id: u32,
+    pub server: String,
+    pub metric: Metric,
+    pub value: f64,
+    pub comparator: Comparator,
+    pub threshold: f64,
+    // When the condition started to hold
+    pub since: DateTime<Utc>,
+    pub at: DateTime<Utc>,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} server={} id={} metric={} value={} {} {} since={}",
+            self.at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.state,
+            self.rule,
+            self.server,
+            self.server_id,
+            self.metric,
+            self.value,
+            self.comparator,
+            self.threshold,
+            self.since.to_rfc3339_opts(SecondsFormat::Millis, true),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RuleState {
+    Pending(DateTime<Utc>),
+    Firing(DateTime<Utc>, f64), // since, and the last value
+}
+
+/// Evaluates every rule on each sample.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    // Keyed by rule index and server id, absent while the condition doesn't hold
+    states: HashMap<(usize, u32), RuleState>,
+    // Labels of the servers alerts fired for
+    labels: HashMap<u32, String>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            states: HashMap::new(),
+            labels: HashMap::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    /// Feed one sample, returning the alerts that fired or resolved on it.
+    pub fn evaluate(&mut self, server_id: u32, server: &str, metric: &Metric, value: f64, at: DateTime<Utc>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.applies_to(server_id, server, metric) {
+                continue;
+            }
+
+            let key = (index, server_id);
+            let event = |state, since| AlertEvent {
+                rule: rule.name.clone(),
+                state,
+                server_id,
+                server: server.to_string(),
+                metric: metric.clone(),
+                value,
+                comparator: rule.comparator,
+                threshold: rule.threshold,
+                since,
+                at,
+            };
+
+            match self.states.get(&key).copied() {
+                None if rule.breached(value) && rule.duration().is_zero() => {
+                    self.states.insert(key, RuleState::Firing(at, value));
+                    self.labels.insert(server_id, server.to_string());
+                    events.push(event(AlertState::Firing, at));
+                }
+                None if rule.breached(value) => {
+                    self.states.insert(key, RuleState::Pending(at));
+                }
+                None => {}
+                Some(RuleState::Pending(_)) if !rule.breached(value) => {
+                    self.states.remove(&key);
+                }
+                Some(RuleState::Pending(since)) => {
+                    let held = (at - since).to_std().unwrap_or_default();
+                    if held >= rule.duration() {
+                        self.states.insert(key, RuleState::Firing(since, value));
+                        self.labels.insert(server_id, server.to_string());
+                        events.push(event(AlertState::Firing, since));
+                    }
+                }
+                Some(RuleState::Firing(since, _)) if rule.cleared(value) => {
+                    self.states.remove(&key);
+                    events.push(event(AlertState::Resolved, since));
+                }
+                Some(RuleState::Firing(since, _)) => {
+                    self.states.insert(key, RuleState::Firing(since, value));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Forget the states of a server that went down or disconnected,
+    /// returning the alerts that were firing for it as stale.
+    pub fn server_down(&mut self, server_id: u32, at: DateTime<Utc>) -> Vec<AlertEvent> {
+        let server = self.labels.remove(&server_id).unwrap_or_default();
+        let mut events = Vec::new();
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            if let Some(RuleState::Firing(since, value)) = self.states.remove(&(index, server_id)) {
+                events.push(AlertEvent {
+                    rule: rule.name.clone(),
+                    state: AlertState::Stale,
+                    server_id,
+                    server: server.clone(),
+                    metric: rule.metric.clone(),
+                    value,
+                    comparator: rule.comparator,
+                    threshold: rule.threshold,
+                    since,
+                    at,
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn high_cpu() -> AlertRule {
+        toml::from_str(
+            r#"
+            name = "high-cpu"
+            metric = "cpu"
+            servers = ["web-*"]
+            comparator = ">"
+            threshold = 90.0
+            duration_secs = 30
+            hysteresis = 5.0
+            "#,
+        )
+        .unwrap()
+    }
+
+    // Feed (seconds, value) samples, returning the states of the events
+    fn run(engine: &mut AlertEngine, server: &str, samples: &[(i64, f64)]) -> Vec<(i64, AlertState)> {
+        let start = DateTime::parse_from_rfc3339("2025-03-17T12:00:00Z").unwrap().with_timezone(&Utc);
+        samples
+            .iter()
+            .flat_map(|&(seconds, value)| {
+                let at = start + Duration::seconds(seconds);
+                engine
+                    .evaluate(1, server, &Metric::Cpu, value, at)
+                    .into_iter()
+                    .map(move |event| ((event.at - start).num_seconds(), event.state))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fires_after_the_duration_and_resolves_past_the_hysteresis() {
+        let mut engine = AlertEngine::new(vec![high_cpu()]);
+        let events = run(
+            &mut engine,
+            "web-1",
+            &[(0, 95.0), (20, 96.0), (30, 97.0), (40, 89.0), (50, 85.0), (60, 91.0)],
+        );
+        assert_eq!(events, [(30, AlertState::Firing), (50, AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn a_dip_before_the_duration_starts_over() {
+        let mut engine = AlertEngine::new(vec![high_cpu()]);
+        let events = run(&mut engine, "web-1", &[(0, 95.0), (20, 80.0), (25, 95.0), (50, 95.0), (55, 95.0)]);
+        assert_eq!(events, [(55, AlertState::Firing)]);
+    }
+
+    #[test]
+    fn only_selected_servers_and_metrics_are_checked() {
+        let mut engine = AlertEngine::new(vec![high_cpu()]);
+        assert!(run(&mut engine, "db-1", &[(0, 99.0), (60, 99.0)]).is_empty());
+
+        let at = Utc::now();
+        assert!(engine.evaluate(2, "web-2", &Metric::Memory, 99.0, at).is_empty());
+        assert!(engine.evaluate(2, "web-2", &Metric::Memory, 99.0, at + Duration::seconds(60)).is_empty());
+    }
+
+    #[test]
+    fn alerts_of_a_server_that_goes_down_end_as_stale() {
+        let mut engine = AlertEngine::new(vec![high_cpu()]);
+        assert_eq!(run(&mut engine, "web-1", &[(0, 95.0), (30, 97.0)]), [(30, AlertState::Firing)]);
+
+        let at = Utc::now();
+        let events = engine.server_down(1, at);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!((event.state, event.server.as_str(), event.value, event.at), (AlertState::Stale, "web-1", 97.0, at));
+        assert!(engine.server_down(1, at).is_empty());
+
+        // Back up, the alert has to build up again
+        assert_eq!(run(&mut engine, "web-1", &[(40, 95.0), (60, 95.0), (70, 95.0)]), [(70, AlertState::Firing)]);
+    }
+}
@@ -0,0 +1,139 @@
+use super::sinks::SinkConfig;
+use crate::protocol::Metric;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
+
+// Picked up from the working directory by the collector and the dashboard
+pub const RULES_FILE: &str = "alerts.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Comparator {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Comparator {
+    pub fn matches(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparator::Above => value > threshold,
+            Comparator::AtLeast => value >= threshold,
+            Comparator::Below => value < threshold,
+            Comparator::AtMost => value <= threshold,
+        }
+    }
+
+    // Back on the right side of the threshold by at least the hysteresis
+    fn clears(&self, value: f64, threshold: f64, hysteresis: f64) -> bool {
+        match self {
+            Comparator::Above | Comparator::AtLeast => value <= threshold - hysteresis,
+            Comparator::Below | Comparator::AtMost => value >= threshold + hysteresis,
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparator::Above => write!(f, ">"),
+            Comparator::AtLeast => write!(f, ">="),
+            Comparator::Below => write!(f, "<"),
+            Comparator::AtMost => write!(f, "<="),
+        }
+    }
+}
+
+/// Which servers a rule applies to: ids or labels, a trailing `*` matches a
+/// label prefix. An empty list matches every server.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ServerSelector(Vec<String>);
+
+impl ServerSelector {
+    pub fn matches(&self, server_id: u32, label: &str) -> bool {
+        self.0.is_empty()
+            || self.0.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => label.starts_with(prefix),
+                None => pattern == label || pattern.parse() == Ok(server_id),
+            })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(deserialize_with = "metric_from_name")]
+    pub metric: Metric,
+    #[serde(default)]
+    pub servers: ServerSelector,
+    pub comparator: Comparator,
+    pub threshold: f64,
+    // How long the condition has to hold before the alert fires
+    #[serde(default)]
+    pub duration_secs: u64,
+    // How far back past the threshold the value has to go to resolve
+    #[serde(default)]
+    pub hysteresis: f64,
+}
+
+impl AlertRule {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
+    }
+
+    pub fn applies_to(&self, server_id: u32, label: &str, metric: &Metric) -> bool {
+        self.metric == *metric && self.servers.matches(server_id, label)
+    }
+
+    pub fn breached(&self, value: f64) -> bool {
+        self.comparator.matches(value, self.threshold)
+    }
+
+    pub fn cleared(&self, value: f64) -> bool {
+        self.comparator.clears(value, self.threshold, self.hysteresis)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<AlertRule>,
+    #[serde(default, rename = "sink")]
+    sinks: Vec<SinkConfig>,
+}
+
+/// Read the rules of a TOML file, no rules when it doesn't exist.
+pub fn load_rules(path: &Path) -> io::Result<Vec<AlertRule>> {
+    Ok(read_file(path)?.rules)
+}
+
+/// Read the notification sinks declared next to the rules.
+pub fn load_sinks(path: &Path) -> io::Result<Vec<SinkConfig>> {
+    Ok(read_file(path)?.sinks)
+}
+
+fn read_file(path: &Path) -> io::Result<RulesFile> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RulesFile::default()),
+        Err(e) => return Err(e),
+    };
+
+    toml::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+fn metric_from_name<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Metric, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Ok(Metric::from_name(&name))
+}
@@ -0,0 +1,54 @@
+use crate::alerts::AlertEvent;
+use chrono::SecondsFormat;
+use std::io;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A command still running after this is killed and counts as failed
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(50);
+
+/// The environment a command sink runs with.
+pub fn environment(event: &AlertEvent) -> Vec<(&'static str, String)> {
+    vec![
+        ("ALERT_RULE", event.rule.clone()),
+        ("ALERT_STATE", event.state.to_string()),
+        ("ALERT_SERVER", event.server.clone()),
+        ("ALERT_SERVER_ID", event.server_id.to_string()),
+        ("ALERT_METRIC", event.metric.to_string()),
+        ("ALERT_VALUE", event.value.to_string()),
+        ("ALERT_COMPARATOR", event.comparator.to_string()),
+        ("ALERT_THRESHOLD", event.threshold.to_string()),
+        ("ALERT_SINCE", event.since.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ("ALERT_AT", event.at.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ("ALERT_MESSAGE", event.to_string()),
+    ]
+}
+
+/// Run the command, a non-zero exit status is a failure.
+pub fn run(command: &str, args: &[String], event: &AlertEvent) -> io::Result<()> {
+    let mut child = Command::new(command)
+        .args(args)
+        .envs(environment(event))
+        .stdin(Stdio::null())
+        .spawn()?;
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("{} exited with {}", command, status)))
+            };
+        }
+
+        if started.elapsed() >= TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} still running after {:?}", command, TIMEOUT)));
+        }
+        thread::sleep(POLL);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::alerts::AlertEvent;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+const SENDER: &str = "alerts@localhost";
+
+pub(super) fn default_recipient() -> String {
+    String::from("root@localhost")
+}
+
+/// One mbox message for the alert, `From ` separator line included.
+pub fn message(to: &str, event: &AlertEvent) -> String {
+    let mut message = String::new();
+    let _ = writeln!(message, "From {} {}", SENDER, event.at.format("%a %b %e %H:%M:%S %Y"));
+    let _ = writeln!(message, "From: ServerRemoteDash <{}>", SENDER);
+    let _ = writeln!(message, "To: {}", to);
+    let _ = writeln!(message, "Date: {}", event.at.to_rfc2822());
+    let _ = writeln!(
+        message,
+        "Subject: [{}] {} on {}: {} {} {} {}",
+        event.state, event.rule, event.server, event.metric, event.value, event.comparator, event.threshold
+    );
+    message.push('\n');
+
+    let body = format!(
+        "{}\n\nRule:      {}\nState:     {}\nServer:    {} (id {})\nMetric:    {}\nValue:     {}\nThreshold: {} {}\nSince:     {}\n",
+        event, event.rule, event.state, event.server, event.server_id, event.metric, event.value, event.comparator, event.threshold, event.since
+    );
+    for line in body.lines() {
+        // mboxrd quoting, so no body line reads as a message separator
+        if line.trim_start_matches('>').starts_with("From ") {
+            message.push('>');
+        }
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+
+    message
+}
+
+/// Append the alert to the mbox at `path`, created if missing.
+pub fn append(path: &Path, to: &str, event: &AlertEvent) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write, so concurrent readers never see half a message
+    file.write_all(message(to, event).as_bytes())
+}
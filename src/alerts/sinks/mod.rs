@@ -0,0 +1,328 @@
+//! Delivery of alerts outside the dashboard.
+//!
+//! Sinks are declared next to the rules in `alerts.toml`:
+//!
+//! ```toml
+//! [[sink]]
+//! name = "ops"
+//! type = "webhook"              # webhook, command or mbox
+//! url = "http://127.0.0.1:9000/alerts"
+//! max_per_minute = 20           # further alerts are dropped
+//! retries = 3                   # failed deliveries, with a doubling delay
+//! retry_delay_ms = 1000
+//! dedup_secs = 300              # repeats of the last state sent for a rule and server
+//! ```
+//!
+//! Every sink has its own thread, so a slow webhook or a hanging command
+//! doesn't hold up the others.
+
+pub mod command;
+pub mod mbox;
+pub mod webhook;
+
+use super::{AlertEvent, AlertState};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Window of the per sink rate limit
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkKind {
+    // POST the alert as JSON, plain http only
+    Webhook { url: String },
+    // Run a program with the alert in ALERT_* environment variables
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    // Append a message to a local mbox file
+    Mbox {
+        path: PathBuf,
+        #[serde(default = "mbox::default_recipient")]
+        to: String,
+    },
+}
+
+impl SinkKind {
+    fn deliver(&self, event: &AlertEvent) -> io::Result<()> {
+        match self {
+            SinkKind::Webhook { url } => webhook::post(url, event),
+            SinkKind::Command { command, args } => command::run(command, args, event),
+            SinkKind::Mbox { path, to } => mbox::append(path, to, event),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    #[serde(default = "default_max_per_minute")]
+    pub max_per_minute: u32,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    #[serde(default = "default_dedup_secs")]
+    pub dedup_secs: u64,
+}
+
+fn default_max_per_minute() -> u32 {
+    20
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_dedup_secs() -> u64 {
+    300
+}
+
+/// Hands every alert to each configured sink.
+#[derive(Default)]
+pub struct Notifier {
+    workers: Vec<(Sender<AlertEvent>, thread::JoinHandle<()>)>,
+}
+
+impl Notifier {
+    pub fn new(sinks: Vec<SinkConfig>) -> Self {
+        let workers = sinks
+            .into_iter()
+            .map(|config| {
+                let (tx, rx) = mpsc::channel::<AlertEvent>();
+                let handle = thread::spawn(move || {
+                    let mut worker = SinkWorker::new(config);
+                    for event in rx {
+                        worker.handle(&event);
+                    }
+                });
+                (tx, handle)
+            })
+            .collect();
+
+        Self { workers }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    pub fn notify(&self, event: &AlertEvent) {
+        for (tx, _) in &self.workers {
+            let _ = tx.send(event.clone());
+        }
+    }
+
+    /// Wait for the sinks to deliver what they were given.
+    pub fn close(self) {
+        for (tx, handle) in self.workers {
+            drop(tx);
+            if handle.join().is_err() {
+                eprintln!("Alert sink thread panicked");
+            }
+        }
+    }
+}
+
+// Rate limiting, deduplication and retries of one sink
+struct SinkWorker {
+    config: SinkConfig,
+    // Deliveries within the last RATE_WINDOW
+    sent: VecDeque<Instant>,
+    // State last delivered per rule and server, and when
+    delivered: HashMap<(String, u32), (AlertState, Instant)>,
+    dropped: u64,
+}
+
+impl SinkWorker {
+    fn new(config: SinkConfig) -> Self {
+        Self {
+            config,
+            sent: VecDeque::new(),
+            delivered: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    fn handle(&mut self, event: &AlertEvent) {
+        let name = &self.config.name;
+        let key = (event.rule.clone(), event.server_id);
+        let dedup = Duration::from_secs(self.config.dedup_secs);
+        // A change of state always goes out, or a receiver could be left
+        // believing an alert that fired again is resolved
+        let repeated = self
+            .delivered
+            .get(&key)
+            .is_some_and(|(state, at)| *state == event.state && at.elapsed() < dedup);
+        if repeated {
+            println!("Sink {}: skipped repeated {} {} for {}", name, event.state, event.rule, event.server);
+            return;
+        }
+
+        while self.sent.front().is_some_and(|at| at.elapsed() >= RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.config.max_per_minute as usize {
+            self.dropped += 1;
+            eprintln!(
+                "Sink {}: rate limit of {}/min reached, dropped {} {} for {} ({} dropped so far)",
+                name, self.config.max_per_minute, event.state, event.rule, event.server, self.dropped
+            );
+            return;
+        }
+        self.sent.push_back(Instant::now());
+
+        let mut delay = Duration::from_millis(self.config.retry_delay_ms);
+        for attempt in 0..=self.config.retries {
+            match self.config.kind.deliver(event) {
+                Ok(()) => {
+                    self.delivered.insert(key, (event.state, Instant::now()));
+                    return;
+                }
+                Err(e) if attempt < self.config.retries => {
+                    eprintln!("Sink {}: delivery failed ({}), retrying in {:?}", name, e, delay);
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(e) => {
+                    eprintln!("Sink {}: giving up on {} {} for {}: {}", name, event.state, event.rule, event.server, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::rules::Comparator;
+    use crate::protocol::Metric;
+    use chrono::Utc;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    // Answers each request with the next status, 200 once they run out, and
+    // keeps the bodies it was sent
+    fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let bodies = Arc::clone(&received);
+        thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                loop {
+                    let n = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .and_then(|length| length.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            bodies.lock().unwrap().push(body.to_string());
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                let status = statuses.next().unwrap_or(200);
+                let _ = write!(stream, "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n", status);
+            }
+        });
+
+        (url, received)
+    }
+
+    fn worker(url: String) -> SinkWorker {
+        SinkWorker::new(SinkConfig {
+            name: String::from("test"),
+            kind: SinkKind::Webhook { url },
+            max_per_minute: 100,
+            retries: 2,
+            retry_delay_ms: 1,
+            dedup_secs: 300,
+        })
+    }
+
+    fn event(state: AlertState) -> AlertEvent {
+        AlertEvent {
+            rule: String::from("high-cpu"),
+            state,
+            server_id: 0,
+            server: String::from("web-1"),
+            metric: Metric::Cpu,
+            value: 95.0,
+            comparator: Comparator::Above,
+            threshold: 90.0,
+            since: Utc::now(),
+            at: Utc::now(),
+        }
+    }
+
+    fn states(received: &Mutex<Vec<String>>) -> Vec<String> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| serde_json::from_str::<serde_json::Value>(body).unwrap()["state"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried() {
+        let (url, received) = stand_in(vec![500, 503]);
+        let mut worker = worker(url);
+
+        worker.handle(&event(AlertState::Firing));
+
+        assert_eq!(states(&received), ["firing", "firing", "firing"]);
+        assert!(worker.delivered.contains_key(&(String::from("high-cpu"), 0)));
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let (url, received) = stand_in(vec![500, 500, 500, 500]);
+        let mut worker = worker(url);
+
+        worker.handle(&event(AlertState::Firing));
+
+        assert_eq!(received.lock().unwrap().len(), 3);
+        assert!(worker.delivered.is_empty());
+    }
+
+    #[test]
+    fn repeats_are_skipped_but_changes_of_state_are_not() {
+        let (url, received) = stand_in(Vec::new());
+        let mut worker = worker(url);
+
+        for state in [AlertState::Firing, AlertState::Firing, AlertState::Resolved, AlertState::Firing] {
+            worker.handle(&event(state));
+        }
+
+        assert_eq!(states(&received), ["firing", "resolved", "firing"]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::alerts::AlertEvent;
+use chrono::SecondsFormat;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Applies to connecting, sending and waiting for the response each
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The JSON body posted for an alert.
+pub fn payload(event: &AlertEvent) -> serde_json::Value {
+    serde_json::json!({
+        "rule": event.rule,
+        "state": event.state.to_string().to_lowercase(),
+        "server": event.server,
+        "server_id": event.server_id,
+        "metric": event.metric.name(),
+        "value": event.value,
+        "comparator": event.comparator.to_string(),
+        "threshold": event.threshold,
+        "since": event.since.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "at": event.at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "message": event.to_string(),
+    })
+}
+
+/// POST the alert to an `http://host[:port]/path` url, any 2xx is a success.
+pub fn post(url: &str, event: &AlertEvent) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", url, message));
+
+    let rest = url.strip_prefix("http://").ok_or_else(|| invalid("only http:// webhooks are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let address = address.to_socket_addrs()?.next().ok_or_else(|| invalid("host not found"))?;
+
+    let body = payload(event).to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(request.as_bytes())?;
+
+    // The status line is all that matters, the rest of the response is ignored
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !response.contains(&b'\n') {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..n]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no HTTP response", url)))?;
+
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} answered {}", url, status)))
+    }
+}
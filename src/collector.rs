@@ -0,0 +1,117 @@
+use clap::Parser;
+use server_remote_dash::gui_connection::backpressure::{BackpressureConfig, OverloadPolicy};
+use server_remote_dash::gui_connection::liveness::LivenessConfig;
+use server_remote_dash::gui_connection::{
+    configure_agents, configure_backpressure, configure_file_writer, configure_heartbeat, configure_liveness,
+    configure_metrics_endpoint, configure_tls, initialize_server,
+};
+use server_remote_dash::heartbeat::HeartbeatConfig;
+use server_remote_dash::tls::ServerTls;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(about = "Collects metrics from agents and writes them to the log files")]
+struct Args {
+    /// Address to listen for agents on
+    #[arg(long, default_value = "0.0.0.0:8888")]
+    listen: String,
+
+    /// Certificate chain (PEM) to serve agents TLS with, requires --tls-key
+    #[arg(long, env = "SRD_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of the TLS certificate
+    #[arg(long, env = "SRD_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// CA (PEM) agents' client certificates are checked against. Without
+    /// --agents-file every agent needs a certificate it signed.
+    #[arg(long, env = "SRD_TLS_CLIENT_CA", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Registry of the agents allowed to connect, by token or certificate
+    #[arg(long, env = "SRD_AGENTS_FILE")]
+    agents_file: Option<PathBuf>,
+
+    /// Records per second each agent may send, 0 for no limit
+    #[arg(long, env = "SRD_RATE_LIMIT", default_value_t = BackpressureConfig::default().records_per_sec)]
+    rate_limit: f64,
+
+    /// Records an agent may send at once after being quiet
+    #[arg(long, env = "SRD_RATE_BURST", default_value_t = BackpressureConfig::default().burst)]
+    rate_burst: f64,
+
+    /// What to do with records over the limit or while the writer is behind:
+    /// slow-down stops reading from the agent, drop discards and counts them
+    #[arg(long, env = "SRD_OVERLOAD_POLICY", default_value_t = OverloadPolicy::default())]
+    overload_policy: OverloadPolicy,
+
+    /// Serve the collector's own metrics and the latest agent values for
+    /// Prometheus at http://<address>/metrics, e.g. 0.0.0.0:9464
+    #[arg(long, env = "SRD_METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+
+    /// Seconds an agent may stay quiet before it is shown as stale
+    #[arg(long, env = "SRD_STALE_AFTER_SECS", default_value_t = LivenessConfig::default().stale_after.as_secs())]
+    stale_after_secs: u64,
+
+    /// Seconds an agent may stay quiet before it is shown as down
+    #[arg(long, env = "SRD_DOWN_AFTER_SECS", default_value_t = LivenessConfig::default().down_after.as_secs())]
+    down_after_secs: u64,
+
+    /// Milliseconds between heartbeats to agents that send them, 0 disables them
+    #[arg(long, env = "SRD_HEARTBEAT_INTERVAL_MS", default_value_t = HeartbeatConfig::default().interval.as_millis() as u64)]
+    heartbeat_interval_ms: u64,
+
+    /// Close a connection after hearing nothing from its agent for this many
+    /// milliseconds, never less than three of the agent's heartbeat intervals
+    #[arg(long, env = "SRD_PEER_TIMEOUT_MS", default_value_t = HeartbeatConfig::default().timeout.as_millis() as u64)]
+    peer_timeout_ms: u64,
+
+    /// Seconds idle before TCP keepalive probes start, 0 disables them
+    #[arg(long, env = "SRD_KEEPALIVE_SECS", default_value_t = HeartbeatConfig::default().keepalive.as_secs())]
+    keepalive_secs: u64,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+
+    // Configure the file writer
+    configure_file_writer(true, "tcp_logs", "data");
+
+    if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+        configure_tls(Some(ServerTls {
+            cert,
+            key,
+            client_ca: args.tls_client_ca,
+        }));
+    }
+    configure_agents(args.agents_file);
+    configure_metrics_endpoint(args.metrics_listen);
+    let heartbeat = HeartbeatConfig {
+        interval: Duration::from_millis(args.heartbeat_interval_ms),
+        timeout: Duration::from_millis(args.peer_timeout_ms),
+        keepalive: Duration::from_secs(args.keepalive_secs),
+    };
+    heartbeat.validate()?;
+    configure_heartbeat(heartbeat);
+    configure_liveness(LivenessConfig::new(
+        Duration::from_secs(args.stale_after_secs),
+        Duration::from_secs(args.down_after_secs),
+    )?);
+    configure_backpressure(BackpressureConfig {
+        records_per_sec: args.rate_limit,
+        burst: args.rate_burst,
+        policy: args.overload_policy,
+    });
+
+    // Initialize the server and file writer
+    let server = initialize_server(&args.listen)?;
+
+    println!("Server running. Press Ctrl+C to stop.");
+
+    // Run until Ctrl+C or SIGTERM, then drain and stop cleanly
+    server.wait_for_signal()
+}
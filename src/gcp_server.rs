@@ -1,94 +1,357 @@
 use rand::Rng;
-use std::io::{self, Write};
-use std::net::TcpStream;
+use server_remote_dash::agent::backoff::Backoff;
+use server_remote_dash::agent::config::AgentConfig;
+use server_remote_dash::agent::metrics::SystemSampler;
+use server_remote_dash::agent::spool::Spool;
+use server_remote_dash::agent::transport::Connection;
+use server_remote_dash::heartbeat::{self, HeartbeatSequence};
+use server_remote_dash::protocol::{parse_frame, Frame, Hello, Metric, MetricRecord, PROTOCOL_VERSION};
+use std::io::{self, BufRead, BufReader, Write};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 fn main() -> io::Result<()> {
-    // Configuration
-    let server_address = "127.0.0.1:8888"; // Change to your central server address
-    let server_id = 0; // Change this for each cloud server instance
-    let reconnect_delay = Duration::from_secs(5);
+    let config = AgentConfig::load()?;
+    let server_address = config.server_address();
+    let metrics = enabled_metrics(&config);
+    let mut sampler = SystemSampler::default();
+    let mut failed_attempts = 0;
+    let mut backoff = Backoff::new(&config.reconnect);
+    let mut spool = open_spool(&config);
 
-    println!("Cloud server {} starting up...", server_id);
+    // Identity the collector uses to give us the same id on every connection
+    let mut hello = Hello::new(PROTOCOL_VERSION).with_attribute("name", &config.display_name());
+    if let Some(uuid) = &config.server_uuid {
+        hello = hello.with_attribute("uuid", uuid);
+    }
+    if let Some(token) = &config.token {
+        hello = hello.with_attribute("token", token);
+    }
+    let tls = config.tls.as_ref().map(|tls| tls.client());
+    let heartbeat = config.heartbeat.config();
+    if heartbeat.enabled() {
+        hello = hello.with_attribute("heartbeat", &heartbeat.interval.as_millis().to_string());
+    }
+
+    println!("Cloud server {} starting up...", config.display_name());
+    println!("Target central server: {}", server_address);
+    if config.simulate {
+        println!("Simulation mode: sending random monitoring data");
+    }
+    if let Some(tls) = &tls {
+        println!("Using TLS, trusting the CA in {}", tls.ca.display());
+    }
 
     // Continuously try to connect and send data
     loop {
+        if let Some(max_attempts) = config.reconnect.max_attempts
+            && failed_attempts >= max_attempts
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("giving up after {} failed connection attempts", failed_attempts),
+            ));
+        }
+
         println!(
             "Attempting to connect to central server at {}",
             server_address
         );
 
-        match TcpStream::connect(server_address) {
+        match Connection::connect(&server_address, tls.as_ref()) {
             Ok(mut stream) => {
                 println!("Connected to central server!");
 
+                if let Err(e) = heartbeat::set_keepalive(stream.socket(), heartbeat.keepalive) {
+                    println!("Failed to enable keepalive: {}", e);
+                }
+
+                let (reply, reader) = match handshake(&mut stream, &hello) {
+                    Ok(handshake) => handshake,
+                    Err(e) => {
+                        println!("Handshake failed: {}", e);
+                        failed_attempts += 1;
+                        wait_before_reconnect(&mut backoff, &config, &mut sampler, &metrics, spool.as_mut());
+                        continue;
+                    }
+                };
+                println!("Central server speaks protocol v{}", reply.version);
+                failed_attempts = 0;
+                backoff.reset();
+
+                // Collectors that don't send heartbeats can't be timed out
+                let peer_heartbeat = reply
+                    .attribute("heartbeat")
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .filter(|ms| *ms > 0 && heartbeat.enabled())
+                    .map(Duration::from_millis);
+
                 // Start sending monitoring data
                 println!("Starting to send monitoring data...");
 
                 // Keep sending data until connection fails
-                loop {
-                    // Generate random monitoring data
-                    let message = generate_random_monitoring_data(server_id);
-
-                    // Send the message
-                    match stream.write_all(message.as_bytes()) {
-                        Ok(_) => {
-                            println!("Sent: {}", message.trim());
-                            // Flush to ensure data is sent
-                            if let Err(e) = stream.flush() {
-                                println!("Error flushing stream: {}", e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            println!("Error sending data: {}", e);
-                            break;
-                        }
-                    }
-
-                    // Sleep before sending next message
-                    thread::sleep(Duration::from_millis(1000));
+                if let Err(e) = send_until_failure(&mut stream, reader, &config, peer_heartbeat, &mut sampler, &metrics, spool.as_mut()) {
+                    println!("Error sending data: {}", e);
                 }
 
                 println!("Lost connection to central server. Will try to reconnect...");
             }
             Err(e) => {
                 println!("Failed to connect to central server: {}", e);
+                failed_attempts += 1;
             }
         }
 
-        // Wait before trying to reconnect
-        println!(
-            "Waiting {} seconds before reconnecting...",
-            reconnect_delay.as_secs()
-        );
-        thread::sleep(reconnect_delay);
+        wait_before_reconnect(&mut backoff, &config, &mut sampler, &metrics, spool.as_mut());
+    }
+}
+
+// The spool samples go to while disconnected, None when disabled or unusable
+fn open_spool(config: &AgentConfig) -> Option<Spool> {
+    if config.spool.max_records == 0 {
+        return None;
     }
+
+    match Spool::open(&config.spool.path, config.spool.max_records) {
+        Ok(spool) => {
+            if !spool.is_empty() {
+                println!("{} samples spooled in {} from an earlier run", spool.len(), config.spool.path.display());
+            }
+            Some(spool)
+        }
+        Err(e) => {
+            println!("Failed to open spool {}, samples taken while disconnected will be lost: {}", config.spool.path.display(), e);
+            None
+        }
+    }
+}
+
+// Sleep for the next backoff delay, spooling the samples that come due meanwhile
+fn wait_before_reconnect(
+    backoff: &mut Backoff,
+    config: &AgentConfig,
+    sampler: &mut SystemSampler,
+    metrics: &[Metric],
+    spool: Option<&mut Spool>,
+) {
+    let delay = backoff.next_delay();
+    println!("Waiting {:.1} seconds before reconnecting...", delay.as_secs_f64());
+
+    let deadline = Instant::now() + delay;
+    let Some(spool) = spool else {
+        thread::sleep(delay);
+        return;
+    };
+
+    loop {
+        let records = take_sample(config, sampler, metrics);
+        if let Err(e) = spool.push(&records) {
+            println!("Failed to spool samples: {}", e);
+        }
+
+        let next_sample = Instant::now() + config.interval();
+        if next_sample >= deadline {
+            break;
+        }
+        thread::sleep(next_sample.saturating_duration_since(Instant::now()));
+    }
+    thread::sleep(deadline.saturating_duration_since(Instant::now()));
 }
 
-fn generate_random_monitoring_data(server_id: u8) -> String {
+// Send samples, and heartbeats if the collector agreed to them, until a write
+// fails or the collector has been silent for longer than the dead peer timeout
+fn send_until_failure(
+    stream: &mut Connection,
+    reader: BufReader<Connection>,
+    config: &AgentConfig,
+    peer_heartbeat: Option<Duration>,
+    sampler: &mut SystemSampler,
+    metrics: &[Metric],
+    mut spool: Option<&mut Spool>,
+) -> io::Result<()> {
+    let heartbeat = config.heartbeat.config();
+    // A collector that stopped reading mustn't block us forever either
+    stream.socket().set_write_timeout(Some(heartbeat.timeout).filter(|timeout| !timeout.is_zero()))?;
+
+    // What was spooled during the outage goes first, so the collector gets
+    // every sample in order
+    if let Some(spool) = spool.as_deref_mut()
+        && !spool.is_empty()
+    {
+        println!("Replaying {} spooled samples", spool.len());
+        let replayed = spool.replay(|lines| stream.write_all(lines.as_bytes()))?;
+        println!("Replayed {} spooled samples", replayed);
+    }
+
+    let timeout = peer_heartbeat.map(|interval| heartbeat.peer_timeout(interval));
+    let reader = spawn_reader(reader, timeout)?;
+    let mut heartbeats = HeartbeatSequence::default();
+    let mut next_sample = Instant::now();
+    let mut next_heartbeat = peer_heartbeat.map(|_| Instant::now());
+
+    let result = loop {
+        let now = Instant::now();
+        if now >= next_sample {
+            let records = take_sample(config, sampler, metrics);
+            let message: String = records.iter().map(|r| format!("{}\n", r)).collect();
+
+            // Send the message, flushing to ensure data is sent. Unsent
+            // samples are spooled, a sample the collector did get before the
+            // failure may then arrive twice.
+            if let Err(e) = stream.write_all(message.as_bytes()).and_then(|_| stream.flush()) {
+                if let Some(spool) = spool.as_deref_mut()
+                    && let Err(e) = spool.push(&records)
+                {
+                    println!("Failed to spool samples: {}", e);
+                }
+                break Err(e);
+            }
+            for line in message.lines() {
+                println!("Sent: {}", line);
+            }
+            next_sample = Instant::now() + config.interval();
+        }
+
+        if let Some(due) = next_heartbeat
+            && now >= due
+        {
+            if let Err(e) = stream.write_all(format!("{}\n", heartbeats.next_frame()).as_bytes()) {
+                break Err(e);
+            }
+            next_heartbeat = Some(Instant::now() + heartbeat.interval);
+        }
+
+        // Sleep until the next sample or heartbeat is due
+        let wake = next_heartbeat.map_or(next_sample, |due| due.min(next_sample));
+        thread::sleep(wake.saturating_duration_since(Instant::now()));
+    };
+
+    // Unblocks the reader thread
+    let _ = stream.shutdown();
+    match reader.join() {
+        // The writes only failed because the reader gave up on the collector
+        Ok(Err(e)) => Err(e),
+        _ => result,
+    }
+}
+
+// Read what the collector sends after the handshake until the connection is
+// closed, or until nothing arrived for `timeout`. Then shut the connection
+// down, which makes the next write fail.
+fn spawn_reader(mut reader: BufReader<Connection>, timeout: Option<Duration>) -> io::Result<thread::JoinHandle<io::Result<()>>> {
+    // A read blocked while we were suspended still returns what arrived meanwhile
+    reader.get_ref().socket().set_read_timeout(timeout)?;
+
+    Ok(thread::spawn(move || {
+        let mut heartbeats = HeartbeatSequence::default();
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    let _ = reader.get_ref().shutdown();
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("nothing heard from the collector for {:?}", timeout.unwrap_or_default()),
+                    ));
+                }
+                Err(_) => return Ok(()),
+            }
+
+            if let Some(Frame::Heartbeat(heartbeat)) = parse_frame(&line) {
+                let missed = heartbeats.receive(heartbeat);
+                if missed > 0 {
+                    println!("Collector skipped {} heartbeats before seq {}", missed, heartbeat.seq);
+                }
+            }
+        }
+    }))
+}
+
+// Announce our protocol version and identity and wait for the collector to
+// acknowledge it. The reader keeps whatever the collector sent after its reply.
+fn handshake(stream: &mut Connection, hello: &Hello) -> io::Result<(Hello, BufReader<Connection>)> {
+    stream.write_all(format!("{}\n", hello).as_bytes())?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    stream.socket().set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    let read = reader.read_line(&mut line);
+    stream.socket().set_read_timeout(None)?;
+
+    match read? {
+        0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during handshake")),
+        _ => match parse_frame(&line) {
+            Some(Frame::Hello(hello)) => Ok((hello, reader)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected handshake reply: {}", line.trim()))),
+        },
+    }
+}
+
+// Metrics requested in the config that this agent knows how to produce
+fn enabled_metrics(config: &AgentConfig) -> Vec<Metric> {
+    config
+        .metrics()
+        .into_iter()
+        .filter(|metric| {
+            let known = metric.id().is_some();
+            if !known {
+                println!("Ignoring unknown metric {}", metric);
+            }
+            known
+        })
+        .collect()
+}
+
+// One round of samples, real or simulated
+fn take_sample(config: &AgentConfig, sampler: &mut SystemSampler, metrics: &[Metric]) -> Vec<MetricRecord> {
+    if config.simulate {
+        vec![generate_random_monitoring_data(config.server_id, metrics)]
+    } else {
+        collect_system_metrics(sampler, config.server_id, metrics)
+    }
+}
+
+// Sample the enabled metrics from the host, skipping the ones that can't be read
+fn collect_system_metrics(sampler: &mut SystemSampler, server_id: u32, metrics: &[Metric]) -> Vec<MetricRecord> {
+    let timestamp = chrono::Utc::now();
+
+    metrics
+        .iter()
+        .filter_map(|metric| match sampler.sample(metric) {
+            Ok(value) => Some(MetricRecord {
+                timestamp,
+                server_id,
+                metric: metric.clone(),
+                value: (value * 10.0).round() / 10.0,
+                unit: String::from("%"),
+            }),
+            Err(e) => {
+                println!("Failed to sample {}: {}", metric, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn generate_random_monitoring_data(server_id: u32, metrics: &[Metric]) -> MetricRecord {
     let mut rng = rand::thread_rng();
 
     // Generate random values
-    let metric_type = rng.gen_range(0..5); // 0=CPU, 1=IP, 2=Network, 3=FS, 4=Memory
-    let utilization = rng.gen_range(0.0..100.0);
-
-    // Get current time
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0));
-
-    // Format time as hh:mm:ss
-    let total_seconds = now.as_secs() % (24 * 3600);
-    let hours = total_seconds / 3600;
-    let minutes = (total_seconds % 3600) / 60;
-    let seconds = total_seconds % 60;
-    let time_str = format!("{:02}:{:02}:{:02}", hours, minutes, seconds);
-
-    // Format the message according to the specified format
-    format!(
-        "{}-{}-{:.1}-{}\n",
-        server_id, metric_type, utilization, time_str
-    )
+    let metric = metrics
+        .get(rng.gen_range(0..metrics.len().max(1)))
+        .cloned()
+        .unwrap_or(Metric::Cpu);
+    let utilization: f64 = rng.gen_range(0.0..100.0);
+
+    MetricRecord {
+        timestamp: chrono::Utc::now(),
+        server_id,
+        metric,
+        value: (utilization * 10.0).round() / 10.0,
+        unit: String::from("%"),
+    }
 }
@@ -0,0 +1,328 @@
+//! Which agents may send data to the collector.
+//!
+//! The registry file lists every agent with the server name it has to
+//! announce and the credential it proves itself with: a pre-shared token,
+//! sent in its handshake as `HELLO v2 name=web-1 token=<token>`, or the
+//! SHA-256 fingerprint of its TLS client certificate. Tokens can't contain
+//! whitespace or `=`.
+//!
+//! ```toml
+//! [[agent]]
+//! name = "web-1"
+//! token = "c2f1d7a0e9b84b6f"
+//!
+//! [[agent]]
+//! name = "db-1"
+//! certificate = "5e:0c:...:9a"
+//! # Turned away on its next connection, and its open one is closed
+//! revoked = true
+//! ```
+//!
+//! The file is read again whenever it changes, so agents can be added and
+//! revoked while the collector runs.
+
+use crate::protocol::{sanitize_token, Hello};
+use ring::digest;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Environment variable naming the registry file.
+pub const AGENTS_FILE_ENV: &str = "SRD_AGENTS_FILE";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentEntry {
+    // Server name the agent has to announce, so its credential can't speak
+    // for another server
+    pub name: String,
+    pub token: Option<String>,
+    // SHA-256 of the client certificate, hex with or without colons
+    pub certificate: Option<String>,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AgentsFile {
+    #[serde(default, rename = "agent")]
+    agents: Vec<AgentEntry>,
+}
+
+/// What an agent presented when it connected.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub name: String,
+    pub token: Option<String>,
+    pub certificate: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(hello: &Hello, certificate: Option<String>) -> Self {
+        Self {
+            name: hello.attribute("name").map(sanitize_token).unwrap_or_default(),
+            token: hello.attribute("token").map(str::to_string),
+            certificate,
+        }
+    }
+}
+
+/// Why an agent was turned away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    // Sent data before a HELLO, as legacy agents do
+    NoHandshake,
+    NoCredentials,
+    UnknownToken,
+    UnknownCertificate,
+    Revoked(String),
+    WrongName { registered: String, announced: String },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::NoHandshake => write!(f, "sent data without a handshake"),
+            Rejection::NoCredentials => write!(f, "presented no token or client certificate"),
+            Rejection::UnknownToken => write!(f, "unknown token"),
+            Rejection::UnknownCertificate => write!(f, "unknown client certificate"),
+            Rejection::Revoked(name) => write!(f, "credentials of {} are revoked", name),
+            Rejection::WrongName { registered, announced } if announced.is_empty() => {
+                write!(f, "credentials of {} used without a name", registered)
+            }
+            Rejection::WrongName { registered, announced } => {
+                write!(f, "credentials of {} used by {}", registered, announced)
+            }
+        }
+    }
+}
+
+/// The agents listed in the registry file, reloaded when it changes.
+#[derive(Debug)]
+pub struct AgentRegistry {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    agents: Vec<AgentEntry>,
+}
+
+impl AgentRegistry {
+    /// Unlike later reloads, a missing or broken file is an error here, so
+    /// the collector doesn't start turning every agent away.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let modified = fs::metadata(path)?.modified().ok();
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified,
+            agents: read_agents(path)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check `credentials` against the current file.
+    pub fn authenticate(&mut self, credentials: &Credentials) -> Result<(), Rejection> {
+        self.reload_if_changed();
+
+        let entry = match (&credentials.token, &credentials.certificate) {
+            (Some(token), _) => self
+                .agents
+                .iter()
+                .find(|agent| agent.token.as_deref().is_some_and(|t| tokens_match(t, token)))
+                .ok_or(Rejection::UnknownToken)?,
+            (None, Some(certificate)) => self
+                .agents
+                .iter()
+                .find(|agent| agent.certificate.as_deref().is_some_and(|c| normalize(c) == *certificate))
+                .ok_or(Rejection::UnknownCertificate)?,
+            (None, None) => return Err(Rejection::NoCredentials),
+        };
+
+        if entry.revoked {
+            return Err(Rejection::Revoked(entry.name.clone()));
+        }
+        if entry.name != credentials.name {
+            return Err(Rejection::WrongName {
+                registered: entry.name.clone(),
+                announced: credentials.name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    // A file that fails to read keeps the agents loaded before
+    fn reload_if_changed(&mut self) {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match read_agents(&self.path) {
+            Ok(agents) => {
+                println!("Reloaded {} agents from {}", agents.len(), self.path.display());
+                self.agents = agents;
+            }
+            Err(e) => eprintln!("Keeping the previous agents, failed to reload {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// SHA-256 of a DER certificate as lowercase hex, the form compared against
+/// the registry.
+pub fn fingerprint(der: &[u8]) -> String {
+    digest::digest(&digest::SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn read_agents(path: &Path) -> io::Result<Vec<AgentEntry>> {
+    let contents = fs::read_to_string(path)?;
+    let file: AgentsFile = toml::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+    Ok(file.agents)
+}
+
+fn normalize(fingerprint: &str) -> String {
+    fingerprint.chars().filter(|c| *c != ':').collect::<String>().to_lowercase()
+}
+
+// Compare digests so the time taken doesn't tell how much of a guess was right
+fn tokens_match(registered: &str, presented: &str) -> bool {
+    let registered = digest::digest(&digest::SHA256, registered.as_bytes());
+    let presented = digest::digest(&digest::SHA256, presented.as_bytes());
+    registered.as_ref() == presented.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Fingerprint of the empty certificate
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    // The same, as certificate tools print it
+    const EMPTY_WRITTEN: &str =
+        "E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55";
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-agents-{}-{}.toml", name, std::process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn registry(name: &str) -> (TempFile, AgentRegistry) {
+        let file = TempFile::new(
+            name,
+            &format!(
+                r#"
+                [[agent]]
+                name = "web-1"
+                token = "c2f1d7a0e9b84b6f"
+
+                [[agent]]
+                name = "web-2"
+                token = "0b4f6a1c"
+                revoked = true
+
+                [[agent]]
+                name = "db-1"
+                certificate = "{}"
+                "#,
+                EMPTY_WRITTEN
+            ),
+        );
+        let registry = AgentRegistry::load(&file.0).unwrap();
+        (file, registry)
+    }
+
+    fn with_token(name: &str, token: &str) -> Credentials {
+        Credentials {
+            name: name.to_string(),
+            token: Some(token.to_string()),
+            certificate: None,
+        }
+    }
+
+    #[test]
+    fn tokens_must_be_listed_and_match_the_name() {
+        let (_file, mut registry) = registry("tokens");
+
+        assert_eq!(registry.authenticate(&with_token("web-1", "c2f1d7a0e9b84b6f")), Ok(()));
+        assert_eq!(
+            registry.authenticate(&with_token("web-3", "c2f1d7a0e9b84b6f")),
+            Err(Rejection::WrongName {
+                registered: "web-1".to_string(),
+                announced: "web-3".to_string(),
+            })
+        );
+        assert_eq!(registry.authenticate(&with_token("web-1", "c2f1d7a0")), Err(Rejection::UnknownToken));
+        assert_eq!(
+            registry.authenticate(&with_token("web-2", "0b4f6a1c")),
+            Err(Rejection::Revoked("web-2".to_string()))
+        );
+    }
+
+    #[test]
+    fn certificates_match_however_the_fingerprint_is_written() {
+        let (_file, mut registry) = registry("certificates");
+        let mut credentials = Credentials {
+            name: "db-1".to_string(),
+            token: None,
+            certificate: Some(fingerprint(b"")),
+        };
+        assert_eq!(registry.authenticate(&credentials), Ok(()));
+
+        credentials.certificate = Some(fingerprint(b"another certificate"));
+        assert_eq!(registry.authenticate(&credentials), Err(Rejection::UnknownCertificate));
+
+        credentials.certificate = None;
+        assert_eq!(registry.authenticate(&credentials), Err(Rejection::NoCredentials));
+    }
+
+    #[test]
+    fn fingerprints_are_lowercase_sha256() {
+        assert_eq!(fingerprint(b""), EMPTY);
+    }
+
+    #[test]
+    fn changes_to_the_file_are_picked_up() {
+        let (file, mut registry) = registry("reload");
+        let credentials = with_token("web-1", "c2f1d7a0e9b84b6f");
+        assert_eq!(registry.authenticate(&credentials), Ok(()));
+
+        // Set the time by hand, a rewrite within the same tick wouldn't show
+        let bump = |contents: &str| {
+            fs::write(&file.0, contents).unwrap();
+            let modified = registry_modified(&file.0) + Duration::from_secs(1);
+            fs::File::options().write(true).open(&file.0).unwrap().set_modified(modified).unwrap();
+        };
+
+        bump("[[agent]]\nname = \"web-1\"\ntoken = \"c2f1d7a0e9b84b6f\"\nrevoked = true\n");
+        assert_eq!(registry.authenticate(&credentials), Err(Rejection::Revoked("web-1".to_string())));
+
+        // A broken file keeps what was loaded before
+        bump("[[agent]\n");
+        assert_eq!(registry.authenticate(&credentials), Err(Rejection::Revoked("web-1".to_string())));
+    }
+
+    fn registry_modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+}
@@ -0,0 +1,198 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+pub const RATE_LIMIT_ENV: &str = "SRD_RATE_LIMIT";
+pub const RATE_BURST_ENV: &str = "SRD_RATE_BURST";
+pub const OVERLOAD_POLICY_ENV: &str = "SRD_OVERLOAD_POLICY";
+
+/// What happens to records an agent sends faster than it is allowed to, or
+/// while the file writer's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverloadPolicy {
+    /// Stop reading from the agent until it is within its limit again, so
+    /// TCP pushes back on it and nothing is lost
+    #[default]
+    SlowDown,
+    /// Keep reading and count what is thrown away
+    Drop,
+}
+
+impl fmt::Display for OverloadPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverloadPolicy::SlowDown => write!(f, "slow-down"),
+            OverloadPolicy::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slow-down" => Ok(OverloadPolicy::SlowDown),
+            "drop" => Ok(OverloadPolicy::Drop),
+            other => Err(format!("unknown overload policy {}, expected slow-down or drop", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackpressureConfig {
+    // Records per second each connection may send, 0 for no limit
+    pub records_per_sec: f64,
+    // Records a connection may send at once after being quiet, e.g. a
+    // replayed spool
+    pub burst: f64,
+    pub policy: OverloadPolicy,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            records_per_sec: 1000.0,
+            burst: 5000.0,
+            policy: OverloadPolicy::default(),
+        }
+    }
+}
+
+impl BackpressureConfig {
+    /// A full bucket for a new connection, None without a limit.
+    pub fn bucket(&self) -> Option<TokenBucket> {
+        (self.records_per_sec > 0.0).then(|| TokenBucket::new(self.records_per_sec, self.burst.max(1.0)))
+    }
+
+    /// From `SRD_RATE_LIMIT`, `SRD_RATE_BURST` and `SRD_OVERLOAD_POLICY`,
+    /// the defaults for those not set.
+    pub fn from_env() -> io::Result<Self> {
+        fn parse<T: FromStr<Err: fmt::Display>>(name: &str, default: T) -> io::Result<T> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}={}: {}", name, value, e))),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let defaults = Self::default();
+        Ok(Self {
+            records_per_sec: parse(RATE_LIMIT_ENV, defaults.records_per_sec)?,
+            burst: parse(RATE_BURST_ENV, defaults.burst)?,
+            policy: parse(OVERLOAD_POLICY_ENV, defaults.policy)?,
+        })
+    }
+}
+
+/// Token bucket refilled at `rate` tokens per second up to `burst`.
+///
+/// `take` may overdraw it, which is how a slowed down connection keeps the
+/// records it already read: it then waits out the debt before reading more.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take a token if there is one.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Take a token, going into debt if there is none.
+    pub fn take(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens -= 1.0;
+    }
+
+    /// How long until the bucket is out of debt, zero if it isn't in debt.
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / self.rate)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_full_and_refills_at_its_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 3.0);
+        bucket.updated = start;
+
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+
+        // A token every 100ms, never more than the burst
+        assert!(!bucket.try_take(start + Duration::from_millis(50)));
+        assert!(bucket.try_take(start + Duration::from_millis(100)));
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| bucket.try_take(later)).count(), 3);
+    }
+
+    #[test]
+    fn debt_is_waited_out() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(100.0, 1.0);
+        bucket.updated = start;
+
+        assert_eq!(bucket.wait(start), Duration::ZERO);
+        for _ in 0..6 {
+            bucket.take(start);
+        }
+
+        // One token was there, the other five take 10ms each
+        let wait = bucket.wait(start);
+        assert!((wait.as_secs_f64() - 0.05).abs() < 1e-9, "{:?}", wait);
+        assert_eq!(bucket.wait(start + wait), Duration::ZERO);
+        assert!(!bucket.try_take(start + wait));
+    }
+
+    #[test]
+    fn no_bucket_without_a_limit() {
+        let config = BackpressureConfig {
+            records_per_sec: 0.0,
+            ..Default::default()
+        };
+        assert!(config.bucket().is_none());
+        assert!(BackpressureConfig::default().bucket().is_some());
+    }
+
+    #[test]
+    fn policies_parse_from_their_names() {
+        for policy in [OverloadPolicy::SlowDown, OverloadPolicy::Drop] {
+            assert_eq!(policy.to_string().parse::<OverloadPolicy>(), Ok(policy));
+        }
+        assert!("block".parse::<OverloadPolicy>().is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! The collector's own metrics, and the latest value of every agent metric,
+//! over HTTP in the Prometheus text exposition format.
+//!
+//! ```text
+//! GET /metrics
+//!
+//! # HELP srd_collector_records_total Metric records received from agents and queued for the writer.
+//! # TYPE srd_collector_records_total counter
+//! srd_collector_records_total 1234
+//! # HELP srd_agent_value Latest value of each agent metric.
+//! # TYPE srd_agent_value gauge
+//! srd_agent_value{server_id="0",server="web-1",metric="cpu",unit="%"} 12.5
+//! ```
+
+use super::liveness::Liveness;
+use super::shutdown::stopped;
+use std::fmt::Write as _;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+// Longest a scraper gets to send its request and read the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Requests are a line and a few headers, anything bigger isn't a scrape
+const MAX_REQUEST: usize = 8 * 1024;
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Environment variable with the address to serve metrics on.
+pub const METRICS_LISTEN_ENV: &str = "SRD_METRICS_LISTEN";
+
+/// Counters and gauges of the collector and the agents it hears from, as
+/// they are now.
+pub fn render() -> String {
+    let mut out = Exposition::default();
+
+    let stats = super::collector_stats();
+    out.family("srd_collector_start_time_seconds", "gauge", "When the collector started, in seconds since the epoch.");
+    out.sample("srd_collector_start_time_seconds", &[], stats.started.timestamp() as f64);
+    out.family("srd_collector_connections_open", "gauge", "Agent connections currently open.");
+    out.sample("srd_collector_connections_open", &[], stats.open_connections as f64);
+    out.family("srd_collector_connections_total", "counter", "Agent connections accepted.");
+    out.sample("srd_collector_connections_total", &[], stats.connections as f64);
+    out.family(
+        "srd_collector_connections_rejected_total",
+        "counter",
+        "Agent connections closed for a failed TLS handshake or authentication.",
+    );
+    out.sample("srd_collector_connections_rejected_total", &[], stats.rejected as f64);
+    out.family(
+        "srd_collector_records_total",
+        "counter",
+        "Metric records received from agents and queued for the writer.",
+    );
+    out.sample("srd_collector_records_total", &[], stats.records as f64);
+    out.family(
+        "srd_collector_invalid_frames_total",
+        "counter",
+        "Malformed frames on all connections, including ones that never identified.",
+    );
+    out.sample("srd_collector_invalid_frames_total", &[], stats.malformed as f64);
+    out.family("srd_collector_alerts_total", "counter", "Alerts fired or resolved.");
+    out.sample("srd_collector_alerts_total", &[], stats.alerts as f64);
+    out.family(
+        "srd_collector_subscriber_dropped_events_total",
+        "counter",
+        "Events live subscribers such as the dashboard missed for falling behind.",
+    );
+    out.sample("srd_collector_subscriber_dropped_events_total", &[], stats.subscriber_drops as f64);
+
+    let writer = super::writer_stats();
+    out.family("srd_collector_written_lines_total", "counter", "Lines written to the log files.");
+    out.sample("srd_collector_written_lines_total", &[], writer.lines as f64);
+    out.family("srd_collector_written_bytes_total", "counter", "Bytes written to the log files.");
+    out.sample("srd_collector_written_bytes_total", &[], writer.bytes as f64);
+    out.family("srd_collector_open_files", "gauge", "Log files currently open.");
+    out.sample("srd_collector_open_files", &[], writer.open_files as f64);
+    out.family("srd_collector_write_errors_total", "counter", "Failed writes, by where they went.");
+    out.sample("srd_collector_write_errors_total", &[("target", "log")], writer.errors as f64);
+    out.sample("srd_collector_write_errors_total", &[("target", "store")], stats.store_errors as f64);
+
+    let mut frames: Vec<_> = super::frame_stats().into_iter().collect();
+    frames.sort_by_key(|(server_id, _)| *server_id);
+    out.family("srd_collector_frames_total", "counter", "Frames received per server.");
+    for (server_id, stats) in &frames {
+        let (id, label) = (server_id.to_string(), super::server_label(*server_id));
+        out.sample("srd_collector_frames_total", &[("server_id", &id), ("server", &label)], stats.frames as f64);
+    }
+    out.family("srd_collector_malformed_frames_total", "counter", "Frames rejected per server, by reason.");
+    for (server_id, stats) in &frames {
+        let (id, label) = (server_id.to_string(), super::server_label(*server_id));
+        for (reason, count) in [
+            ("too_long", stats.too_long),
+            ("invalid_utf8", stats.invalid_utf8),
+            ("unparseable", stats.unparseable),
+            ("out_of_range", stats.out_of_range),
+        ] {
+            let labels = [("server_id", id.as_str()), ("server", &label), ("reason", reason)];
+            out.sample("srd_collector_malformed_frames_total", &labels, count as f64);
+        }
+    }
+    out.family("srd_collector_dropped_records_total", "counter", "Records dropped per server, by reason.");
+    for (server_id, stats) in &frames {
+        let (id, label) = (server_id.to_string(), super::server_label(*server_id));
+        for (reason, count) in [("rate_limited", stats.rate_limited), ("queue_full", stats.queue_full)] {
+            let labels = [("server_id", id.as_str()), ("server", &label), ("reason", reason)];
+            out.sample("srd_collector_dropped_records_total", &labels, count as f64);
+        }
+    }
+
+    out.family("srd_server_state", "gauge", "Whether each server is up, stale or down, 1 for its current state.");
+    for (server_id, _) in &frames {
+        let Some(state) = super::liveness(*server_id) else {
+            continue;
+        };
+        let (id, label) = (server_id.to_string(), super::server_label(*server_id));
+        for candidate in [Liveness::Up, Liveness::Stale, Liveness::Down] {
+            let name = candidate.to_string();
+            let labels = [("server_id", id.as_str()), ("server", &label), ("state", &name)];
+            out.sample("srd_server_state", &labels, if candidate == state { 1.0 } else { 0.0 });
+        }
+    }
+
+    out.family(
+        "srd_collector_untracked_values_total",
+        "counter",
+        "Agent samples left out of srd_agent_value once it had as many series as it keeps.",
+    );
+    out.sample("srd_collector_untracked_values_total", &[], stats.untracked_values as f64);
+
+    let latest = super::latest_values();
+    out.family("srd_agent_value", "gauge", "Latest value of each agent metric.");
+    for record in &latest {
+        let (id, label) = (record.server_id.to_string(), super::server_label(record.server_id));
+        let labels = [
+            ("server_id", id.as_str()),
+            ("server", &label),
+            ("metric", record.metric.name()),
+            ("unit", &record.unit),
+        ];
+        out.sample("srd_agent_value", &labels, record.value);
+    }
+    out.family(
+        "srd_agent_value_timestamp_seconds",
+        "gauge",
+        "When the latest value of each agent metric was sampled, in seconds since the epoch.",
+    );
+    for record in &latest {
+        let (id, label) = (record.server_id.to_string(), super::server_label(record.server_id));
+        let labels = [("server_id", id.as_str()), ("server", &label), ("metric", record.metric.name())];
+        let timestamp = record.timestamp.timestamp_millis() as f64 / 1000.0;
+        out.sample("srd_agent_value_timestamp_seconds", &labels, timestamp);
+    }
+
+    out.text
+}
+
+/// Answer scrapes on `listener` until `stop` is set.
+pub async fn serve(listener: TcpListener, mut stop: watch::Receiver<bool>) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = stopped(&mut stop) => break,
+        };
+
+        match accepted {
+            Ok((stream, peer)) => {
+                tokio::spawn(async move {
+                    match tokio::time::timeout(REQUEST_TIMEOUT, answer(stream)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => println!("Error answering metrics request from {}: {}", peer, e),
+                        Err(_) => println!("Metrics request from {} timed out", peer),
+                    }
+                });
+            }
+            Err(e) => {
+                println!("Error accepting metrics request: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn answer(mut stream: TcpStream) -> std::io::Result<()> {
+    // Only the request line matters, the headers are read past
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let size = stream.read(&mut buffer).await?;
+        if size == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..size]);
+    }
+
+    let response = respond(&String::from_utf8_lossy(&request));
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// The full HTTP response to a request
+fn respond(request: &str) -> String {
+    let mut parts = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render()),
+        ("GET", _) => ("404 Not Found", String::from("Not found, try /metrics\n")),
+        _ => ("405 Method Not Allowed", String::from("Only GET is supported\n")),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", format_value(value));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_values_are_escaped() {
+        let mut out = Exposition::default();
+        out.family("srd_test", "gauge", "A test family.");
+        out.sample("srd_test", &[], 1.0);
+        out.sample("srd_test", &[("server", "web \"1\"\\a\nb"), ("metric", "cpu")], 12.5);
+
+        assert_eq!(
+            out.text,
+            "# HELP srd_test A test family.\n\
+             # TYPE srd_test gauge\n\
+             srd_test 1\n\
+             srd_test{server=\"web \\\"1\\\"\\\\a\\nb\",metric=\"cpu\"} 12.5\n"
+        );
+    }
+
+    #[test]
+    fn special_values_use_the_exposition_names() {
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(-0.25), "-0.25");
+        assert_eq!(format_value(1e21), "1000000000000000000000");
+    }
+
+    #[test]
+    fn every_sample_follows_its_family() {
+        let text = render();
+        let mut declared = Vec::new();
+
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = rest.split_once(' ').unwrap();
+                assert!(kind == "counter" || kind == "gauge", "{}", line);
+                declared.push(name.to_string());
+            } else if !line.starts_with("# HELP ") {
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert_eq!(declared.last().map(String::as_str), Some(name), "{}", line);
+                let value = line.rsplit(' ').next().unwrap();
+                assert!(value.parse::<f64>().is_ok() || value == "NaN", "{}", line);
+            }
+        }
+        assert!(declared.iter().any(|name| name == "srd_collector_records_total"));
+        assert!(declared.iter().any(|name| name == "srd_agent_value"));
+    }
+
+    #[test]
+    fn only_get_metrics_is_served() {
+        let response = respond("GET /metrics?name=x HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("# TYPE srd_collector_records_total counter"));
+
+        assert!(respond("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found"));
+        assert!(respond("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405 Method Not Allowed"));
+        assert!(respond("").starts_with("HTTP/1.1 405"));
+    }
+}
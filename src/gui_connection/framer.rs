@@ -0,0 +1,200 @@
+use std::fmt;
+
+// Longest line accepted from an agent, a v2 record is well under 200 bytes
+pub const MAX_LINE_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    TooLong,
+    InvalidUtf8,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLong => write!(f, "line longer than {} bytes", MAX_LINE_LENGTH),
+            FrameError::InvalidUtf8 => write!(f, "line is not valid UTF-8"),
+        }
+    }
+}
+
+/// Splits a byte stream into lines, carrying partial lines across reads.
+///
+/// A line over the limit is reported once and then skipped up to its
+/// newline, so a misbehaving agent can't make the buffer grow unbounded.
+pub struct LineFramer {
+    buffer: Vec<u8>,
+    max_line: usize,
+    discarding: bool,
+}
+
+impl Default for LineFramer {
+    fn default() -> Self {
+        Self::new(MAX_LINE_LENGTH)
+    }
+}
+
+impl LineFramer {
+    pub fn new(max_line: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_line,
+            discarding: false,
+        }
+    }
+
+    /// Feed newly read bytes and return every line they complete.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<String, FrameError>> {
+        let mut frames = Vec::new();
+        let mut rest = data;
+
+        while let Some(newline) = rest.iter().position(|b| *b == b'\n') {
+            let (line, tail) = rest.split_at(newline);
+            rest = &tail[1..];
+
+            if self.discarding {
+                // End of an oversized line that was already reported
+                self.discarding = false;
+                self.buffer.clear();
+                continue;
+            }
+
+            self.buffer.extend_from_slice(line);
+            frames.push(self.take_line());
+        }
+
+        if !self.discarding {
+            self.buffer.extend_from_slice(rest);
+            if self.buffer.len() > self.max_line {
+                self.buffer.clear();
+                self.discarding = true;
+                frames.push(Err(FrameError::TooLong));
+            }
+        }
+
+        frames
+    }
+
+    /// Flush a trailing line that was not newline terminated, at end of stream.
+    pub fn finish(&mut self) -> Option<Result<String, FrameError>> {
+        if self.discarding || self.buffer.is_empty() {
+            self.discarding = false;
+            self.buffer.clear();
+            return None;
+        }
+
+        Some(self.take_line())
+    }
+
+    fn take_line(&mut self) -> Result<String, FrameError> {
+        let mut line = std::mem::take(&mut self.buffer);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        if line.len() > self.max_line {
+            return Err(FrameError::TooLong);
+        }
+        String::from_utf8(line).map_err(|_| FrameError::InvalidUtf8)
+    }
+}
+
+/// Per agent counts of the frames received and the ones rejected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub frames: u64,
+    pub too_long: u64,
+    pub invalid_utf8: u64,
+    pub unparseable: u64,
+    // Records stamped too far from when they arrived
+    pub out_of_range: u64,
+    // Records dropped over the agent's rate limit
+    pub rate_limited: u64,
+    // Records dropped because the file writer had fallen behind
+    pub queue_full: u64,
+}
+
+impl FrameStats {
+    pub fn malformed(&self) -> u64 {
+        self.too_long + self.invalid_utf8 + self.unparseable + self.out_of_range
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.rate_limited + self.queue_full
+    }
+
+    pub fn record_error(&mut self, error: FrameError) {
+        match error {
+            FrameError::TooLong => self.too_long += 1,
+            FrameError::InvalidUtf8 => self.invalid_utf8 += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &FrameStats) {
+        self.frames += other.frames;
+        self.too_long += other.too_long;
+        self.invalid_utf8 += other.invalid_utf8;
+        self.unparseable += other.unparseable;
+        self.out_of_range += other.out_of_range;
+        self.rate_limited += other.rate_limited;
+        self.queue_full += other.queue_full;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_lines_split_across_reads() {
+        let mut framer = LineFramer::default();
+
+        assert_eq!(framer.push(b"METRIC a"), []);
+        assert_eq!(framer.push(b"b\nMETRIC c\r\nMET"), [Ok(String::from("METRIC ab")), Ok(String::from("METRIC c"))]);
+        assert_eq!(framer.push(b"RIC d\n"), [Ok(String::from("METRIC d"))]);
+        assert_eq!(framer.finish(), None);
+    }
+
+    #[test]
+    fn flushes_a_last_line_without_newline() {
+        let mut framer = LineFramer::default();
+
+        assert_eq!(framer.push(b"first\nlast"), [Ok(String::from("first"))]);
+        assert_eq!(framer.finish(), Some(Ok(String::from("last"))));
+        assert_eq!(framer.finish(), None);
+    }
+
+    #[test]
+    fn reports_a_long_line_once_and_skips_the_rest_of_it() {
+        let mut framer = LineFramer::new(8);
+
+        assert_eq!(framer.push(b"0123456789"), [Err(FrameError::TooLong)]);
+        assert_eq!(framer.push(b"more of the same line"), []);
+        assert_eq!(framer.push(b" end\nok\n"), [Ok(String::from("ok"))]);
+
+        // Over the limit within a single read
+        assert_eq!(framer.push(b"0123456789\nok\n"), [Err(FrameError::TooLong), Ok(String::from("ok"))]);
+        assert_eq!(framer.push(b"12345678\n"), [Ok(String::from("12345678"))]);
+    }
+
+    #[test]
+    fn reports_invalid_utf8() {
+        let mut framer = LineFramer::default();
+
+        assert_eq!(framer.push(b"\xff\xfe\nok\n"), [Err(FrameError::InvalidUtf8), Ok(String::from("ok"))]);
+    }
+
+    #[test]
+    fn merges_stats() {
+        let mut total = FrameStats::default();
+        let mut stats = FrameStats { frames: 3, ..Default::default() };
+        stats.record_error(FrameError::TooLong);
+        stats.record_error(FrameError::InvalidUtf8);
+
+        total.merge(&stats);
+        total.merge(&stats);
+
+        assert_eq!(total.frames, 6);
+        assert_eq!(total.malformed(), 4);
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Environment variables with the seconds of silence after which a server
+/// is stale and down.
+pub const STALE_AFTER_ENV: &str = "SRD_STALE_AFTER_SECS";
+pub const DOWN_AFTER_ENV: &str = "SRD_DOWN_AFTER_SECS";
+
+/// Whether a server is still sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    Up,
+    // Quiet for longer than expected, may only be slow
+    Stale,
+    // Gone quiet for good or disconnected
+    Down,
+}
+
+impl fmt::Display for Liveness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Liveness::Up => write!(f, "up"),
+            Liveness::Stale => write!(f, "stale"),
+            Liveness::Down => write!(f, "down"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LivenessConfig {
+    // Silence after which a server is stale
+    pub stale_after: Duration,
+    // Silence after which a server is down
+    pub down_after: Duration,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            stale_after: Duration::from_secs(5),
+            down_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl LivenessConfig {
+    /// Stale after `stale_after` and down after `down_after`, which has to
+    /// be later.
+    pub fn new(stale_after: Duration, down_after: Duration) -> io::Result<Self> {
+        if stale_after.is_zero() || down_after <= stale_after {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the stale timeout has to be above zero and below the down timeout, got {:?} and {:?}",
+                    stale_after, down_after
+                ),
+            ));
+        }
+        Ok(Self { stale_after, down_after })
+    }
+
+    /// From `SRD_STALE_AFTER_SECS` and `SRD_DOWN_AFTER_SECS`, the defaults
+    /// for those not set.
+    pub fn from_env() -> io::Result<Self> {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}={}: {}", name, value, e))),
+            Err(_) => Ok(default),
+        };
+        Self::new(secs(STALE_AFTER_ENV, defaults.stale_after)?, secs(DOWN_AFTER_ENV, defaults.down_after)?)
+    }
+}
+
+/// Up/Stale/Down state of every server from when it was last heard from.
+///
+/// Anything a server sends counts as a heartbeat. The state only moves back
+/// to up when the server is heard from again. A server connected more than
+/// once, e.g. while an agent reconnects, is down once its last connection
+/// closes.
+#[derive(Debug, Default)]
+pub struct LivenessTracker {
+    config: LivenessConfig,
+    servers: HashMap<u32, (DateTime<Utc>, Liveness)>,
+    // Open connections of each server
+    connections: HashMap<u32, usize>,
+}
+
+impl LivenessTracker {
+    pub fn new(config: LivenessConfig) -> Self {
+        Self {
+            config,
+            servers: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> LivenessConfig {
+        self.config
+    }
+
+    pub fn state(&self, server_id: u32) -> Option<Liveness> {
+        self.servers.get(&server_id).map(|(_, state)| *state)
+    }
+
+    /// Record a heartbeat, returning `Up` if the server wasn't up before.
+    /// Heartbeats older than the last one are ignored.
+    pub fn seen(&mut self, server_id: u32, at: DateTime<Utc>) -> Option<Liveness> {
+        let (last_seen, state) = self.servers.entry(server_id).or_insert((at, Liveness::Down));
+        if at < *last_seen {
+            return None;
+        }
+        *last_seen = at;
+
+        let was = std::mem::replace(state, Liveness::Up);
+        (was != Liveness::Up).then_some(Liveness::Up)
+    }
+
+    /// Force a state, e.g. down once the connection closed. Returns the
+    /// state if it changed.
+    pub fn set(&mut self, server_id: u32, state: Liveness, at: DateTime<Utc>) -> Option<Liveness> {
+        if state == Liveness::Up {
+            return self.seen(server_id, at);
+        }
+
+        let (_, current) = self.servers.entry(server_id).or_insert((at, state));
+        let was = std::mem::replace(current, state);
+        (was != state).then_some(state)
+    }
+
+    /// Count a connection identified as the server.
+    pub fn connected(&mut self, server_id: u32) {
+        *self.connections.entry(server_id).or_default() += 1;
+    }
+
+    /// Forget a closed connection, marking the server down if it was its
+    /// last. Returns the state if it changed.
+    pub fn disconnected(&mut self, server_id: u32, at: DateTime<Utc>) -> Option<Liveness> {
+        match self.connections.get_mut(&server_id) {
+            Some(open) if *open > 1 => {
+                *open -= 1;
+                None
+            }
+            _ => {
+                self.connections.remove(&server_id);
+                self.set(server_id, Liveness::Down, at)
+            }
+        }
+    }
+
+    /// Age every server by the time since its last heartbeat, returning the
+    /// servers whose state changed.
+    pub fn check(&mut self, now: DateTime<Utc>) -> Vec<(u32, Liveness)> {
+        let mut changed = Vec::new();
+
+        for (server_id, (last_seen, state)) in &mut self.servers {
+            let silence = (now - *last_seen).to_std().unwrap_or_default();
+            let aged = if silence >= self.config.down_after {
+                Liveness::Down
+            } else if silence >= self.config.stale_after {
+                Liveness::Stale
+            } else {
+                Liveness::Up
+            };
+
+            // Only ever downgrade here, a server comes back through `seen`
+            let downgrade = matches!((*state, aged), (Liveness::Up, Liveness::Stale | Liveness::Down) | (Liveness::Stale, Liveness::Down));
+            if downgrade {
+                *state = aged;
+                changed.push((*server_id, aged));
+            }
+        }
+
+        changed.sort_by_key(|(server_id, _)| *server_id);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn down_has_to_come_after_stale() {
+        let secs = Duration::from_secs;
+        assert!(LivenessConfig::new(secs(5), secs(30)).is_ok());
+        assert!(LivenessConfig::new(secs(30), secs(5)).is_err());
+        assert!(LivenessConfig::new(secs(5), secs(5)).is_err());
+        assert!(LivenessConfig::new(secs(0), secs(5)).is_err());
+    }
+
+    #[test]
+    fn quiet_servers_go_stale_then_down_and_come_back_up() {
+        let config = LivenessConfig::new(Duration::from_secs(5), Duration::from_secs(30)).unwrap();
+        let mut tracker = LivenessTracker::new(config);
+        let start = Utc::now();
+
+        assert_eq!(tracker.seen(1, start), Some(Liveness::Up));
+        assert_eq!(tracker.check(start + chrono::Duration::seconds(4)), []);
+        assert_eq!(tracker.check(start + chrono::Duration::seconds(6)), [(1, Liveness::Stale)]);
+        assert_eq!(tracker.check(start + chrono::Duration::seconds(31)), [(1, Liveness::Down)]);
+        assert_eq!(tracker.seen(1, start + chrono::Duration::seconds(32)), Some(Liveness::Up));
+    }
+
+    #[test]
+    fn servers_are_down_once_their_last_connection_closes() {
+        let mut tracker = LivenessTracker::default();
+        let now = Utc::now();
+
+        // The agent reconnected before its old connection was noticed closed
+        tracker.connected(1);
+        assert_eq!(tracker.seen(1, now), Some(Liveness::Up));
+        tracker.connected(1);
+        assert_eq!(tracker.disconnected(1, now), None);
+        assert_eq!(tracker.state(1), Some(Liveness::Up));
+
+        assert_eq!(tracker.disconnected(1, now), Some(Liveness::Down));
+        assert_eq!(tracker.disconnected(1, now), None);
+
+        tracker.connected(1);
+        assert_eq!(tracker.seen(1, now), Some(Liveness::Up));
+        assert_eq!(tracker.disconnected(1, now), Some(Liveness::Down));
+    }
+}
@@ -0,0 +1,144 @@
+//! Format of the lines the collector writes to its log files.
+//!
+//! Each record is one line of tab separated fields (shown here as `→`):
+//!
+//! ```text
+//! <received>→<peer>→<connection>→<server id>→<server label>→<payload>
+//! 2025-03-17T01:47:02.123Z→10.0.0.4:51234→3→0→web-1→METRIC ts=2025-03-17T01:47:02.000Z server=0 metric=cpu value=12.5 unit=%
+//! ```
+//!
+//! - `received`: when the collector read the line, RFC3339 UTC with milliseconds
+//! - `peer`: address the agent connected from
+//! - `connection`: collector connection id, unique until the collector restarts
+//! - `server id` / `server label`: the identity assigned by the server registry
+//! - `payload`: the line exactly as the agent sent it, v1 or v2
+//!
+//! Logs written before this format held bare payload lines, which
+//! `parse_log_line` still accepts.
+
+use crate::protocol::{parse_frame, parse_frame_at, Frame, MetricRecord};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub received: DateTime<Utc>,
+    pub peer: String,
+    pub connection_id: u64,
+    pub server_id: u32,
+    pub label: String,
+    pub payload: String,
+}
+
+impl LogRecord {
+    /// Parse one line written by `Display`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end_matches(['\r', '\n']).splitn(6, '\t');
+
+        Some(Self {
+            received: DateTime::parse_from_rfc3339(fields.next()?).ok()?.with_timezone(&Utc),
+            peer: fields.next()?.to_string(),
+            connection_id: fields.next()?.parse().ok()?,
+            server_id: fields.next()?.parse().ok()?,
+            label: fields.next()?.to_string(),
+            payload: fields.next()?.to_string(),
+        })
+    }
+
+    /// The metric in the payload, attributed to the assigned server id.
+    pub fn metric(&self) -> Option<MetricRecord> {
+        match parse_frame_at(&self.payload, self.received)? {
+            Frame::Metric(mut record) => {
+                record.server_id = self.server_id;
+                Some(record)
+            }
+            Frame::Hello(_) | Frame::Heartbeat(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.received.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.peer,
+            self.connection_id,
+            self.server_id,
+            self.label,
+            self.payload
+        )
+    }
+}
+
+/// Read the metric from a log line of either the current or the old bare format.
+pub fn parse_log_line(line: &str) -> Option<MetricRecord> {
+    match LogRecord::parse(line) {
+        Some(record) => record.metric(),
+        None => match parse_frame(line)? {
+            Frame::Metric(record) => Some(record),
+            Frame::Hello(_) | Frame::Heartbeat(_) => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Metric;
+
+    fn record(payload: &str) -> LogRecord {
+        LogRecord {
+            received: DateTime::parse_from_rfc3339("2025-03-17T01:47:02.123Z").unwrap().with_timezone(&Utc),
+            peer: "10.0.0.4:51234".to_string(),
+            connection_id: 3,
+            server_id: 7,
+            label: "web-1".to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let record = record("METRIC ts=2025-03-17T01:47:02.000Z server=0 metric=cpu value=12.5 unit=%");
+        let line = record.to_string();
+        assert_eq!(LogRecord::parse(&line), Some(record.clone()));
+        assert_eq!(LogRecord::parse(&format!("{}\r\n", line)), Some(record));
+    }
+
+    #[test]
+    fn tabs_stay_in_the_payload() {
+        let record = record("METRIC ts=2025-03-17T01:47:02.000Z\tserver=0\tmetric=cpu\tvalue=12.5");
+        let parsed = LogRecord::parse(&record.to_string()).unwrap();
+        assert_eq!(parsed.payload, record.payload);
+
+        // Attributed to the assigned id, not the one the agent sent
+        let metric = parsed.metric().unwrap();
+        assert_eq!(metric.server_id, 7);
+        assert_eq!(metric.value, 12.5);
+    }
+
+    #[test]
+    fn bare_lines_of_old_logs_still_parse() {
+        let metric = parse_log_line("METRIC ts=2025-03-17T01:47:02.000Z server=2 metric=fs value=40 unit=%").unwrap();
+        assert_eq!(metric.server_id, 2);
+        assert_eq!(metric.metric, Metric::Fs);
+
+        let legacy = parse_log_line("4-0-55.5-10:11:12").unwrap();
+        assert_eq!((legacy.server_id, legacy.metric, legacy.value), (4, Metric::Cpu, 55.5));
+
+        // Legacy stamps land on the receive date of the record
+        let line = record("4-0-55.5-10:11:12").to_string();
+        let dated = parse_log_line(&line).unwrap();
+        assert_eq!(dated.timestamp.to_rfc3339(), "2025-03-17T10:11:12+00:00");
+        assert_eq!(dated.server_id, 7);
+    }
+
+    #[test]
+    fn frames_without_a_metric_are_skipped() {
+        assert_eq!(parse_log_line(&record("HEARTBEAT seq=4").to_string()), None);
+        assert_eq!(parse_log_line("HELLO v2 name=web-1"), None);
+        assert_eq!(parse_log_line("not a record"), None);
+    }
+}
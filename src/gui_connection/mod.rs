@@ -0,0 +1,1270 @@
+pub mod auth;
+pub mod backpressure;
+pub mod exporter;
+pub mod framer;
+pub mod liveness;
+pub mod log_format;
+pub mod registry;
+pub mod shutdown;
+pub mod writer;
+
+use crate::alerts::rules::{load_rules, load_sinks, RULES_FILE};
+use crate::alerts::sinks::{Notifier, SinkConfig};
+use crate::alerts::{AlertEngine, AlertEvent};
+use crate::heartbeat::{self, HeartbeatConfig, HeartbeatSequence};
+use crate::protocol::{parse_frame_at, Frame, Hello, Metric, MetricRecord, LEGACY_VERSION, PROTOCOL_VERSION};
+use crate::store::{SharedStore, Store, StoreConfig, STORE_DIRECTORY};
+use crate::tls::ServerTls;
+use auth::{AgentRegistry, Credentials, Rejection};
+use backpressure::{BackpressureConfig, OverloadPolicy, TokenBucket};
+use once_cell::sync::Lazy;
+use framer::{FrameError, FrameStats, LineFramer};
+use liveness::{Liveness, LivenessConfig, LivenessTracker};
+use log_format::LogRecord;
+use shutdown::{stopped, ShutdownHandle};
+use writer::{LogWriter, WriterStats};
+use registry::{is_safe_name, RegisteredServer, ServerIdentity, ServerRegistry, REGISTRY_FILE};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError}, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{Receiver as EventReceiver, Sender as EventSender};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+
+// Longest wait for an agent to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How often open connections are checked against the agent registry, so
+// revoking an agent also closes its connection
+const AUTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Pause after a failed accept so a persistent error doesn't spin the loop
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+// How often the file writer checks for shutdown while the channel is idle
+const WRITER_POLL: Duration = Duration::from_millis(100);
+// How often the file writer prints its throughput
+const WRITER_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+// Every alert that fired or resolved, in the log directory
+pub const ALERT_LOG_FILE: &str = "alerts.log";
+// How often expired store segments are looked for
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// How often servers that went quiet are looked for
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Events waiting for the file writer. Once full, connections are slowed
+// down or their records dropped, as BACKPRESSURE_CONFIG says.
+const EVENT_QUEUE_CAPACITY: usize = 10_000;
+// Events waiting for each live subscriber; a subscriber that falls this far
+// behind misses events rather than holding up the collector
+const SUBSCRIBER_CAPACITY: usize = 10_000;
+// Least time between two reports of a connection's dropped records
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+// Most series kept for the metrics endpoint, as agents pick their metric names
+const MAX_LATEST_VALUES: usize = 10_000;
+
+#[derive(Debug)]
+struct CollectorCounters {
+    started: chrono::DateTime<chrono::Utc>,
+    connections: AtomicU64,
+    open_connections: AtomicU64,
+    // TLS handshakes that failed and agents turned away or closed by authentication
+    rejected: AtomicU64,
+    records: AtomicU64,
+    // Malformed frames on every connection, including ones that never identify
+    malformed: AtomicU64,
+    store_errors: AtomicU64,
+    alerts: AtomicU64,
+    // Latest values not kept once MAX_LATEST_VALUES series were
+    untracked_values: AtomicU64,
+}
+
+impl Default for CollectorCounters {
+    fn default() -> Self {
+        Self {
+            started: chrono::Utc::now(),
+            connections: AtomicU64::new(0),
+            open_connections: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            records: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+            store_errors: AtomicU64::new(0),
+            alerts: AtomicU64::new(0),
+            untracked_values: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Totals of the collector since it started.
+#[derive(Debug, Clone, Copy)]
+pub struct CollectorStats {
+    pub started: chrono::DateTime<chrono::Utc>,
+    pub connections: u64,
+    pub open_connections: u64,
+    pub rejected: u64,
+    pub records: u64,
+    pub malformed: u64,
+    pub store_errors: u64,
+    pub alerts: u64,
+    pub subscriber_drops: u64,
+    pub untracked_values: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    Identified(u32, String), // server_id and its label
+    NewMessage(LogRecord), // Received line with its metadata
+    Disconnected(u32),
+    Alert(AlertEvent), // An alert rule fired or resolved
+    Liveness(u32, Liveness), // A server came up, went stale or went down
+    Dropped(u32, u64), // server_id and the records dropped from it so far
+}
+
+type EventChannel = (
+    Arc<Mutex<SyncSender<ConnectionEvent>>>,
+    Arc<Mutex<Receiver<ConnectionEvent>>>,
+);
+
+// Global channel to receive server events
+static MESSAGE_CHANNEL: Lazy<EventChannel> = Lazy::new(|| {
+    let (tx, rx) = mpsc::sync_channel(EVENT_QUEUE_CAPACITY);
+    (Arc::new(Mutex::new(tx)), Arc::new(Mutex::new(rx)))
+});
+
+// Live listeners (e.g. the GUI) that get a copy of every event
+static SUBSCRIBERS: Lazy<Mutex<Vec<EventSender<ConnectionEvent>>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+// Events live subscribers missed because they had fallen behind
+static SUBSCRIBER_DROPS: AtomicU64 = AtomicU64::new(0);
+
+// What the collector itself has done, for the metrics endpoint
+static COUNTERS: Lazy<CollectorCounters> = Lazy::new(CollectorCounters::default);
+
+// Latest sample of every metric of every server
+static LATEST_VALUES: Lazy<Mutex<HashMap<(u32, Metric), MetricRecord>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Where the metrics endpoint listens, None to not serve it
+static METRICS_ADDRESS: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+// Stable ids of every agent that has ever connected
+static SERVER_REGISTRY: Lazy<Mutex<ServerRegistry>> = Lazy::new(|| Mutex::new(ServerRegistry::default()));
+
+// Frame counts per server id, accumulated across connections
+static FRAME_STATS: Lazy<Mutex<HashMap<u32, FrameStats>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Certificate and key agents are served TLS with, None for plaintext
+static TLS_CONFIG: Lazy<Mutex<Option<ServerTls>>> = Lazy::new(|| Mutex::new(None));
+
+// Registry of the agents allowed to connect, None lets any agent in
+static AGENTS_FILE: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+// Rate limit of each connection and what to do when it or the writer is overwhelmed
+static BACKPRESSURE_CONFIG: Lazy<Mutex<BackpressureConfig>> = Lazy::new(|| Mutex::new(BackpressureConfig::default()));
+
+// Heartbeats, dead peer timeout and keepalive of agent connections
+static HEARTBEAT_CONFIG: Lazy<Mutex<HeartbeatConfig>> = Lazy::new(|| Mutex::new(HeartbeatConfig::default()));
+
+// When every server was last heard from
+static LIVENESS: Lazy<Mutex<LivenessTracker>> = Lazy::new(|| Mutex::new(LivenessTracker::default()));
+
+// Latest totals of the file writer thread
+static WRITER_STATS: Lazy<Mutex<WriterStats>> = Lazy::new(|| Mutex::new(WriterStats::default()));
+
+// The store the file writer appends to, while it runs
+static STORE: Lazy<Mutex<Option<SharedStore>>> = Lazy::new(|| Mutex::new(None));
+
+// File writer configuration
+struct FileWriterConfig {
+    enabled: bool,
+    directory: String,
+    file_prefix: String,
+    store: Option<StoreConfig>, // None keeps only the text logs
+    alert_rules: PathBuf,
+}
+
+// Default configuration
+static FILE_WRITER_CONFIG: Lazy<Mutex<FileWriterConfig>> = Lazy::new(|| {
+    Mutex::new(FileWriterConfig {
+        enabled: true,
+        directory: "logs".to_string(),
+        file_prefix: "tcpdata".to_string(),
+        store: Some(StoreConfig::default()),
+        alert_rules: PathBuf::from(RULES_FILE),
+    })
+});
+
+struct ServerMonitor {
+    listener: std::net::TcpListener,
+    // Open connections and their peers, keyed by connection id
+    connections: Arc<Mutex<HashMap<u64, SocketAddr>>>,
+    next_connection_id: u64,
+    tls: Option<TlsAcceptor>,
+    access: Access,
+    metrics_listener: Option<std::net::TcpListener>,
+}
+
+// Who a connection is let in as
+#[derive(Clone, Default)]
+struct Access {
+    // None lets in any agent that got through the TLS handshake
+    agents: Option<Arc<Mutex<AgentRegistry>>>,
+    // With a client CA but no registry, the certificate is all there is to check
+    require_certificate: bool,
+    // Fingerprint of the agent's verified client certificate
+    certificate: Option<String>,
+}
+
+impl ServerMonitor {
+    fn new(address: &str) -> io::Result<Self> {
+        // Bind up front so callers see address errors immediately
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        // Likewise for unreadable certificates and agent registries
+        let tls_config = TLS_CONFIG.lock().unwrap().clone();
+        let tls = match &tls_config {
+            Some(tls) => Some(TlsAcceptor::from(tls.config()?)),
+            None => None,
+        };
+        let agents = match AGENTS_FILE.lock().unwrap().as_deref() {
+            Some(path) => Some(Arc::new(Mutex::new(AgentRegistry::load(path)?))),
+            None => None,
+        };
+        let access = Access {
+            require_certificate: agents.is_none() && tls_config.is_some_and(|tls| tls.client_ca.is_some()),
+            agents,
+            certificate: None,
+        };
+
+        // Counts from here, whenever the first event happens
+        Lazy::force(&COUNTERS);
+        let metrics_listener = match METRICS_ADDRESS.lock().unwrap().as_deref() {
+            Some(address) => {
+                let listener = std::net::TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+
+        Ok(ServerMonitor {
+            listener,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            next_connection_id: 0,
+            tls,
+            access,
+            metrics_listener,
+        })
+    }
+
+    // Run the accept loop on a dedicated tokio runtime until shutdown
+    fn start(self, stop: watch::Receiver<bool>) {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("collector")
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start collector runtime: {}", e);
+                return;
+            }
+        };
+
+        println!(
+            "TCP server started, listening on {}{}",
+            self.listener.local_addr().unwrap(),
+            if self.tls.is_some() { " with TLS" } else { "" }
+        );
+        if let Some(listener) = &self.metrics_listener {
+            println!("Serving metrics on http://{}/metrics", listener.local_addr().unwrap());
+        }
+        if let Some(agents) = &self.access.agents {
+            println!("Accepting only the agents in {}", agents.lock().unwrap().path().display());
+        } else if self.access.require_certificate {
+            println!("Accepting only agents with a client certificate");
+        }
+
+        // Create log directory if it doesn't exist
+        let config = FILE_WRITER_CONFIG.lock().unwrap();
+        if config.enabled
+            && !Path::new(&config.directory).exists()
+            && let Err(e) = std::fs::create_dir_all(&config.directory)
+        {
+            eprintln!("Failed to create log directory: {}", e);
+        }
+        *SERVER_REGISTRY.lock().unwrap() = ServerRegistry::load(&Path::new(&config.directory).join(REGISTRY_FILE));
+        drop(config);
+
+        runtime.block_on(self.run(stop));
+    }
+
+    async fn run(self, mut stop: watch::Receiver<bool>) {
+        let ServerMonitor { listener, connections, mut next_connection_id, tls, access, metrics_listener } = self;
+        let listener = match TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to register listener: {}", e);
+                return;
+            }
+        };
+
+        let metrics = match metrics_listener.map(TcpListener::from_std) {
+            Some(Ok(listener)) => Some(tokio::spawn(exporter::serve(listener, stop.clone()))),
+            Some(Err(e)) => {
+                eprintln!("Failed to register metrics listener: {}", e);
+                None
+            }
+            None => None,
+        };
+
+        let mut clients = JoinSet::new();
+        let mut liveness_check = tokio::time::interval(LIVENESS_CHECK_INTERVAL);
+        let sender = MESSAGE_CHANNEL.0.lock().unwrap().clone();
+
+        loop {
+            // Accept new connections
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = liveness_check.tick() => {
+                    for (server_id, state) in LIVENESS.lock().unwrap().check(chrono::Utc::now()) {
+                        queue_event(&sender, ConnectionEvent::Liveness(server_id, state));
+                    }
+                    continue;
+                }
+                _ = stopped(&mut stop) => break,
+            };
+
+            // Forget connections that have already finished
+            while clients.try_join_next().is_some() {}
+
+            match accepted {
+                Ok((stream, addr)) => {
+                    println!("New connection from: {}", addr);
+
+                    // The server id is only known after the handshake
+                    let connection_id = next_connection_id;
+                    next_connection_id += 1;
+                    connections.lock().unwrap().insert(connection_id, addr);
+                    COUNTERS.connections.fetch_add(1, Ordering::Relaxed);
+                    COUNTERS.open_connections.fetch_add(1, Ordering::Relaxed);
+
+                    let heartbeat = *HEARTBEAT_CONFIG.lock().unwrap();
+                    if let Err(e) = heartbeat::set_keepalive(&stream, heartbeat.keepalive) {
+                        println!("Failed to enable keepalive on connection {}: {}", connection_id, e);
+                    }
+
+                    // Spawn a task to handle this connection
+                    let sender = MESSAGE_CHANNEL.0.lock().unwrap().clone();
+                    let connections = Arc::clone(&connections);
+                    let stop = stop.clone();
+                    let tls = tls.clone();
+                    let mut access = access.clone();
+                    clients.spawn(async move {
+                        match tls {
+                            None => handle_client(connection_id, addr, stream, heartbeat, access, sender, stop).await,
+                            Some(acceptor) => match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => {
+                                    access.certificate = stream
+                                        .get_ref()
+                                        .1
+                                        .peer_certificates()
+                                        .and_then(|chain| chain.first())
+                                        .map(|cert| auth::fingerprint(cert));
+                                    handle_client(connection_id, addr, stream, heartbeat, access, sender, stop).await
+                                }
+                                Ok(Err(e)) => {
+                                    println!("TLS handshake with {} failed: {}", addr, e);
+                                    COUNTERS.rejected.fetch_add(1, Ordering::Relaxed);
+                                }
+                                Err(_) => {
+                                    println!("TLS handshake with {} timed out", addr);
+                                    COUNTERS.rejected.fetch_add(1, Ordering::Relaxed);
+                                }
+                            },
+                        }
+                        connections.lock().unwrap().remove(&connection_id);
+                        COUNTERS.open_connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                },
+                Err(e) => {
+                    // Usually transient (e.g. out of file descriptors), keep serving
+                    println!("Error accepting connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            }
+        }
+
+        // Stop accepting and wait for the open connections to close
+        drop(listener);
+        println!("Stopped accepting connections, closing {} open", clients.len());
+        while clients.join_next().await.is_some() {}
+        if let Some(metrics) = metrics {
+            let _ = metrics.await;
+        }
+    }
+}
+
+async fn throttled_read<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    bucket: Option<&mut TokenBucket>,
+) -> io::Result<usize> {
+    let wait = bucket.map_or(Duration::ZERO, |bucket| bucket.wait(Instant::now()));
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+    reader.read(buffer).await
+}
+
+// Map an identity to its stable server id and tell the listeners about it
+fn identify(identity: &ServerIdentity, sender: &SyncSender<ConnectionEvent>) -> RegisteredServer {
+    let server = SERVER_REGISTRY.lock().unwrap().resolve(identity);
+    queue_event(sender, ConnectionEvent::Identified(server.id, server.label.clone()));
+    server
+}
+
+// Queue an event that mustn't be lost, waiting for room if the writer has
+// fallen behind. Records go through ClientSession::queue_record instead.
+fn queue_event(sender: &SyncSender<ConnectionEvent>, event: ConnectionEvent) {
+    if let Err(TrySendError::Full(event)) = sender.try_send(event) {
+        // Off the async worker, so the other connections keep being served
+        let _ = tokio::task::block_in_place(|| sender.send(event));
+    }
+}
+
+// State of one agent connection
+struct ClientSession {
+    connection_id: u64,
+    peer: SocketAddr,
+    // Agents that never send HELLO speak the legacy line format
+    version: u32,
+    server: Option<RegisteredServer>,
+    // Counts not yet added to FRAME_STATS
+    stats: FrameStats,
+    malformed: u64,
+    sender: SyncSender<ConnectionEvent>,
+    heartbeat: HeartbeatConfig,
+    // The agent's heartbeat interval, None if it doesn't send heartbeats
+    peer_heartbeat: Option<Duration>,
+    heartbeats: HeartbeatSequence,
+    access: Access,
+    // What the agent was let in with, None until its HELLO when checked
+    // against the registry
+    credentials: Option<Credentials>,
+    // Turned away, nothing more is read from the connection
+    rejected: bool,
+    policy: OverloadPolicy,
+    // None without a rate limit
+    bucket: Option<TokenBucket>,
+    // Total dropped from the server when last reported, and when that was
+    reported_drops: (u64, Option<Instant>),
+}
+
+impl ClientSession {
+    // Handle one framed line, returning the reply to send back, if any
+    fn handle_frame(&mut self, frame: Result<String, FrameError>) -> Option<String> {
+        let received = chrono::Utc::now();
+        let line = match frame {
+            Ok(line) => line,
+            Err(e) => {
+                self.stats.record_error(e);
+                self.count_malformed();
+                println!(
+                    "Dropping malformed frame on connection {}: {} ({} malformed so far)",
+                    self.connection_id, e, self.malformed
+                );
+                return None;
+            }
+        };
+
+        if line.is_empty() || self.rejected {
+            return None;
+        }
+        self.stats.frames += 1;
+
+        let frame = parse_frame_at(&line, received);
+        if self.access.agents.is_some() && self.credentials.is_none() && !matches!(frame, Some(Frame::Hello(_))) {
+            self.reject(Rejection::NoHandshake);
+            return None;
+        }
+
+        match frame {
+            Some(Frame::Hello(hello)) => {
+                self.version = hello.version.min(PROTOCOL_VERSION);
+                println!("Connection {} speaks protocol v{}", self.connection_id, self.version);
+
+                // Before the name is trusted with a server id
+                if let Some(agents) = &self.access.agents {
+                    let credentials = Credentials::new(&hello, self.access.certificate.clone());
+                    let authenticated = agents.lock().unwrap().authenticate(&credentials);
+                    if let Err(rejection) = authenticated {
+                        self.reject(rejection);
+                        return None;
+                    }
+                    self.credentials = Some(credentials);
+                }
+
+                match ServerIdentity::from_hello(&hello) {
+                    Some(identity) => {
+                        let server = identify(&identity, &self.sender);
+                        self.assign(server);
+                    }
+                    None => println!(
+                        "Connection {} sent no usable name, using the ids in its lines",
+                        self.connection_id
+                    ),
+                }
+
+                // Acknowledge with the version we agreed to, and our own
+                // heartbeat interval if the agent offered one
+                let mut reply = Hello::new(self.version);
+                let offered = hello.attribute("heartbeat").and_then(|ms| ms.parse::<u64>().ok()).filter(|ms| *ms > 0);
+                if let Some(ms) = offered
+                    && self.heartbeat.enabled()
+                {
+                    self.peer_heartbeat = Some(Duration::from_millis(ms));
+                    reply = reply.with_attribute("heartbeat", &self.heartbeat.interval.as_millis().to_string());
+                }
+                Some(format!("{}\n", reply))
+            }
+            Some(Frame::Heartbeat(heartbeat)) => {
+                let missed = self.heartbeats.receive(heartbeat);
+                if missed > 0 {
+                    println!("Connection {} skipped {} heartbeats before seq {}", self.connection_id, missed, heartbeat.seq);
+                }
+                None
+            }
+            Some(Frame::Metric(record)) if !record.is_timely(received) => {
+                self.stats.out_of_range += 1;
+                self.count_malformed();
+                println!(
+                    "Dropping record stamped {} received at {} on connection {} ({} malformed so far)",
+                    record.timestamp, received, self.connection_id, self.malformed
+                );
+                None
+            }
+            Some(Frame::Metric(record)) => {
+                if self.server.is_none() {
+                    let server = identify(&ServerIdentity::legacy(record.server_id), &self.sender);
+                    self.assign(server);
+                }
+                let Some(server) = &self.server else {
+                    return None;
+                };
+
+                if let Some(bucket) = &mut self.bucket {
+                    match self.policy {
+                        // Paid back by waiting before the next read
+                        OverloadPolicy::SlowDown => bucket.take(Instant::now()),
+                        OverloadPolicy::Drop if !bucket.try_take(Instant::now()) => {
+                            self.stats.rate_limited += 1;
+                            return None;
+                        }
+                        OverloadPolicy::Drop => {}
+                    }
+                }
+
+                let record = LogRecord {
+                    received,
+                    peer: self.peer.to_string(),
+                    connection_id: self.connection_id,
+                    server_id: server.id,
+                    label: server.label.clone(),
+                    payload: line,
+                };
+                self.queue_record(ConnectionEvent::NewMessage(record));
+                None
+            }
+            None => {
+                self.stats.unparseable += 1;
+                self.count_malformed();
+                println!(
+                    "Dropping malformed v{} line on connection {}: {} ({} malformed so far)",
+                    self.version, self.connection_id, line, self.malformed
+                );
+                None
+            }
+        }
+    }
+
+    // Attribute the connection to a server, counting it as one of the
+    // server's connections instead of any it had before
+    fn assign(&mut self, server: RegisteredServer) {
+        if self.server.as_ref().is_some_and(|current| current.id == server.id) {
+            return;
+        }
+
+        let mut liveness = LIVENESS.lock().unwrap();
+        if let Some(previous) = self.server.take()
+            && let Some(state) = liveness.disconnected(previous.id, chrono::Utc::now())
+        {
+            queue_event(&self.sender, ConnectionEvent::Liveness(previous.id, state));
+        }
+        liveness.connected(server.id);
+        self.server = Some(server);
+    }
+
+    fn reject(&mut self, rejection: Rejection) {
+        println!("Rejected connection {} from {}: {}", self.connection_id, self.peer, rejection);
+        COUNTERS.rejected.fetch_add(1, Ordering::Relaxed);
+        self.rejected = true;
+    }
+
+    // Check the credentials the agent was let in with against the registry
+    // as it is now
+    fn reauthorize(&self) -> Result<(), Rejection> {
+        match (&self.access.agents, &self.credentials) {
+            (Some(agents), Some(credentials)) => agents.lock().unwrap().authenticate(credentials),
+            _ => Ok(()),
+        }
+    }
+
+    // Queue a record for the writer. When it has fallen behind, either wait
+    // for room, which stops reading from this agent meanwhile, or drop it.
+    fn queue_record(&mut self, event: ConnectionEvent) {
+        match self.sender.try_send(event) {
+            Err(TrySendError::Full(_)) if self.policy == OverloadPolicy::Drop => {
+                self.stats.queue_full += 1;
+                return;
+            }
+            Err(TrySendError::Full(event)) => queue_event(&self.sender, event),
+            _ => {}
+        }
+        COUNTERS.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Anything received counts as a heartbeat once the server is known
+    fn mark_seen(&self, at: chrono::DateTime<chrono::Utc>) {
+        if let Some(server) = &self.server
+            && let Some(state) = LIVENESS.lock().unwrap().seen(server.id, at)
+        {
+            queue_event(&self.sender, ConnectionEvent::Liveness(server.id, state));
+        }
+    }
+
+    // Counted for the collector right away, for the server once it is known
+    fn count_malformed(&mut self) {
+        self.malformed += 1;
+        COUNTERS.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Add the counts gathered so far to the server's totals once it is known
+    fn flush_stats(&mut self) {
+        if let Some(server) = &self.server {
+            FRAME_STATS
+                .lock()
+                .unwrap()
+                .entry(server.id)
+                .or_default()
+                .merge(&self.stats);
+            self.stats = FrameStats::default();
+        }
+    }
+
+    // Tell about records dropped since the last report, as they happen but
+    // not more often than every few seconds unless `now`
+    fn report_drops(&mut self, now: bool) {
+        let Some(server) = &self.server else {
+            return;
+        };
+        let Some(dropped) = FRAME_STATS.lock().unwrap().get(&server.id).copied() else {
+            return;
+        };
+
+        let (reported, reported_at) = self.reported_drops;
+        if dropped.dropped() > reported && (now || reported_at.is_none_or(|at| at.elapsed() >= DROP_REPORT_INTERVAL)) {
+            println!(
+                "Connection {} dropping records of server {}: {} over its rate limit, {} with the writer behind",
+                self.connection_id, server.id, dropped.rate_limited, dropped.queue_full
+            );
+            queue_event(&self.sender, ConnectionEvent::Dropped(server.id, dropped.dropped()));
+            self.reported_drops = (dropped.dropped(), Some(Instant::now()));
+        }
+    }
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite>(
+    connection_id: u64,
+    peer: SocketAddr,
+    stream: S,
+    heartbeat: HeartbeatConfig,
+    access: Access,
+    sender: SyncSender<ConnectionEvent>,
+    mut stop: watch::Receiver<bool>,
+) {
+    if access.require_certificate && access.certificate.is_none() {
+        println!("Rejected connection {} from {}: {}", connection_id, peer, Rejection::NoCredentials);
+        COUNTERS.rejected.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let backpressure = *BACKPRESSURE_CONFIG.lock().unwrap();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut buffer = [0; 4096];
+    let mut framer = LineFramer::default();
+    let mut session = ClientSession {
+        connection_id,
+        peer,
+        version: LEGACY_VERSION,
+        server: None,
+        stats: FrameStats::default(),
+        malformed: 0,
+        sender,
+        heartbeat,
+        peer_heartbeat: None,
+        heartbeats: HeartbeatSequence::default(),
+        access,
+        credentials: None,
+        rejected: false,
+        policy: backpressure.policy,
+        bucket: backpressure.bucket(),
+        reported_drops: (0, None),
+    };
+    let mut auth_checks = tokio::time::interval(AUTH_CHECK_INTERVAL);
+    // Only ticks once heartbeats were agreed on in the handshake
+    let mut heartbeat_ticks = tokio::time::interval(heartbeat.interval.max(Duration::from_millis(1)));
+    let mut last_received = Instant::now();
+
+    loop {
+        let timeout = session.peer_heartbeat.map(|interval| heartbeat.peer_timeout(interval));
+        let deadline = tokio::time::Instant::from_std(last_received + timeout.unwrap_or_default());
+
+        // Biased so data that already arrived always wins over the dead peer timeout
+        let read = tokio::select! {
+            biased;
+            _ = stopped(&mut stop) => {
+                println!("Closing connection {} for shutdown", connection_id);
+                break;
+            }
+            // Records read ahead of the rate limit are waited out before reading
+            // more, which stalls the agent once the socket buffers are full
+            read = throttled_read(&mut reader, &mut buffer, session.bucket.as_mut()) => read,
+            _ = tokio::time::sleep_until(deadline), if timeout.is_some() => {
+                println!("Connection {} silent for {:?}, closing", connection_id, last_received.elapsed());
+                break;
+            }
+            _ = heartbeat_ticks.tick(), if timeout.is_some() => {
+                // A peer that stopped reading can't hold up the connection task
+                let line = format!("{}\n", session.heartbeats.next_frame());
+                match tokio::time::timeout(timeout.unwrap_or_default(), writer.write_all(line.as_bytes())).await {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(e)) => println!("Error sending heartbeat on connection {}: {}", connection_id, e),
+                    Err(_) => println!("Connection {} stopped reading, closing", connection_id),
+                }
+                break;
+            }
+            _ = auth_checks.tick(), if session.credentials.is_some() => {
+                match session.reauthorize() {
+                    Ok(()) => continue,
+                    Err(rejection) => println!("Closing connection {} from {}: {}", connection_id, peer, rejection),
+                }
+                COUNTERS.rejected.fetch_add(1, Ordering::Relaxed);
+                session.rejected = true;
+                break;
+            }
+        };
+
+        let size = match read {
+            Ok(0) => {
+                // Connection closed
+                println!("Connection {} closed by client", connection_id);
+                break;
+            }
+            Ok(size) => size,
+            Err(e) => {
+                println!("Error reading from connection {}: {}", connection_id, e);
+                break;
+            }
+        };
+        last_received = Instant::now();
+
+        for frame in framer.push(&buffer[..size]) {
+            if let Some(reply) = session.handle_frame(frame)
+                && let Err(e) = writer.write_all(reply.as_bytes()).await
+            {
+                println!("Error answering connection {}: {}", connection_id, e);
+            }
+        }
+        if session.rejected {
+            break;
+        }
+        session.mark_seen(chrono::Utc::now());
+        session.flush_stats();
+        session.report_drops(false);
+    }
+
+    // A clean close, so a TLS agent doesn't take the rejection for a dropped connection
+    if session.rejected {
+        let _ = tokio::time::timeout(Duration::from_secs(1), writer.shutdown()).await;
+    }
+
+    // A last line without a trailing newline
+    if let Some(frame) = framer.finish() {
+        session.handle_frame(frame);
+    }
+    session.flush_stats();
+    session.report_drops(true);
+
+    let malformed = session.stats.malformed();
+    if malformed > 0 {
+        println!("Connection {} never identified, dropped {} malformed frames", connection_id, malformed);
+    }
+
+    if let Some(server) = &session.server {
+        if let Some(stats) = FRAME_STATS.lock().unwrap().get(&server.id)
+            && (stats.malformed() > 0 || stats.dropped() > 0)
+        {
+            println!(
+                "Server {} has sent {} frames, {} malformed ({} too long, {} invalid UTF-8, {} unparseable, {} out of range), {} records dropped ({} over its rate limit, {} with the writer behind)",
+                server.id,
+                stats.frames + stats.too_long + stats.invalid_utf8,
+                stats.malformed(),
+                stats.too_long,
+                stats.invalid_utf8,
+                stats.unparseable,
+                stats.out_of_range,
+                stats.dropped(),
+                stats.rate_limited,
+                stats.queue_full
+            );
+        }
+        // Down only once the server's last connection is gone
+        if let Some(state) = LIVENESS.lock().unwrap().disconnected(server.id, chrono::Utc::now()) {
+            queue_event(&session.sender, ConnectionEvent::Liveness(server.id, state));
+        }
+        queue_event(&session.sender, ConnectionEvent::Disconnected(server.id));
+    }
+}
+
+// Log file for a server's lines today, None when file writing is disabled
+fn log_path(server_id: u32) -> Option<PathBuf> {
+    let config = FILE_WRITER_CONFIG.lock().unwrap();
+
+    if !config.enabled {
+        return None;
+    }
+
+    // Use a simple date format YYYYMMDD
+    let date = chrono::Utc::now().format("%Y%m%d").to_string();
+
+    // Create filename with the server's label and date, labels are checked
+    // when registered but never trusted as a path component
+    let label = Some(server_label(server_id))
+        .filter(|label| is_safe_name(label))
+        .unwrap_or_else(|| format!("server{}", server_id));
+
+    Some(Path::new(&config.directory).join(format!("{}_{}_{}.log", config.file_prefix, date, label)))
+}
+
+// Label of a registered server, or one made from its id
+fn server_label(server_id: u32) -> String {
+    SERVER_REGISTRY
+        .lock()
+        .unwrap()
+        .get(server_id)
+        .map(|s| s.label.clone())
+        .unwrap_or_else(|| format!("server{}", server_id))
+}
+
+// Totals of the collector itself
+pub fn collector_stats() -> CollectorStats {
+    CollectorStats {
+        started: COUNTERS.started,
+        connections: COUNTERS.connections.load(Ordering::Relaxed),
+        open_connections: COUNTERS.open_connections.load(Ordering::Relaxed),
+        rejected: COUNTERS.rejected.load(Ordering::Relaxed),
+        records: COUNTERS.records.load(Ordering::Relaxed),
+        malformed: COUNTERS.malformed.load(Ordering::Relaxed),
+        store_errors: COUNTERS.store_errors.load(Ordering::Relaxed),
+        alerts: COUNTERS.alerts.load(Ordering::Relaxed),
+        subscriber_drops: SUBSCRIBER_DROPS.load(Ordering::Relaxed),
+        untracked_values: COUNTERS.untracked_values.load(Ordering::Relaxed),
+    }
+}
+
+// Latest sample of every metric of every server, by server id and metric
+pub fn latest_values() -> Vec<MetricRecord> {
+    let mut values: Vec<MetricRecord> = LATEST_VALUES.lock().unwrap().values().cloned().collect();
+    values.sort_by(|a, b| (a.server_id, &a.metric).cmp(&(b.server_id, &b.metric)));
+    values
+}
+
+// Totals of the file writer thread
+pub fn writer_stats() -> WriterStats {
+    *WRITER_STATS.lock().unwrap()
+}
+
+// The store the collector appends to, for queries that should include the
+// points it hasn't written yet. None while it isn't running or has no store.
+pub fn store() -> Option<SharedStore> {
+    STORE.lock().unwrap().clone()
+}
+
+// Frames received and rejected so far, per server id
+pub fn frame_stats() -> HashMap<u32, FrameStats> {
+    FRAME_STATS.lock().unwrap().clone()
+}
+
+// Register a live listener for collector events. The receiver is dropped from
+// the subscriber list as soon as it is closed.
+pub fn subscribe() -> EventReceiver<ConnectionEvent> {
+    let (tx, rx) = tokio::sync::mpsc::channel(SUBSCRIBER_CAPACITY);
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+// Forward an event to every live subscriber, pruning the closed ones
+fn publish(event: &ConnectionEvent) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|tx| match tx.try_send(event.clone()) {
+        Ok(()) => true,
+        Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+            SUBSCRIBER_DROPS.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+    });
+}
+
+// Start a message processing thread that writes incoming messages to files
+// and forwards them to the live subscribers, running until `stop` is set and
+// every queued event has been handled
+fn start_file_writer(stop: Arc<AtomicBool>, store: Option<SharedStore>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // Get the receiver from the global channel
+        let receiver = MESSAGE_CHANNEL.1.clone();
+        let mut log_writer = LogWriter::default();
+        let mut last_report = (Instant::now(), WriterStats::default());
+        let mut reported_subscriber_drops = 0;
+        let mut alerts = load_alert_engine();
+        let mut last_store_flush = Instant::now();
+        let mut last_retention_check: Option<Instant> = None;
+
+        loop {
+            // Try to get an event from the receiver
+            let event = match receiver.lock() {
+                Ok(rx) => match rx.recv_timeout(WRITER_POLL) {
+                    Ok(event) => Some(event),
+                    // Producers are gone by the time stop is set, so an idle
+                    // channel means it has been drained
+                    Err(RecvTimeoutError::Timeout) if stop.load(Ordering::SeqCst) => break,
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break, // Channel closed
+                },
+                Err(_) => {
+                    println!("Failed to lock receiver");
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            };
+
+            if let Some(event) = event {
+                publish(&event);
+
+                match event {
+                    ConnectionEvent::NewMessage(record) => {
+                        // Write the record to file
+                        if let Some(path) = log_path(record.server_id)
+                            && let Err(e) = log_writer.write(record.server_id, &path, &record.to_string())
+                        {
+                            eprintln!("Error writing to file {}: {}", path.display(), e);
+                        }
+
+                        let metric = record.metric();
+                        if let Some(store) = &store
+                            && let Some(metric) = &metric
+                            && let Err(e) = {
+                                let mut store = store.lock().unwrap();
+                                store
+                                    .set_unit(metric.server_id, &metric.metric, &metric.unit)
+                                    .and_then(|()| store.append(metric.server_id, &metric.metric, metric.timestamp, metric.value))
+                            }
+                        {
+                            eprintln!("Error writing to store: {}", e);
+                            COUNTERS.store_errors.fetch_add(1, Ordering::Relaxed);
+                        }
+
+                        if let Some(metric) = &metric {
+                            let mut latest = LATEST_VALUES.lock().unwrap();
+                            let key = (metric.server_id, metric.metric.clone());
+                            if latest.len() < MAX_LATEST_VALUES || latest.contains_key(&key) {
+                                latest.insert(key, metric.clone());
+                            } else if COUNTERS.untracked_values.fetch_add(1, Ordering::Relaxed) == 0 {
+                                eprintln!(
+                                    "Keeping the latest values of {} series, further ones are left out of the metrics endpoint",
+                                    MAX_LATEST_VALUES
+                                );
+                            }
+                            drop(latest);
+
+                            let events = alerts.evaluate(metric.server_id, &record.label, &metric.metric, metric.value, metric.timestamp);
+                            for event in events {
+                                COUNTERS.alerts.fetch_add(1, Ordering::Relaxed);
+                                log_alert(&event);
+                                publish(&ConnectionEvent::Alert(event));
+                            }
+                        }
+                    },
+                    ConnectionEvent::Identified(id, label) => {
+                        println!("Server {} is {}", id, label);
+                    }
+                    ConnectionEvent::Disconnected(id) => {
+                        println!("Client {} disconnected", id);
+                        if let Err(e) = log_writer.close(id) {
+                            eprintln!("Error closing log of server {}: {}", id, e);
+                        }
+                    }
+                    ConnectionEvent::Liveness(id, state) => {
+                        println!("Server {} is now {}", id, state);
+
+                        // Nothing will resolve what was firing for it
+                        if state == Liveness::Down {
+                            for event in alerts.server_down(id, chrono::Utc::now()) {
+                                COUNTERS.alerts.fetch_add(1, Ordering::Relaxed);
+                                log_alert(&event);
+                                publish(&ConnectionEvent::Alert(event));
+                            }
+                        }
+                    }
+                    // Logged by the connection, only the subscribers need these
+                    ConnectionEvent::Alert(_) | ConnectionEvent::Dropped(..) => {}
+                }
+            }
+
+            if let Err(e) = log_writer.flush_if_due() {
+                eprintln!("Error flushing log files: {}", e);
+            }
+            let stats = log_writer.stats();
+            *WRITER_STATS.lock().unwrap() = stats;
+
+            if let Some(store) = &store {
+                let mut store = store.lock().unwrap();
+                // Blocks are written as they fill, this only catches slow series
+                if last_store_flush.elapsed() >= writer::FLUSH_INTERVAL {
+                    if let Err(e) = store.flush_due() {
+                        eprintln!("Error flushing store: {}", e);
+                    }
+                    last_store_flush = Instant::now();
+                }
+
+                if last_retention_check.is_none_or(|last| last.elapsed() >= RETENTION_CHECK_INTERVAL) {
+                    match store.enforce_retention(chrono::Utc::now()) {
+                        Ok(0) => {}
+                        Ok(removed) => println!("Store: removed {} expired segments", removed),
+                        Err(e) => eprintln!("Error removing expired store segments: {}", e),
+                    }
+                    last_retention_check = Some(Instant::now());
+                }
+            }
+
+            // Periodic throughput summary
+            let (reported_at, reported) = last_report;
+            let elapsed = reported_at.elapsed();
+            if elapsed >= WRITER_REPORT_INTERVAL {
+                let lines = stats.lines - reported.lines;
+                println!(
+                    "File writer: {} lines ({:.1}/s), {} bytes, {} files open",
+                    lines,
+                    lines as f64 / elapsed.as_secs_f64(),
+                    stats.bytes - reported.bytes,
+                    stats.open_files
+                );
+                last_report = (Instant::now(), stats);
+
+                let subscriber_drops = SUBSCRIBER_DROPS.load(Ordering::Relaxed);
+                if subscriber_drops > reported_subscriber_drops {
+                    println!(
+                        "Live subscribers fell behind and missed {} events",
+                        subscriber_drops - reported_subscriber_drops
+                    );
+                    reported_subscriber_drops = subscriber_drops;
+                }
+            }
+        }
+
+        if let Err(e) = log_writer.flush_all() {
+            eprintln!("Error flushing log files: {}", e);
+        }
+        if let Some(store) = &store
+            && let Err(e) = store.lock().unwrap().close()
+        {
+            eprintln!("Error flushing store: {}", e);
+        }
+        *STORE.lock().unwrap() = None;
+        let stats = log_writer.stats();
+        *WRITER_STATS.lock().unwrap() = stats;
+        // Nothing is published after this, closing the subscriptions ends their streams
+        SUBSCRIBERS.lock().unwrap().clear();
+        println!("File writer stopped after {} lines ({} bytes)", stats.lines, stats.bytes);
+    })
+}
+
+// The store in the log directory, None if disabled or it can't be opened
+fn open_store() -> Option<SharedStore> {
+    let config = FILE_WRITER_CONFIG.lock().unwrap();
+    let store_config = config.store.filter(|_| config.enabled)?;
+    let directory = Path::new(&config.directory).join(STORE_DIRECTORY);
+
+    match Store::open(&directory, store_config) {
+        Ok(store) => Some(Arc::new(Mutex::new(store))),
+        Err(e) => {
+            eprintln!("Failed to open store in {}: {}", directory.display(), e);
+            None
+        }
+    }
+}
+
+// Rules of the alerts the collector raises, none if the file is missing or invalid
+fn load_alert_engine() -> AlertEngine {
+    let path = FILE_WRITER_CONFIG.lock().unwrap().alert_rules.clone();
+
+    match load_rules(&path) {
+        Ok(rules) => {
+            if !rules.is_empty() {
+                println!("Loaded {} alert rules from {}", rules.len(), path.display());
+            }
+            AlertEngine::new(rules)
+        }
+        Err(e) => {
+            eprintln!("Failed to load alert rules: {}", e);
+            AlertEngine::default()
+        }
+    }
+}
+
+// Sinks the collector delivers alerts to, none if the file is missing or invalid
+fn load_alert_sinks() -> Vec<SinkConfig> {
+    let path = FILE_WRITER_CONFIG.lock().unwrap().alert_rules.clone();
+
+    match load_sinks(&path) {
+        Ok(sinks) => sinks,
+        Err(e) => {
+            eprintln!("Failed to load alert sinks: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Hand the alerts of the event stream to the sinks until the stream ends,
+// then wait for the sinks to finish delivering
+fn start_notifier(sinks: Vec<SinkConfig>, mut events: EventReceiver<ConnectionEvent>) -> thread::JoinHandle<()> {
+    println!("Delivering alerts to {} sinks", sinks.len());
+
+    thread::spawn(move || {
+        let notifier = Notifier::new(sinks);
+        while let Some(event) = events.blocking_recv() {
+            if let ConnectionEvent::Alert(alert) = event {
+                notifier.notify(&alert);
+            }
+        }
+        notifier.close();
+    })
+}
+
+// Append to the alert log, alerts are rare enough to open it each time
+fn log_alert(event: &AlertEvent) {
+    println!("Alert: {}", event);
+
+    let config = FILE_WRITER_CONFIG.lock().unwrap();
+    if !config.enabled {
+        return;
+    }
+    let path = Path::new(&config.directory).join(ALERT_LOG_FILE);
+    drop(config);
+
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", event));
+    if let Err(e) = result {
+        eprintln!("Error writing to {}: {}", path.display(), e);
+    }
+}
+
+// Where the collector reads its alert rules from
+pub fn configure_alerts(rules_path: &str) {
+    FILE_WRITER_CONFIG.lock().unwrap().alert_rules = PathBuf::from(rules_path);
+}
+
+// Keep metrics in the store as well as the text logs, None to disable it
+pub fn configure_store(config: Option<StoreConfig>) {
+    FILE_WRITER_CONFIG.lock().unwrap().store = config;
+}
+
+// How long a server may stay quiet before it is stale and down
+pub fn configure_liveness(config: LivenessConfig) {
+    *LIVENESS.lock().unwrap() = LivenessTracker::new(config);
+}
+
+// State of a server as the collector sees it, None if it was never heard from
+pub fn liveness(server_id: u32) -> Option<Liveness> {
+    LIVENESS.lock().unwrap().state(server_id)
+}
+
+// Serve agents TLS with this certificate and key, None for plaintext. Takes
+// effect for servers initialized afterwards.
+pub fn configure_tls(config: Option<ServerTls>) {
+    *TLS_CONFIG.lock().unwrap() = config;
+}
+
+// Accept only the agents listed in this registry file, None accepts any.
+// Takes effect for servers initialized afterwards.
+pub fn configure_agents(path: Option<PathBuf>) {
+    *AGENTS_FILE.lock().unwrap() = path;
+}
+
+// Serve the collector's metrics in the Prometheus format at
+// http://<address>/metrics, None to not serve them. Takes effect for servers
+// initialized afterwards.
+pub fn configure_metrics_endpoint(address: Option<String>) {
+    *METRICS_ADDRESS.lock().unwrap() = address;
+}
+
+// Rate limit and overload policy of new connections
+pub fn configure_backpressure(config: BackpressureConfig) {
+    *BACKPRESSURE_CONFIG.lock().unwrap() = config;
+}
+
+// Heartbeat interval, dead peer timeout and keepalive of new connections
+pub fn configure_heartbeat(config: HeartbeatConfig) {
+    *HEARTBEAT_CONFIG.lock().unwrap() = config;
+}
+
+// Configure file writing settings
+pub fn configure_file_writer(enabled: bool, directory: &str, file_prefix: &str) {
+    let mut config = FILE_WRITER_CONFIG.lock().unwrap();
+    config.enabled = enabled;
+    config.directory = directory.to_string();
+    config.file_prefix = file_prefix.to_string();
+}
+
+// Initialize the server - returns a handle that stops the server, file writer and alert sink threads
+pub fn initialize_server(address: &str) -> io::Result<ShutdownHandle> {
+    // Create the server first so a bad address doesn't leave a writer running
+    let server = ServerMonitor::new(address)?;
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let writer_stop = Arc::new(AtomicBool::new(false));
+
+    // Subscribe before the writer starts so no alert is missed
+    let sinks = load_alert_sinks();
+    let notifier_handle = (!sinks.is_empty()).then(|| start_notifier(sinks, subscribe()));
+
+    // Start the file writer thread
+    let store = open_store();
+    *STORE.lock().unwrap() = store.clone();
+    let file_writer_handle = start_file_writer(Arc::clone(&writer_stop), store);
+
+    // Start the server
+    let server_handle = thread::spawn(move || {
+        server.start(stop_rx);
+    });
+
+    Ok(ShutdownHandle::new(stop_tx, writer_stop, server_handle, file_writer_handle, notifier_handle))
+}
@@ -0,0 +1,313 @@
+use crate::protocol::{sanitize_token, Hello};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Stored next to the logs so ids survive collector restarts
+pub const REGISTRY_FILE: &str = "servers.toml";
+
+/// Identity an agent announces in its HELLO line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerIdentity {
+    pub name: String,
+    pub uuid: Option<String>,
+}
+
+impl ServerIdentity {
+    /// None without a name, or with one that isn't safe to use in a file
+    /// name.
+    pub fn from_hello(hello: &Hello) -> Option<Self> {
+        let name = sanitize_token(hello.attribute("name")?);
+        if !is_safe_name(&name) {
+            return None;
+        }
+
+        Some(Self {
+            name,
+            uuid: hello.attribute("uuid").map(str::to_string),
+        })
+    }
+
+    // Agents without a handshake are known only by the id in their lines
+    pub fn legacy(server_id: u32) -> Self {
+        Self {
+            name: format!("server{}", server_id),
+            uuid: None,
+        }
+    }
+}
+
+/// Whether `name` is made only of `[A-Za-z0-9._-]` and isn't `.` or `..`,
+/// so it can be used as part of a log file name.
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains("..")
+        && name != "."
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredServer {
+    pub id: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    // Unique name used for log files and GUI labels
+    pub label: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default, rename = "server")]
+    servers: Vec<RegisteredServer>,
+}
+
+/// Maps agent identities to stable numeric ids.
+#[derive(Debug, Default)]
+pub struct ServerRegistry {
+    path: Option<PathBuf>,
+    servers: Vec<RegisteredServer>,
+    // Entries with unusable labels, kept in the file and their ids reserved
+    ignored: Vec<RegisteredServer>,
+}
+
+impl ServerRegistry {
+    /// Load the registry from `path`, starting empty if it doesn't exist yet.
+    /// An unreadable file is moved aside to `<path>.bad` before anything new
+    /// is saved over it.
+    pub fn load(path: &Path) -> Self {
+        Self::read(path, true)
+    }
+
+    /// Load the registry of another process, e.g. the collector's for its
+    /// labels, without ever writing to it.
+    pub fn load_read_only(path: &Path) -> Self {
+        Self::read(path, false)
+    }
+
+    fn read(path: &Path, mut writable: bool) -> Self {
+        let servers = match fs::read_to_string(path) {
+            Ok(contents) => match toml::from_str::<RegistryFile>(&contents) {
+                Ok(file) => file.servers,
+                Err(e) if !writable => {
+                    eprintln!("Ignoring unreadable server registry {}: {}", path.display(), e);
+                    Vec::new()
+                }
+                Err(e) => {
+                    let backup = path.with_extension("toml.bad");
+                    eprintln!(
+                        "Ignoring unreadable server registry {}, moving it to {}: {}",
+                        path.display(),
+                        backup.display(),
+                        e
+                    );
+                    if let Err(e) = fs::rename(path, &backup) {
+                        eprintln!("Not saving the server registry, it couldn't be moved: {}", e);
+                        writable = false;
+                    }
+                    Vec::new()
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+
+        // The file may have been edited by hand, and labels end up in paths
+        let (servers, unsafe_labels): (Vec<_>, Vec<_>) =
+            servers.into_iter().partition(|s: &RegisteredServer| is_safe_name(&s.label));
+        for server in &unsafe_labels {
+            eprintln!(
+                "Ignoring server {} in {}, its label {:?} can't be used in a file name",
+                server.id,
+                path.display(),
+                server.label
+            );
+        }
+
+        Self {
+            path: writable.then(|| path.to_path_buf()),
+            servers,
+            ignored: unsafe_labels,
+        }
+    }
+
+    pub fn servers(&self) -> &[RegisteredServer] {
+        &self.servers
+    }
+
+    pub fn get(&self, id: u32) -> Option<&RegisteredServer> {
+        self.servers.iter().find(|s| s.id == id)
+    }
+
+    /// Return the registered server for `identity`, assigning a new id the
+    /// first time it is seen.
+    pub fn resolve(&mut self, identity: &ServerIdentity) -> RegisteredServer {
+        let position = self.servers.iter().position(|s| match &identity.uuid {
+            Some(uuid) => s.uuid.as_ref() == Some(uuid),
+            None => s.uuid.is_none() && s.name == identity.name,
+        });
+
+        let server = match position {
+            Some(index) if self.servers[index].name == identity.name => return self.servers[index].clone(),
+            Some(index) => {
+                // Same agent under a new name
+                let label = self.unique_label(&identity.name, self.servers[index].id);
+                let server = &mut self.servers[index];
+                server.name = identity.name.clone();
+                server.label = label;
+                server.clone()
+            }
+            None => {
+                let id = self.servers.iter().chain(&self.ignored).map(|s| s.id + 1).max().unwrap_or(0);
+                let server = RegisteredServer {
+                    id,
+                    name: identity.name.clone(),
+                    uuid: identity.uuid.clone(),
+                    label: self.unique_label(&identity.name, id),
+                };
+                self.servers.push(server.clone());
+                server
+            }
+        };
+
+        if let Err(e) = self.save() {
+            eprintln!("Failed to save server registry: {}", e);
+        }
+
+        server
+    }
+
+    fn unique_label(&self, name: &str, id: u32) -> String {
+        if self.servers.iter().any(|s| s.id != id && s.label == name) {
+            format!("{}-{}", name, id)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut servers = self.servers.clone();
+        servers.extend(self.ignored.iter().cloned());
+        servers.sort_by_key(|s| s.id);
+        let contents = toml::to_string(&RegistryFile { servers })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write then rename so a crash never leaves a half written registry
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-registry-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn identity(name: &str) -> ServerIdentity {
+        ServerIdentity {
+            name: name.to_string(),
+            uuid: None,
+        }
+    }
+
+    #[test]
+    fn names_that_would_escape_the_log_directory_are_refused() {
+        for name in ["web-1", "db.internal", "host_2"] {
+            assert!(is_safe_name(name), "{}", name);
+        }
+        for name in ["", ".", "..", "/../../tmp/x", "a/b", "a\\b", "x..y", "web 1"] {
+            assert!(!is_safe_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn hello_with_a_path_as_name_gives_no_identity() {
+        let hello = Hello::new(2).with_attribute("name", "/../../../tmp/x");
+        assert_eq!(ServerIdentity::from_hello(&hello), None);
+
+        let hello = Hello::new(2).with_attribute("name", "web-1");
+        assert_eq!(ServerIdentity::from_hello(&hello).map(|i| i.name), Some(String::from("web-1")));
+    }
+
+    #[test]
+    fn ids_survive_a_reload() {
+        let dir = TempDir::new("reload");
+        let path = dir.0.join(REGISTRY_FILE);
+
+        let mut registry = ServerRegistry::load(&path);
+        assert_eq!(registry.resolve(&identity("web-1")).id, 0);
+        assert_eq!(registry.resolve(&identity("web-2")).id, 1);
+        assert!(!path.with_extension("tmp").exists());
+
+        let mut registry = ServerRegistry::load(&path);
+        assert_eq!(registry.resolve(&identity("web-2")).id, 1);
+        assert_eq!(registry.resolve(&identity("web-3")).id, 2);
+    }
+
+    #[test]
+    fn an_unreadable_registry_is_moved_aside_before_saving() {
+        let dir = TempDir::new("unreadable");
+        let path = dir.0.join(REGISTRY_FILE);
+        fs::write(&path, "[[server]\nid = 0").unwrap();
+
+        let mut registry = ServerRegistry::load(&path);
+        assert_eq!(registry.servers(), []);
+        registry.resolve(&identity("web-1"));
+
+        assert_eq!(fs::read_to_string(path.with_extension("toml.bad")).unwrap(), "[[server]\nid = 0");
+        assert_eq!(ServerRegistry::load(&path).servers().len(), 1);
+    }
+
+    #[test]
+    fn read_only_registries_are_never_written() {
+        let dir = TempDir::new("read-only");
+        let path = dir.0.join(REGISTRY_FILE);
+        fs::write(&path, "[[server]\nid = 0").unwrap();
+
+        let mut registry = ServerRegistry::load_read_only(&path);
+        registry.resolve(&identity("web-1"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[[server]\nid = 0");
+        assert!(!path.with_extension("toml.bad").exists());
+    }
+
+    #[test]
+    fn servers_with_unsafe_labels_keep_their_ids() {
+        let dir = TempDir::new("unsafe-label");
+        let path = dir.0.join(REGISTRY_FILE);
+        fs::write(&path, "[[server]]\nid = 4\nname = \"x\"\nlabel = \"../x\"\n").unwrap();
+
+        let mut registry = ServerRegistry::load(&path);
+        assert_eq!(registry.servers(), []);
+        assert_eq!(registry.resolve(&identity("web-1")).id, 5);
+
+        // Still in the file for someone to fix by hand
+        let contents = fs::read_to_string(&path).unwrap();
+        let file: RegistryFile = toml::from_str(&contents).unwrap();
+        assert_eq!(file.servers.iter().map(|s| s.id).collect::<Vec<_>>(), [4, 5]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tokio::sync::watch;
+
+/// Stops a collector started by `initialize_server`.
+///
+/// Shutting down stops accepting, closes the agent connections, lets the
+/// file writer drain every queued event, waits for the alert sinks to deliver
+/// what they were given and joins the threads. Dropping the
+/// handle instead leaves the collector running for the life of the process.
+pub struct ShutdownHandle {
+    stop: watch::Sender<bool>,
+    writer_stop: Arc<AtomicBool>,
+    server_handle: thread::JoinHandle<()>,
+    file_writer_handle: thread::JoinHandle<()>,
+    notifier_handle: Option<thread::JoinHandle<()>>, // None without alert sinks
+}
+
+impl ShutdownHandle {
+    pub(super) fn new(
+        stop: watch::Sender<bool>,
+        writer_stop: Arc<AtomicBool>,
+        server_handle: thread::JoinHandle<()>,
+        file_writer_handle: thread::JoinHandle<()>,
+        notifier_handle: Option<thread::JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            stop,
+            writer_stop,
+            server_handle,
+            file_writer_handle,
+            notifier_handle,
+        }
+    }
+
+    pub fn shutdown(self) {
+        println!("Stopping collector...");
+
+        // The server thread ends once every connection task has finished, so
+        // nothing can be added to the channel after it is joined
+        let _ = self.stop.send(true);
+        if self.server_handle.join().is_err() {
+            eprintln!("Server thread panicked");
+        }
+
+        self.writer_stop.store(true, Ordering::SeqCst);
+        if self.file_writer_handle.join().is_err() {
+            eprintln!("File writer thread panicked");
+        }
+
+        // The writer closed the event stream, so the sinks only have their queues left
+        if let Some(handle) = self.notifier_handle
+            && handle.join().is_err()
+        {
+            eprintln!("Alert sink thread panicked");
+        }
+
+        println!("Collector stopped");
+    }
+
+    /// Block until SIGINT or SIGTERM, then shut down.
+    pub fn wait_for_signal(self) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        ctrlc::set_handler(move || {
+            let _ = tx.send(());
+        })
+        .map_err(io::Error::other)?;
+
+        let _ = rx.recv();
+        println!("\nShutdown signal received. Closing connections...");
+        self.shutdown();
+
+        Ok(())
+    }
+}
+
+// Resolves once shutdown is requested. A dropped handle never stops the collector.
+pub(super) async fn stopped(stop: &mut watch::Receiver<bool>) {
+    if stop.wait_for(|stopped| *stopped).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Flush buffered lines at least this often...
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+// ...or as soon as this many bytes are waiting for one file
+pub const FLUSH_BYTES: usize = 64 * 1024;
+
+/// Running totals of what the writer has done.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriterStats {
+    pub lines: u64,
+    pub bytes: u64,
+    pub flushes: u64,
+    pub rotations: u64,
+    pub errors: u64,
+    pub open_files: usize,
+}
+
+struct OpenLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    pending: usize,
+}
+
+/// Keeps one buffered append handle per server.
+///
+/// The caller passes the path for every line; when it changes (new day at
+/// midnight, a renamed server, a new directory) the old file is flushed and
+/// closed and the new one opened.
+#[derive(Default)]
+pub struct LogWriter {
+    files: HashMap<u32, OpenLog>,
+    last_flush: Option<Instant>,
+    stats: WriterStats,
+}
+
+impl LogWriter {
+    pub fn write(&mut self, server_id: u32, path: &Path, line: &str) -> io::Result<()> {
+        let result = self.write_line(server_id, path, line);
+        if result.is_err() {
+            self.stats.errors += 1;
+        }
+        result
+    }
+
+    fn write_line(&mut self, server_id: u32, path: &Path, line: &str) -> io::Result<()> {
+        if let Some(log) = self.files.get(&server_id)
+            && log.path != path
+        {
+            self.close(server_id)?;
+            self.stats.rotations += 1;
+        }
+
+        let log = match self.files.entry(server_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                entry.insert(OpenLog {
+                    path: path.to_path_buf(),
+                    writer: BufWriter::with_capacity(FLUSH_BYTES, file),
+                    pending: 0,
+                })
+            }
+        };
+
+        log.writer.write_all(line.as_bytes())?;
+        log.writer.write_all(b"\n")?;
+        log.pending += line.len() + 1;
+        self.stats.lines += 1;
+        self.stats.bytes += line.len() as u64 + 1;
+
+        if log.pending >= FLUSH_BYTES {
+            log.writer.flush()?;
+            log.pending = 0;
+            self.stats.flushes += 1;
+        }
+        self.stats.open_files = self.files.len();
+
+        Ok(())
+    }
+
+    /// Flush every file if the interval has passed since the last flush.
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        let due = self
+            .last_flush
+            .is_none_or(|last| last.elapsed() >= FLUSH_INTERVAL);
+        if due {
+            self.flush_all()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush_all(&mut self) -> io::Result<()> {
+        self.last_flush = Some(Instant::now());
+        let mut result = Ok(());
+
+        for log in self.files.values_mut() {
+            if log.pending == 0 {
+                continue;
+            }
+            match log.writer.flush() {
+                Ok(()) => {
+                    log.pending = 0;
+                    self.stats.flushes += 1;
+                }
+                Err(e) => {
+                    self.stats.errors += 1;
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Flush and close the file of one server, e.g. when it disconnects.
+    pub fn close(&mut self, server_id: u32) -> io::Result<()> {
+        let result = match self.files.remove(&server_id) {
+            Some(mut log) if log.pending > 0 => {
+                self.stats.flushes += 1;
+                log.writer.flush()
+            }
+            _ => Ok(()),
+        };
+        self.stats.open_files = self.files.len();
+        result
+    }
+
+    pub fn stats(&self) -> WriterStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-writer-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn on_disk(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn lines_wait_for_the_flush_interval() {
+        let dir = TempDir::new("interval");
+        let path = dir.0.join("server0_2025-03-17.log");
+        let mut writer = LogWriter::default();
+        writer.flush_all().unwrap();
+
+        writer.write(0, &path, "first").unwrap();
+        writer.flush_if_due().unwrap();
+        assert_eq!(on_disk(&path), "");
+
+        writer.last_flush = Some(Instant::now() - FLUSH_INTERVAL);
+        writer.flush_if_due().unwrap();
+        assert_eq!(on_disk(&path), "first\n");
+        assert_eq!(writer.stats().flushes, 1);
+    }
+
+    #[test]
+    fn a_full_buffer_is_flushed_right_away() {
+        let dir = TempDir::new("size");
+        let path = dir.0.join("server0_2025-03-17.log");
+        let mut writer = LogWriter::default();
+        let line = "x".repeat(1023);
+
+        for _ in 0..FLUSH_BYTES / 1024 - 1 {
+            writer.write(0, &path, &line).unwrap();
+        }
+        assert_eq!(on_disk(&path).len(), 0);
+
+        writer.write(0, &path, &line).unwrap();
+        assert_eq!(on_disk(&path).len(), FLUSH_BYTES);
+        assert_eq!(writer.stats().flushes, 1);
+    }
+
+    #[test]
+    fn a_new_path_closes_the_previous_day() {
+        let dir = TempDir::new("rotation");
+        let before = dir.0.join("server0_2025-03-17.log");
+        let after = dir.0.join("server0_2025-03-18.log");
+        let mut writer = LogWriter::default();
+
+        writer.write(0, &before, "23:59:59").unwrap();
+        writer.write(1, &dir.0.join("server1_2025-03-17.log"), "other").unwrap();
+        writer.write(0, &after, "00:00:00").unwrap();
+
+        // The old file is complete as soon as the new one is opened
+        assert_eq!(on_disk(&before), "23:59:59\n");
+        writer.close(0).unwrap();
+        assert_eq!(on_disk(&after), "00:00:00\n");
+
+        let stats = writer.stats();
+        assert_eq!((stats.lines, stats.bytes), (3, 24));
+        assert_eq!((stats.rotations, stats.open_files, stats.errors), (1, 1, 0));
+    }
+
+    #[test]
+    fn failed_opens_are_counted() {
+        let dir = TempDir::new("errors");
+        fs::create_dir_all(&dir.0).unwrap();
+        let mut writer = LogWriter::default();
+
+        // A directory where the file should be
+        assert!(writer.write(0, &dir.0, "line").is_err());
+        assert_eq!(writer.stats().errors, 1);
+        assert_eq!(writer.stats().lines, 0);
+    }
+}
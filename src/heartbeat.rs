@@ -0,0 +1,189 @@
+//! Dead peer detection on agent connections, shared by both ends.
+//!
+//! TCP keepalive catches peers whose host went away, the heartbeat frames of
+//! [`crate::protocol::Heartbeat`] catch the ones that still hold the socket
+//! open but stopped talking.
+
+use crate::protocol::Heartbeat;
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+// Unanswered keepalive probes before the kernel drops the connection
+const KEEPALIVE_PROBES: u32 = 3;
+
+pub const HEARTBEAT_INTERVAL_ENV: &str = "SRD_HEARTBEAT_INTERVAL_MS";
+pub const PEER_TIMEOUT_ENV: &str = "SRD_PEER_TIMEOUT_MS";
+pub const KEEPALIVE_ENV: &str = "SRD_KEEPALIVE_SECS";
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    // Between heartbeats sent, zero disables them
+    pub interval: Duration,
+    // Silence after which the peer is given up on
+    pub timeout: Duration,
+    // Idle time before the first keepalive probe, zero disables keepalive
+    pub keepalive: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+            keepalive: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// A timeout no longer than the interval would give up on every peer
+    /// between two heartbeats.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.enabled() && self.timeout <= self.interval {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the peer timeout ({:?}) has to be longer than the heartbeat interval ({:?})",
+                    self.timeout, self.interval
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// How long to wait for a peer that sends a heartbeat every
+    /// `peer_interval`. Never less than three of its intervals, so a peer
+    /// configured slower than us isn't dropped between two heartbeats.
+    pub fn peer_timeout(&self, peer_interval: Duration) -> Duration {
+        self.timeout.max(peer_interval.saturating_mul(3))
+    }
+
+    /// From `SRD_HEARTBEAT_INTERVAL_MS`, `SRD_PEER_TIMEOUT_MS` and
+    /// `SRD_KEEPALIVE_SECS`, the defaults for those not set.
+    pub fn from_env() -> io::Result<Self> {
+        let defaults = Self::default();
+        let number = |name: &str| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}={}: {}", name, value, e))),
+            Err(_) => Ok(None),
+        };
+
+        let config = Self {
+            interval: number(HEARTBEAT_INTERVAL_ENV)?.map_or(defaults.interval, Duration::from_millis),
+            timeout: number(PEER_TIMEOUT_ENV)?.map_or(defaults.timeout, Duration::from_millis),
+            keepalive: number(KEEPALIVE_ENV)?.map_or(defaults.keepalive, Duration::from_secs),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Turn on TCP keepalive with the configured idle time.
+pub fn set_keepalive<S: AsFd>(socket: &S, idle: Duration) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+    if idle.is_zero() {
+        return socket.set_keepalive(false);
+    }
+
+    let probe_interval = (idle / KEEPALIVE_PROBES).max(Duration::from_secs(1));
+    let keepalive = TcpKeepalive::new()
+        .with_time(idle)
+        .with_interval(probe_interval)
+        .with_retries(KEEPALIVE_PROBES);
+    socket.set_tcp_keepalive(&keepalive)
+}
+
+/// Numbers the heartbeats of one connection and checks the peer's.
+///
+/// Numbers wrap around, a heartbeat counts as newer than the last one when
+/// it is less than half the number range ahead of it.
+#[derive(Debug, Default)]
+pub struct HeartbeatSequence {
+    next: u64,
+    last_received: Option<u64>,
+}
+
+impl HeartbeatSequence {
+    pub fn next_frame(&mut self) -> Heartbeat {
+        let heartbeat = Heartbeat { seq: self.next };
+        self.next = self.next.wrapping_add(1);
+        heartbeat
+    }
+
+    /// Record a heartbeat of the peer, returning how many were skipped
+    /// since the last one. Stale or repeated numbers count as none.
+    pub fn receive(&mut self, heartbeat: Heartbeat) -> u64 {
+        let expected = self.last_received.map_or(0, |last| last.wrapping_add(1));
+        let skipped = heartbeat.seq.wrapping_sub(expected);
+        if self.last_received.is_some() && skipped > u64::MAX / 2 {
+            return 0;
+        }
+
+        self.last_received = Some(heartbeat.seq);
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive_all(sequence: &mut HeartbeatSequence, numbers: &[u64]) -> Vec<u64> {
+        numbers.iter().map(|&seq| sequence.receive(Heartbeat { seq })).collect()
+    }
+
+    #[test]
+    fn skipped_heartbeats_are_counted_and_stale_ones_ignored() {
+        let mut sequence = HeartbeatSequence::default();
+        assert_eq!(receive_all(&mut sequence, &[0, 1, 4, 5, 3, 5, 9]), [0, 0, 2, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn numbers_wrap_around() {
+        let mut sender = HeartbeatSequence {
+            next: u64::MAX - 1,
+            last_received: None,
+        };
+        let sent: Vec<u64> = (0..3).map(|_| sender.next_frame().seq).collect();
+        assert_eq!(sent, [u64::MAX - 1, u64::MAX, 0]);
+
+        let mut receiver = HeartbeatSequence {
+            next: 0,
+            last_received: Some(u64::MAX - 2),
+        };
+        assert_eq!(receive_all(&mut receiver, &[u64::MAX, 1, u64::MAX, 2]), [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn the_peer_timeout_covers_three_of_its_intervals() {
+        let config = HeartbeatConfig::default();
+        assert_eq!(config.peer_timeout(Duration::from_secs(1)), config.timeout);
+        assert_eq!(config.peer_timeout(Duration::from_secs(10)), Duration::from_secs(30));
+        assert_eq!(config.peer_timeout(Duration::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn a_timeout_within_one_interval_is_rejected() {
+        let config = HeartbeatConfig {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(5),
+            ..HeartbeatConfig::default()
+        };
+        assert_eq!(config.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // Without heartbeats there is nothing to time out
+        let disabled = HeartbeatConfig {
+            interval: Duration::ZERO,
+            ..config
+        };
+        disabled.validate().unwrap();
+    }
+}
@@ -0,0 +1,8 @@
+pub mod agent;
+pub mod alerts;
+pub mod gui_connection;
+pub mod heartbeat;
+pub mod protocol;
+pub mod store;
+pub mod stressapp;
+pub mod tls;
@@ -1,39 +1,331 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use iced::{Element, Subscription, Task};
-use stressapp::message::AppMessage;
-use stressapp::monitor_chart::MonitorChart;
+use chrono::{NaiveDate, Utc};
+use iced::futures::{SinkExt, Stream};
+use iced::widget::{button, slider, text_input, Row, Text};
+use iced::{stream, Element, Subscription, Task};
+use server_remote_dash::alerts::rules::{load_rules, AlertRule, RULES_FILE};
+use server_remote_dash::gui_connection::auth::AGENTS_FILE_ENV;
+use server_remote_dash::gui_connection::backpressure::BackpressureConfig;
+use server_remote_dash::gui_connection::exporter::METRICS_LISTEN_ENV;
+use server_remote_dash::gui_connection::liveness::LivenessConfig;
+use server_remote_dash::gui_connection::shutdown::ShutdownHandle;
+use server_remote_dash::gui_connection::{self, ConnectionEvent};
+use server_remote_dash::heartbeat::HeartbeatConfig;
+use server_remote_dash::protocol::MetricRecord;
+use server_remote_dash::store::rollup::RAW_SPAN;
+use server_remote_dash::store::{Store, StoreConfig, STORE_DIRECTORY};
+use server_remote_dash::tls::ServerTls;
+use server_remote_dash::stressapp::message::AppMessage;
+use server_remote_dash::stressapp::monitor_chart::MonitorChart;
+use server_remote_dash::stressapp::replay::{Replay, ReplayMessage, ReplaySpeed};
+use server_remote_dash::stressapp::util_chart::{ChartMessage, ChartSettings, DisplayZone, TimeWindow};
 
-mod stressapp;
-mod gui_connection;
+const LISTEN_ADDRESS: &str = "0.0.0.0:8888";
+const LOG_DIRECTORY: &str = "tcp_logs";
 
 struct State {
     server_chart: MonitorChart,
+    //date range typed into the replay controls, YYYY-MM-DD
+    replay_from: String,
+    replay_to: String,
+    replay_error: Option<String>,
+    //the live chart keeps receiving data while a replay is shown
+    replay: Option<ReplaySession>,
+    //minutes typed for a custom chart window
+    custom_window: String,
+    //None when following a standalone collector's files
+    collector: Option<ShutdownHandle>,
+}
+
+struct ReplaySession {
+    replay: Replay,
+    chart: MonitorChart,
+}
+
+impl ReplaySession {
+    fn new(replay: Replay, rules: Vec<AlertRule>) -> Self {
+        let mut session = Self {
+            replay,
+            chart: MonitorChart::default().with_alerts(rules),
+        };
+        session.reset_chart();
+        session
+    }
+
+    // Charts only move forward, so a seek starts them over
+    fn reset_chart(&mut self) {
+        let settings = self.chart.settings();
+        self.chart = MonitorChart::default().with_alerts(self.chart.alert_rules().to_vec());
+        self.chart.handle_message_all(ChartMessage::SetWindow(settings.window));
+        self.chart.handle_message_all(ChartMessage::SetZone(settings.zone));
+        for (server_id, label) in self.replay.labels() {
+            self.chart.set_label(*server_id, label.clone());
+        }
+    }
+
+    fn play(&mut self, records: Vec<MetricRecord>) {
+        for record in records {
+            self.chart.send_message(record.into());
+        }
+    }
 }
 
 impl State {
     fn new() -> (Self, Task<AppMessage>) {
-        (
-            Self {
-                server_chart: Default::default(),
-            },
-            Task::none(),
-        )
+        gui_connection::configure_file_writer(true, LOG_DIRECTORY, "data");
+        gui_connection::configure_tls(ServerTls::from_env().unwrap_or_else(|e| panic!("Invalid TLS settings: {}", e)));
+        gui_connection::configure_agents(std::env::var_os(AGENTS_FILE_ENV).map(Into::into));
+        gui_connection::configure_metrics_endpoint(std::env::var(METRICS_LISTEN_ENV).ok());
+        let liveness = LivenessConfig::from_env().unwrap_or_else(|e| {
+            eprintln!("Using the default liveness timeouts: {}", e);
+            LivenessConfig::default()
+        });
+        gui_connection::configure_liveness(liveness);
+        let heartbeat = HeartbeatConfig::from_env().unwrap_or_else(|e| {
+            eprintln!("Using the default heartbeat settings: {}", e);
+            HeartbeatConfig::default()
+        });
+        gui_connection::configure_heartbeat(heartbeat);
+        let backpressure = BackpressureConfig::from_env().unwrap_or_else(|e| {
+            eprintln!("Using the default rate limits: {}", e);
+            BackpressureConfig::default()
+        });
+        gui_connection::configure_backpressure(backpressure);
+
+        // Run the collector in-process so data reaches the charts directly.
+        // If the port is taken by a standalone collector, follow its log files instead.
+        let (server_chart, collector) = match gui_connection::initialize_server(LISTEN_ADDRESS) {
+            Ok(collector) => (MonitorChart::default(), Some(collector)),
+            Err(e) => {
+                eprintln!("Failed to start collector on {}: {}", LISTEN_ADDRESS, e);
+                println!("Reading data from {} instead", LOG_DIRECTORY);
+                (MonitorChart::with_directory(LOG_DIRECTORY), None)
+            }
+        };
+
+        // Same rules as the collector, evaluated on what the charts receive
+        let rules = load_rules(Path::new(RULES_FILE)).unwrap_or_else(|e| {
+            eprintln!("Failed to load alert rules: {}", e);
+            Vec::new()
+        });
+        let server_chart = server_chart.with_alerts(rules).with_liveness(liveness);
+
+        // Show what was collected before the dashboard started, from the
+        // collector's own store when it runs here so unwritten points are included
+        let store_directory = Path::new(LOG_DIRECTORY).join(STORE_DIRECTORY);
+        let store = gui_connection::store().map(Ok).unwrap_or_else(|| {
+            Store::open(&store_directory, StoreConfig::default()).map(|store| Arc::new(Mutex::new(store)))
+        });
+        let server_chart = match store {
+            Ok(store) => server_chart.with_store(store),
+            Err(e) => {
+                eprintln!("Failed to open store in {}: {}", store_directory.display(), e);
+                server_chart
+            }
+        };
+
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        let state = Self {
+            server_chart,
+            replay_from: today.clone(),
+            replay_to: today,
+            replay_error: None,
+            replay: None,
+            custom_window: String::new(),
+            collector,
+        };
+
+        (state, Task::none())
     }
 
     fn title(&self) -> String {
         String::from("CPU Monitor Example")
     }
 
-    fn update(&mut self, message: AppMessage){
+    fn update(&mut self, message: AppMessage) -> Task<AppMessage> {
         match message {
             AppMessage::NewDataPoint(basic_message) => {
                 //Update the servers here
                 self.server_chart.send_message(basic_message);
             }
+            AppMessage::ServerIdentified(server_id, label) => {
+                self.server_chart.set_label(server_id, label);
+            }
+            AppMessage::ServerLiveness(server_id, state) => {
+                self.server_chart.set_liveness(server_id, state);
+            }
+            AppMessage::ServerDropped(server_id, dropped) => {
+                self.server_chart.set_dropped(server_id, dropped);
+            }
+            AppMessage::Replay(message) => self.update_replay(message),
+            AppMessage::Chart(server_id, metric, message) => {
+                self.shown_chart().handle_chart_message(server_id, &metric, message);
+            }
+            AppMessage::AllCharts(message) => {
+                if let Some(session) = &mut self.replay {
+                    session.chart.handle_message_all(message.clone());
+                }
+                self.server_chart.handle_message_all(message);
+            }
+            AppMessage::CustomWindowChanged(minutes) => self.custom_window = minutes,
             AppMessage::Tick => {
                 self.server_chart.update();
+
+                if let Some(session) = &mut self.replay {
+                    let records = session.replay.advance();
+                    session.play(records);
+                    session.chart.update();
+                }
+            }
+            AppMessage::CloseRequested => {
+                // Flush the logs and store before the process goes away
+                if let Some(collector) = self.collector.take() {
+                    collector.shutdown();
+                }
+                return iced::exit();
+            }
+        }
+
+        Task::none()
+    }
+
+    // The chart on screen, the replay while one is loaded
+    fn shown_chart(&mut self) -> &mut MonitorChart {
+        match &mut self.replay {
+            Some(session) => &mut session.chart,
+            None => &mut self.server_chart,
+        }
+    }
+
+    fn shown_settings(&self) -> ChartSettings {
+        match &self.replay {
+            Some(session) => session.chart.settings(),
+            None => self.server_chart.settings(),
+        }
+    }
+
+    fn window_controls(&self) -> Element<'_, AppMessage> {
+        let mut row = Row::new()
+            .spacing(10)
+            .align_y(iced::Alignment::Center)
+            .push(Text::new("Window"));
+
+        for window in TimeWindow::PRESETS {
+            row = row.push(button(Text::new(window.to_string())).on_press(AppMessage::AllCharts(ChartMessage::SetWindow(window))));
+        }
+
+        // Custom window in minutes
+        let custom = self
+            .custom_window
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| AppMessage::AllCharts(ChartMessage::SetWindow(TimeWindow::Custom(chrono::Duration::minutes(minutes.into())))));
+        row = row
+            .push(text_input("minutes", &self.custom_window).on_input(AppMessage::CustomWindowChanged).width(80))
+            .push(button("Set").on_press_maybe(custom))
+            .push(button("Follow live").on_press(AppMessage::AllCharts(ChartMessage::FollowLive)));
+
+        // Time zone of the time axis and tooltips
+        let zone = self.shown_settings().zone;
+        for option in DisplayZone::ALL {
+            row = row.push(button(Text::new(option.to_string())).on_press_maybe((option != zone).then_some(AppMessage::AllCharts(ChartMessage::SetZone(option)))));
+        }
+
+        row.into()
+    }
+
+    fn update_replay(&mut self, message: ReplayMessage) {
+        match message {
+            ReplayMessage::FromChanged(from) => self.replay_from = from,
+            ReplayMessage::ToChanged(to) => self.replay_to = to,
+            ReplayMessage::Load => match self.load_replay() {
+                Ok(session) => {
+                    self.replay = Some(session);
+                    self.replay_error = None;
+                }
+                Err(e) => self.replay_error = Some(e),
+            },
+            ReplayMessage::Exit => self.replay = None,
+            message => {
+                let Some(session) = &mut self.replay else {
+                    return;
+                };
+
+                match message {
+                    ReplayMessage::TogglePause => session.replay.toggle_pause(),
+                    ReplayMessage::SetSpeed(speed) => session.replay.set_speed(speed),
+                    ReplayMessage::Seek(seconds) => {
+                        session.reset_chart();
+                        // Refill the whole window, as far as charts keep raw points
+                        let history = session.chart.settings().window.duration().min(RAW_SPAN);
+                        let records = session.replay.seek(seconds, history);
+                        session.play(records);
+                    }
+                    ReplayMessage::Step if session.replay.is_paused() => {
+                        let record = session.replay.step();
+                        session.play(record.into_iter().collect());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn load_replay(&self) -> Result<ReplaySession, String> {
+        let parse = |date: &str| {
+            NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", date))
+        };
+        let from = parse(&self.replay_from)?;
+        let to = parse(&self.replay_to)?;
+        if from > to {
+            return Err(String::from("Start date is after end date"));
+        }
+
+        let replay = Replay::load(Path::new(LOG_DIRECTORY), from, to)
+            .map_err(|e| format!("Failed to read {}: {}", LOG_DIRECTORY, e))?;
+        if replay.is_empty() {
+            return Err(format!("No records between {} and {}", from, to));
+        }
+
+        Ok(ReplaySession::new(replay, self.server_chart.alert_rules().to_vec()))
+    }
+
+    fn replay_controls(&self) -> Element<'_, AppMessage> {
+        let message = AppMessage::Replay;
+
+        let mut row = Row::new()
+            .spacing(10)
+            .align_y(iced::Alignment::Center)
+            .push(Text::new("Replay"))
+            .push(text_input("YYYY-MM-DD", &self.replay_from).on_input(move |s| message(ReplayMessage::FromChanged(s))).width(120))
+            .push(text_input("YYYY-MM-DD", &self.replay_to).on_input(move |s| message(ReplayMessage::ToChanged(s))).width(120))
+            .push(button("Load").on_press(message(ReplayMessage::Load)));
+
+        if let Some(session) = &self.replay {
+            let replay = &session.replay;
+
+            row = row
+                .push(button(if replay.is_paused() { "Play" } else { "Pause" }).on_press(message(ReplayMessage::TogglePause)))
+                .push(button("Step").on_press_maybe(replay.is_paused().then(|| message(ReplayMessage::Step))));
+            for speed in ReplaySpeed::ALL {
+                let selected = replay.speed() == speed;
+                row = row.push(button(Text::new(speed.to_string())).on_press_maybe((!selected).then(|| message(ReplayMessage::SetSpeed(speed)))));
             }
+            row = row
+                .push(slider(0.0..=replay.length_seconds(), replay.elapsed_seconds(), move |s| message(ReplayMessage::Seek(s))).width(300))
+                .push(Text::new(replay.clock().format("%Y-%m-%d %H:%M:%S UTC").to_string()))
+                .push(button("Live").on_press(message(ReplayMessage::Exit)));
+        }
+
+        if let Some(error) = &self.replay_error {
+            row = row.push(Text::new(error.clone()));
         }
+
+        row.into()
     }
 
     fn view(&self) -> Element<'_, AppMessage> {
@@ -42,8 +334,13 @@ impl State {
             .align_x(iced::Alignment::Start)
             .width(iced::Length::Fill)
             .height(iced::Length::Fill)
-            .push(iced::widget::Text::new("Server"))
-            .push(self.server_chart.view());
+            .push(self.replay_controls())
+            .push(self.window_controls());
+
+        let content = match &self.replay {
+            Some(session) => content.push(Text::new("Server (replay)")).push(session.chart.view()),
+            None => content.push(Text::new("Server")).push(self.server_chart.view()),
+        };
 
         iced::widget::Container::new(content)
             .padding(5)
@@ -53,7 +350,11 @@ impl State {
     }
 
     fn subscription(&self) -> Subscription<AppMessage> {
-        Subscription::batch(vec![self.update_all()])
+        Subscription::batch(vec![
+            self.update_all(),
+            Subscription::run(live_data),
+            iced::window::close_requests().map(|_| AppMessage::CloseRequested),
+        ])
     }
 
     fn update_all(&self) -> Subscription<AppMessage> {
@@ -62,9 +363,33 @@ impl State {
     }
 }
 
+/// Streams every line received by the collector as an `AppMessage::NewDataPoint`.
+fn live_data() -> impl Stream<Item = AppMessage> {
+    stream::channel(100, |mut output| async move {
+        let mut events = gui_connection::subscribe();
+
+        while let Some(event) = events.recv().await {
+            let message = match event {
+                ConnectionEvent::NewMessage(record) => record.metric().map(|m| AppMessage::NewDataPoint(m.into())),
+                ConnectionEvent::Identified(server_id, label) => Some(AppMessage::ServerIdentified(server_id, label)),
+                ConnectionEvent::Liveness(server_id, state) => Some(AppMessage::ServerLiveness(server_id, state)),
+                ConnectionEvent::Dropped(server_id, dropped) => Some(AppMessage::ServerDropped(server_id, dropped)),
+                // The charts evaluate the same rules on the data they receive
+                ConnectionEvent::Disconnected(_) | ConnectionEvent::Alert(_) => None,
+            };
+
+            if let Some(message) = message {
+                let _ = output.send(message).await;
+            }
+        }
+    })
+}
+
 fn main()  {
-    iced::application("CPU Monitor Example", State::update, State::view)
+    iced::application(State::title, State::update, State::view)
+        .subscription(State::subscription)
         .antialiasing(true)
-        .run_with(|| State::new())
+        .exit_on_close_request(false)
+        .run_with(State::new)
         .unwrap()
-}
\ No newline at end of file
+}
@@ -0,0 +1,443 @@
+//! Line protocol spoken between the agents and the collector.
+//!
+//! A v2 agent opens the connection with a handshake line, which the collector
+//! echoes back with the version it agreed to:
+//!
+//! ```text
+//! HELLO v2
+//! ```
+//!
+//! followed by one self-describing record per line:
+//!
+//! ```text
+//! METRIC ts=2025-03-17T01:47:02.000Z server=0 metric=cpu value=12.5 unit=%
+//! ```
+//!
+//! Agents that announce a heartbeat interval in their handshake
+//! (`HELLO v2 heartbeat=5000`, in milliseconds) get the collector's interval
+//! back the same way. Both ends then send numbered heartbeats while the
+//! connection is open, so either one notices a silent peer:
+//!
+//! ```text
+//! HEARTBEAT seq=42
+//! ```
+//!
+//! Collectors that only accept registered agents also expect a `token=`
+//! attribute, unless the agent presented a client certificate, and close the
+//! connection without a reply when it doesn't check out.
+//!
+//! Agents that skip the handshake are treated as v1 and send the legacy
+//! `server-metric-value-hh:mm:ss` lines, which are still accepted.
+
+use chrono::{DateTime, NaiveTime, SecondsFormat, Utc};
+use std::fmt;
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const LEGACY_VERSION: u32 = 1;
+
+// Longest metric name accepted. Names become store series and labels, so
+// they are kept short and to `[A-Za-z0-9._-]`.
+pub const MAX_METRIC_NAME: usize = 64;
+// How far before its receive time a record may be stamped, long enough for
+// samples an agent spooled through an outage
+pub const MAX_RECORD_AGE: chrono::Duration = chrono::Duration::days(7);
+// How far after, for agents whose clock runs ahead
+pub const MAX_CLOCK_SKEW: chrono::Duration = chrono::Duration::minutes(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Metric {
+    Cpu,
+    Ip,
+    Network,
+    Fs,
+    Memory,
+    Other(String),
+}
+
+impl Metric {
+    pub const ALL: [Metric; 5] = [Metric::Cpu, Metric::Ip, Metric::Network, Metric::Fs, Metric::Memory];
+
+    // Legacy numeric ids: 0=CPU, 1=IP, 2=Network, 3=FS, 4=Memory
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            0 => Metric::Cpu,
+            1 => Metric::Ip,
+            2 => Metric::Network,
+            3 => Metric::Fs,
+            4 => Metric::Memory,
+            _ => Metric::Other(id.to_string()),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "cpu" => Metric::Cpu,
+            "ip" => Metric::Ip,
+            "network" => Metric::Network,
+            "fs" => Metric::Fs,
+            "memory" => Metric::Memory,
+            other => Metric::Other(other.to_string()),
+        }
+    }
+
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            Metric::Cpu => Some(0),
+            Metric::Ip => Some(1),
+            Metric::Network => Some(2),
+            Metric::Fs => Some(3),
+            Metric::Memory => Some(4),
+            Metric::Other(_) => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Ip => "ip",
+            Metric::Network => "network",
+            Metric::Fs => "fs",
+            Metric::Memory => "memory",
+            Metric::Other(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Handshake line, `HELLO v<version> [key=value ...]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Hello {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.push((key.to_string(), sanitize_token(value)));
+        self
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl fmt::Display for Hello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HELLO v{}", self.version)?;
+        for (key, value) in &self.attributes {
+            write!(f, " {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Liveness frame, `HEARTBEAT seq=<n>`, numbered from 0 per connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub seq: u64,
+}
+
+impl fmt::Display for Heartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HEARTBEAT seq={}", self.seq)
+    }
+}
+
+/// Make a value safe to send as a `key=value` token.
+pub fn sanitize_token(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| if c.is_whitespace() || c == '=' { '_' } else { c })
+        .collect()
+}
+
+/// A single sample of one metric on one server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricRecord {
+    pub timestamp: DateTime<Utc>,
+    pub server_id: u32,
+    pub metric: Metric,
+    pub value: f64,
+    pub unit: String,
+}
+
+impl MetricRecord {
+    /// Whether the timestamp is plausible for a record received at
+    /// `received`, see `MAX_RECORD_AGE` and `MAX_CLOCK_SKEW`.
+    pub fn is_timely(&self, received: DateTime<Utc>) -> bool {
+        self.timestamp >= received - MAX_RECORD_AGE && self.timestamp <= received + MAX_CLOCK_SKEW
+    }
+}
+
+impl fmt::Display for MetricRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "METRIC ts={} server={} metric={} value={} unit={}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.server_id,
+            self.metric,
+            self.value,
+            self.unit
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Hello(Hello),
+    Heartbeat(Heartbeat),
+    Metric(MetricRecord),
+}
+
+/// Parse one line of either protocol version.
+pub fn parse_frame(line: &str) -> Option<Frame> {
+    parse_frame_at(line, Utc::now())
+}
+
+/// Parse one line, placing legacy time-of-day stamps on the day nearest to `received`.
+pub fn parse_frame_at(line: &str, received: DateTime<Utc>) -> Option<Frame> {
+    let line = line.trim();
+    let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+    match kind {
+        "HELLO" => parse_hello(rest).map(Frame::Hello),
+        "HEARTBEAT" => parse_heartbeat(rest).map(Frame::Heartbeat),
+        "METRIC" => parse_metric(rest).map(Frame::Metric),
+        _ => parse_legacy(line, received).map(Frame::Metric),
+    }
+}
+
+fn parse_hello(rest: &str) -> Option<Hello> {
+    let mut tokens = rest.split_whitespace();
+    let version = tokens.next()?.strip_prefix('v')?.parse::<u32>().ok()?;
+    let attributes = tokens
+        .map(|token| token.split_once('=').map(|(k, v)| (k.to_string(), v.to_string())))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Hello { version, attributes })
+}
+
+fn parse_heartbeat(rest: &str) -> Option<Heartbeat> {
+    let seq = rest.trim().strip_prefix("seq=")?.parse::<u64>().ok()?;
+    Some(Heartbeat { seq })
+}
+
+fn parse_metric(rest: &str) -> Option<MetricRecord> {
+    let mut timestamp = None;
+    let mut server_id = None;
+    let mut metric = None;
+    let mut value = None;
+    let mut unit = None;
+
+    for token in rest.split_whitespace() {
+        let (key, val) = token.split_once('=')?;
+        match key {
+            "ts" => timestamp = Some(DateTime::parse_from_rfc3339(val).ok()?.with_timezone(&Utc)),
+            "server" => server_id = Some(val.parse::<u32>().ok()?),
+            "metric" => metric = Some(is_metric_name(val).then(|| Metric::from_name(val))?),
+            "value" => value = Some(val.parse::<f64>().ok().filter(|v| v.is_finite())?),
+            "unit" => unit = Some(val.to_string()),
+            // Unknown keys are skipped so newer agents can add fields
+            _ => {}
+        }
+    }
+
+    Some(MetricRecord {
+        timestamp: timestamp?,
+        server_id: server_id?,
+        metric: metric?,
+        value: value?,
+        unit: unit.unwrap_or_default(),
+    })
+}
+
+fn is_metric_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_METRIC_NAME
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn parse_legacy(line: &str, received: DateTime<Utc>) -> Option<MetricRecord> {
+    // Parse format: server-metric-value-hh:mm:ss, where value may be negative
+    let mut parts = line.splitn(3, '-');
+    let server_id = parts.next()?.parse::<u32>().ok()?;
+    let metric_id = parts.next()?.parse::<u8>().ok()?;
+    let (value, time) = parts.next()?.rsplit_once('-')?;
+
+    let value = value.parse::<f64>().ok().filter(|v| v.is_finite())?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+
+    // Sent just before or after midnight, the stamp belongs to the adjacent day
+    let mut timestamp = received.date_naive().and_time(time).and_utc();
+    if timestamp - received > chrono::Duration::hours(12) {
+        timestamp -= chrono::Duration::days(1);
+    } else if received - timestamp > chrono::Duration::hours(12) {
+        timestamp += chrono::Duration::days(1);
+    }
+
+    Some(MetricRecord {
+        timestamp,
+        server_id,
+        metric: Metric::from_id(metric_id),
+        value,
+        unit: String::from("%"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_hello_with_attributes() {
+        let frame = parse_frame_at("HELLO v2 name=web-1 heartbeat=5000", Utc::now());
+        let Some(Frame::Hello(hello)) = frame else {
+            panic!("not a hello: {:?}", frame);
+        };
+        assert_eq!(hello.version, 2);
+        assert_eq!(hello.attribute("name"), Some("web-1"));
+        assert_eq!(hello.attribute("heartbeat"), Some("5000"));
+        assert_eq!(hello.attribute("uuid"), None);
+
+        assert_eq!(parse_frame_at("HELLO 2", Utc::now()), None);
+        assert_eq!(parse_frame_at("HELLO v2 name", Utc::now()), None);
+    }
+
+    #[test]
+    fn hello_round_trips_through_display() {
+        let hello = Hello::new(2).with_attribute("name", "web 1").with_attribute("token", "a=b");
+        assert_eq!(hello.to_string(), "HELLO v2 name=web_1 token=a_b");
+        assert_eq!(parse_frame_at(&hello.to_string(), Utc::now()), Some(Frame::Hello(hello)));
+        assert_eq!(parse_frame_at("HELLO v2", Utc::now()), Some(Frame::Hello(Hello::new(2))));
+    }
+
+    #[test]
+    fn parses_heartbeats() {
+        assert_eq!(
+            parse_frame_at("HEARTBEAT seq=42", Utc::now()),
+            Some(Frame::Heartbeat(Heartbeat { seq: 42 }))
+        );
+        assert_eq!(parse_frame_at("HEARTBEAT seq=-1", Utc::now()), None);
+        assert_eq!(parse_frame_at("HEARTBEAT", Utc::now()), None);
+    }
+
+    #[test]
+    fn parses_v2_metrics() {
+        let line = "METRIC ts=2025-03-17T01:47:02.000Z server=3 metric=cpu value=12.5 unit=% extra=ignored";
+        let expected = MetricRecord {
+            timestamp: at("2025-03-17T01:47:02Z"),
+            server_id: 3,
+            metric: Metric::Cpu,
+            value: 12.5,
+            unit: String::from("%"),
+        };
+        assert_eq!(parse_frame_at(line, Utc::now()), Some(Frame::Metric(expected.clone())));
+        assert_eq!(parse_frame_at(&expected.to_string(), Utc::now()), Some(Frame::Metric(expected)));
+
+        let custom = parse_frame_at("METRIC ts=2025-03-17T01:47:02Z server=0 metric=load value=1", Utc::now());
+        assert!(matches!(custom, Some(Frame::Metric(r)) if r.metric == Metric::Other(String::from("load")) && r.unit.is_empty()));
+    }
+
+    #[test]
+    fn rejects_incomplete_or_invalid_v2_metrics() {
+        let received = Utc::now();
+        for line in [
+            "METRIC server=0 metric=cpu value=1",
+            "METRIC ts=2025-03-17T01:47:02Z metric=cpu value=1",
+            "METRIC ts=2025-03-17T01:47:02Z server=0 value=1",
+            "METRIC ts=2025-03-17T01:47:02Z server=0 metric=cpu",
+            "METRIC ts=yesterday server=0 metric=cpu value=1",
+            "METRIC ts=2025-03-17T01:47:02Z server=-1 metric=cpu value=1",
+            "METRIC ts=2025-03-17T01:47:02Z server=0 metric=cpu value=NaN",
+            "METRIC ts=2025-03-17T01:47:02Z server=0 metric=cpu value=inf",
+            "METRIC ts=2025-03-17T01:47:02Z server=0 metric=cpu value",
+        ] {
+            assert_eq!(parse_frame_at(line, received), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn places_legacy_lines_on_the_receive_date() {
+        let received = at("2025-03-17T12:00:00Z");
+        let expected = |server_id, metric, value| {
+            Some(Frame::Metric(MetricRecord {
+                timestamp: at("2025-03-17T01:47:02Z"),
+                server_id,
+                metric,
+                value,
+                unit: String::from("%"),
+            }))
+        };
+
+        assert_eq!(parse_frame_at("1-0-42.5-01:47:02", received), expected(1, Metric::Cpu, 42.5));
+        assert_eq!(parse_frame_at("0-4--3-01:47:02\r\n", received), expected(0, Metric::Memory, -3.0));
+        assert_eq!(parse_frame_at("0-0-1-25:00:00", received), None);
+        assert_eq!(parse_frame_at("0-0-1", received), None);
+        assert_eq!(parse_frame_at("", received), None);
+    }
+
+    #[test]
+    fn places_legacy_lines_sent_across_midnight_on_the_right_day() {
+        let timestamp = |line, received| match parse_frame_at(line, at(received)) {
+            Some(Frame::Metric(record)) => record.timestamp,
+            other => panic!("unexpected frame {:?}", other),
+        };
+
+        assert_eq!(timestamp("1-0-1-23:59:59", "2025-03-17T00:00:01Z"), at("2025-03-16T23:59:59Z"));
+        assert_eq!(timestamp("1-0-1-00:00:01", "2025-03-16T23:59:59Z"), at("2025-03-17T00:00:01Z"));
+        assert_eq!(timestamp("1-0-1-11:00:00", "2025-03-17T00:00:01Z"), at("2025-03-17T11:00:00Z"));
+    }
+
+    #[test]
+    fn metric_names_are_limited_in_length_and_characters() {
+        let received = at("2025-03-17T01:47:03Z");
+        let line = |name: &str| format!("METRIC ts=2025-03-17T01:47:02.000Z server=0 metric={} value=1", name);
+
+        assert!(parse_frame_at(&line("disk.io_read-ops"), received).is_some());
+        assert!(parse_frame_at(&line(&"a".repeat(MAX_METRIC_NAME)), received).is_some());
+        assert_eq!(parse_frame_at(&line(&"a".repeat(MAX_METRIC_NAME + 1)), received), None);
+        assert_eq!(parse_frame_at(&line("../x"), received), None);
+        assert_eq!(parse_frame_at(&line("cpu%"), received), None);
+    }
+
+    #[test]
+    fn records_have_to_be_stamped_close_to_their_receive_time() {
+        let received = at("2025-03-17T12:00:00Z");
+        let record = |timestamp| MetricRecord {
+            timestamp,
+            server_id: 0,
+            metric: Metric::Cpu,
+            value: 1.0,
+            unit: String::new(),
+        };
+
+        assert!(record(received).is_timely(received));
+        assert!(record(received - MAX_RECORD_AGE).is_timely(received));
+        assert!(record(received + MAX_CLOCK_SKEW).is_timely(received));
+        assert!(!record(received - MAX_RECORD_AGE - chrono::Duration::seconds(1)).is_timely(received));
+        assert!(!record(received + MAX_CLOCK_SKEW + chrono::Duration::seconds(1)).is_timely(received));
+        assert!(!record(at("2999-01-01T00:00:00Z")).is_timely(received));
+    }
+}
@@ -0,0 +1,655 @@
+//! Embedded time-series store for the metrics the collector receives.
+//!
+//! Points are grouped per series (server id + metric) and written as
+//! compressed blocks to append-only segment files, one per hour:
+//!
+//! ```text
+//! store/
+//!   2025031701.seg
+//!   2025031702.seg
+//!   units.toml
+//! ```
+//!
+//! Points are buffered in memory and written as a block once a series has
+//! `StoreConfig::block_points` of them, once its hour is over, or once they
+//! have waited `StoreConfig::flush_after`. Queries on the appending store
+//! see the buffered points too, other processes only what is on disk.
+//! Whole segments older than the retention are deleted. Several processes
+//! may open the same directory, but only one should append.
+//!
+//! As points are appended, min/max/avg/p95 rollups are computed for 10 s,
+//! 1 min and 1 h buckets and stored alongside the raw points as series of
+//! their own (`cpu@1m.avg`), written when a bucket closes.
+//!
+//! Blocks hold only values, the unit of each series is kept in `units.toml`.
+
+mod codec;
+pub mod rollup;
+mod segment;
+
+use crate::protocol::Metric;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rollup::{Rollup, RollupBuilder, Tier};
+use segment::Segment;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+// Subdirectory of the log directory the collector keeps its store in
+pub const STORE_DIRECTORY: &str = "store";
+// Time covered by one segment file
+const SEGMENT_SPAN_MS: i64 = 60 * 60 * 1000;
+const SEGMENT_NAME_FORMAT: &str = "%Y%m%d%H";
+// Unit of every series, which the blocks don't hold
+const UNITS_FILE: &str = "units.toml";
+
+/// Identifies one series: a metric of one server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub server_id: u32,
+    pub metric: String,
+}
+
+impl SeriesKey {
+    pub fn new(server_id: u32, metric: &Metric) -> Self {
+        Self {
+            server_id,
+            metric: metric.name().to_string(),
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        Metric::from_name(&self.metric)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StoreConfig {
+    pub retention: Duration,
+    pub block_points: usize,
+    // Longest a point waits in memory for its block to fill, so slow series
+    // still reach the disk
+    pub flush_after: Duration,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            retention: Duration::from_secs(30 * 24 * 60 * 60),
+            block_points: 256,
+            flush_after: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// A store shared by the thread appending to it and readers in the same
+/// process, which then also see the points not written yet.
+pub type SharedStore = Arc<Mutex<Store>>;
+
+// Points of one series and segment waiting to be written as a block
+struct Pending {
+    since: Instant,
+    points: Vec<(i64, f64)>,
+}
+
+pub struct Store {
+    directory: PathBuf,
+    config: StoreConfig,
+    // Keyed by the start of the hour they cover, in ms
+    segments: BTreeMap<i64, Segment>,
+    // Points not yet written, per series and segment
+    pending: HashMap<(SeriesKey, i64), Pending>,
+    // Segment the newest point of each series went to
+    current: HashMap<SeriesKey, i64>,
+    rollups: RollupBuilder,
+    // Keyed by `<server id>/<metric>`, with when the file was last read
+    units: UnitsFile,
+    units_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UnitsFile {
+    #[serde(default)]
+    units: BTreeMap<String, String>,
+}
+
+impl Store {
+    /// Open (or create) a store, indexing the segments already on disk.
+    pub fn open(directory: &Path, config: StoreConfig) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        let mut store = Self {
+            directory: directory.to_path_buf(),
+            config,
+            segments: BTreeMap::new(),
+            pending: HashMap::new(),
+            current: HashMap::new(),
+            rollups: RollupBuilder::default(),
+            units: UnitsFile::default(),
+            units_modified: None,
+        };
+        store.refresh()?;
+
+        Ok(store)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Pick up segments and blocks written since the last refresh, e.g. by
+    /// a collector running in another process.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let mut found = HashSet::new();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let Some(start) = segment_start(&path) else {
+                continue;
+            };
+            found.insert(start);
+
+            match self.segments.get_mut(&start) {
+                Some(segment) => segment.scan()?,
+                None => {
+                    self.segments.insert(start, Segment::open(&path)?);
+                }
+            }
+        }
+
+        // Removed by retention in another process
+        self.segments.retain(|start, _| found.contains(start));
+        self.refresh_units();
+        Ok(())
+    }
+
+    // Read the units again if another process changed them
+    fn refresh_units(&mut self) {
+        let path = self.directory.join(UNITS_FILE);
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_none() || modified == self.units_modified {
+            return;
+        }
+
+        match fs::read_to_string(&path).map(|contents| toml::from_str::<UnitsFile>(&contents)) {
+            Ok(Ok(units)) => self.units = units,
+            Ok(Err(e)) => eprintln!("Ignoring unreadable {}: {}", path.display(), e),
+            Err(e) => eprintln!("Error reading {}: {}", path.display(), e),
+        }
+        self.units_modified = modified;
+    }
+
+    /// Unit the values of a series are in, None if it was never given.
+    pub fn unit(&self, server_id: u32, metric: &Metric) -> Option<&str> {
+        self.units.units.get(&unit_key(server_id, metric)).map(String::as_str)
+    }
+
+    /// Remember the unit of a series, written to disk when it changes.
+    pub fn set_unit(&mut self, server_id: u32, metric: &Metric, unit: &str) -> io::Result<()> {
+        let key = unit_key(server_id, metric);
+        if self.units.units.get(&key).map(String::as_str) == Some(unit) {
+            return Ok(());
+        }
+        self.units.units.insert(key, unit.to_string());
+
+        let contents = toml::to_string(&self.units)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        // Write then rename so readers never see a half written file
+        let path = self.directory.join(UNITS_FILE);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(temp, &path)?;
+        self.units_modified = fs::metadata(&path)?.modified().ok();
+        Ok(())
+    }
+
+    /// Buffer one point, writing the series' block once it is full.
+    pub fn append(&mut self, server_id: u32, metric: &Metric, timestamp: DateTime<Utc>, value: f64) -> io::Result<()> {
+        let series = SeriesKey::new(server_id, metric);
+        let ts = timestamp.timestamp_millis();
+
+        for (series, tier, rollup) in self.rollups.push(&series, ts, value) {
+            self.append_rollup(&series, tier, &rollup)?;
+        }
+        self.append_point(series, ts, value)
+    }
+
+    fn append_rollup(&mut self, series: &SeriesKey, tier: Tier, rollup: &Rollup) -> io::Result<()> {
+        let ts = rollup.start.timestamp_millis();
+        for (stat_series, value) in rollup::stat_series(series, tier).into_iter().zip(rollup.stats()) {
+            self.append_point(stat_series, ts, value)?;
+        }
+        Ok(())
+    }
+
+    fn append_point(&mut self, series: SeriesKey, ts: i64, value: f64) -> io::Result<()> {
+        let start = ts.div_euclid(SEGMENT_SPAN_MS) * SEGMENT_SPAN_MS;
+
+        // The series moved on to the next hour, the previous one is complete.
+        // Late points for an earlier hour wait for flush_after.
+        let current = self.current.entry(series.clone()).or_insert(start);
+        if *current < start {
+            let previous = std::mem::replace(current, start);
+            self.write_pending(&(series.clone(), previous))?;
+        }
+
+        let key = (series, start);
+        let pending = self.pending.entry(key.clone()).or_insert_with(|| Pending {
+            since: Instant::now(),
+            points: Vec::new(),
+        });
+        pending.points.push((ts, value));
+
+        if pending.points.len() >= self.config.block_points {
+            self.write_pending(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_pending(&mut self, key: &(SeriesKey, i64)) -> io::Result<()> {
+        match self.pending.remove(key) {
+            Some(pending) => self.write_block(&key.0, key.1, &pending.points),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the points that have waited `StoreConfig::flush_after` for
+    /// their block to fill.
+    pub fn flush_due(&mut self) -> io::Result<()> {
+        let due: Vec<(SeriesKey, i64)> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.since.elapsed() >= self.config.flush_after)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut result = Ok(());
+        for key in due {
+            if let Err(e) = self.write_pending(&key) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Write every buffered point.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        for ((series, start), pending) in std::mem::take(&mut self.pending) {
+            if let Err(e) = self.write_block(&series, start, &pending.points) {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Write the buckets still filling as partial rollups and flush, before
+    /// the store is dropped.
+    pub fn close(&mut self) -> io::Result<()> {
+        for (series, tier, rollup) in self.rollups.drain() {
+            self.append_rollup(&series, tier, &rollup)?;
+        }
+        self.flush()
+    }
+
+    fn write_block(&mut self, series: &SeriesKey, start: i64, points: &[(i64, f64)]) -> io::Result<()> {
+        let segment = match self.segments.entry(start) {
+            std::collections::btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                let path = self.directory.join(segment_name(start));
+                fs::OpenOptions::new().create(true).append(true).open(&path)?;
+
+                entry.insert(Segment::open(&path)?)
+            }
+        };
+
+        segment.append(series, points)
+    }
+
+    /// Every series with data in the store, without the rollups.
+    pub fn series(&self) -> Vec<SeriesKey> {
+        let mut series: Vec<SeriesKey> = self
+            .segments
+            .values()
+            .flat_map(|segment| segment.series().cloned())
+            .chain(self.pending.keys().map(|(series, _)| series.clone()))
+            .filter(|series| !rollup::is_rollup(series))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        series.sort();
+        series
+    }
+
+    /// Points of one series between two times, inclusive, oldest first.
+    pub fn query(
+        &self,
+        server_id: u32,
+        metric: &Metric,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> io::Result<Vec<(DateTime<Utc>, f64)>> {
+        let points = self.read_series(&SeriesKey::new(server_id, metric), from, to)?;
+
+        Ok(points
+            .into_iter()
+            .filter_map(|(ts, value)| Some((DateTime::from_timestamp_millis(ts)?, value)))
+            .collect())
+    }
+
+    /// Rollups of one series for the buckets starting between two times, oldest first.
+    pub fn query_rollups(
+        &self,
+        server_id: u32,
+        metric: &Metric,
+        tier: Tier,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> io::Result<Vec<Rollup>> {
+        let series = SeriesKey::new(server_id, metric);
+        let mut stats: BTreeMap<i64, Vec<[f64; 5]>> = BTreeMap::new();
+
+        for (index, stat_series) in rollup::stat_series(&series, tier).iter().enumerate() {
+            // Buckets written twice (partial at shutdown) come back in the same
+            // order for every statistic, so they line up by position
+            let mut seen: HashMap<i64, usize> = HashMap::new();
+            for (ts, value) in self.read_series(stat_series, from, to)? {
+                let occurrence = seen.entry(ts).or_default();
+                let buckets = stats.entry(ts).or_default();
+                if buckets.len() <= *occurrence {
+                    buckets.push([f64::NAN; 5]);
+                }
+                buckets[*occurrence][index] = value;
+                *occurrence += 1;
+            }
+        }
+
+        Ok(stats
+            .into_iter()
+            .filter_map(|(ts, buckets)| {
+                let start = DateTime::from_timestamp_millis(ts)?;
+                let mut parts = buckets
+                    .into_iter()
+                    .filter(|stats| stats.iter().all(|v| !v.is_nan()))
+                    .map(|stats| Rollup::from_stats(start, stats));
+                let mut rollup = parts.next()?;
+                parts.for_each(|part| rollup.merge(&part));
+                Some(rollup)
+            })
+            .collect())
+    }
+
+    // Points of one series between two timestamps (ms), inclusive, oldest first
+    fn read_series(&self, series: &SeriesKey, from: DateTime<Utc>, to: DateTime<Utc>) -> io::Result<Vec<(i64, f64)>> {
+        let (from, to) = (from.timestamp_millis(), to.timestamp_millis());
+        let first_segment = from.div_euclid(SEGMENT_SPAN_MS) * SEGMENT_SPAN_MS;
+
+        let mut points = Vec::new();
+        for segment in self.segments.range(first_segment..=to).map(|(_, s)| s) {
+            points.extend(segment.read(series, from, to)?);
+        }
+        for ((pending_series, _), pending) in &self.pending {
+            if pending_series == series {
+                points.extend(pending.points.iter().filter(|(ts, _)| (from..=to).contains(ts)));
+            }
+        }
+
+        // Stable, so repeated timestamps keep the order they were written in
+        points.sort_by_key(|(ts, _)| *ts);
+        Ok(points)
+    }
+
+    /// Delete the segments that ended before the retention window. Returns
+    /// how many were removed.
+    pub fn enforce_retention(&mut self, now: DateTime<Utc>) -> io::Result<usize> {
+        let Some(cutoff) = chrono::Duration::from_std(self.config.retention)
+            .ok()
+            .and_then(|retention| now.checked_sub_signed(retention))
+        else {
+            return Ok(0);
+        };
+        let cutoff = cutoff.timestamp_millis();
+
+        let expired: Vec<i64> = self
+            .segments
+            .keys()
+            .copied()
+            .filter(|start| start + SEGMENT_SPAN_MS <= cutoff)
+            .collect();
+
+        for start in &expired {
+            if let Some(segment) = self.segments.remove(start) {
+                fs::remove_file(segment.path())?;
+            }
+        }
+
+        Ok(expired.len())
+    }
+}
+
+fn segment_name(start: i64) -> String {
+    let start = DateTime::from_timestamp_millis(start).unwrap_or_default();
+    format!("{}.seg", start.format(SEGMENT_NAME_FORMAT))
+}
+
+fn unit_key(server_id: u32, metric: &Metric) -> String {
+    format!("{}/{}", server_id, metric.name())
+}
+
+// Start of the hour covered by a segment file, None for other files
+fn segment_start(path: &Path) -> Option<i64> {
+    if path.extension() != Some("seg".as_ref()) {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    let start = NaiveDateTime::parse_from_str(&format!("{}0000", stem), "%Y%m%d%H%M%S").ok()?;
+    Some(start.and_utc().timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory per test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-store-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn points_survive_a_reopen() {
+        let dir = TempDir::new("reopen");
+        let start = at("2025-03-17T01:59:00Z");
+        let config = StoreConfig {
+            block_points: 16,
+            ..Default::default()
+        };
+
+        // Spans two segments and leaves a partly filled block to flush
+        let points: Vec<(DateTime<Utc>, f64)> =
+            (0..150).map(|i| (start + chrono::Duration::seconds(i), i as f64 / 4.0)).collect();
+        let mut store = Store::open(&dir.0, config).unwrap();
+        for (timestamp, value) in &points {
+            store.append(7, &Metric::Cpu, *timestamp, *value).unwrap();
+        }
+        store.flush().unwrap();
+        drop(store);
+
+        let store = Store::open(&dir.0, config).unwrap();
+        let end = points.last().unwrap().0;
+        assert_eq!(store.query(7, &Metric::Cpu, start, end).unwrap(), points);
+        assert_eq!(store.query(7, &Metric::Memory, start, end).unwrap(), []);
+        assert_eq!(store.series(), [SeriesKey::new(7, &Metric::Cpu)]);
+
+        let minute = at("2025-03-17T02:00:00Z");
+        let inside = store.query(7, &Metric::Cpu, minute, minute + chrono::Duration::seconds(9)).unwrap();
+        assert_eq!(inside, points[60..70]);
+    }
+
+    #[test]
+    fn units_are_seen_by_other_stores_of_the_directory() {
+        let dir = TempDir::new("units");
+        let mut writer = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        let mut reader = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        assert_eq!(reader.unit(7, &Metric::Cpu), None);
+
+        writer.set_unit(7, &Metric::Cpu, "%").unwrap();
+        writer.set_unit(7, &Metric::Other(String::from("rx")), "MB/s").unwrap();
+        reader.refresh().unwrap();
+        assert_eq!(reader.unit(7, &Metric::Cpu), Some("%"));
+        assert_eq!(reader.unit(7, &Metric::Other(String::from("rx"))), Some("MB/s"));
+        assert_eq!(reader.unit(8, &Metric::Cpu), None);
+
+        drop(writer);
+        let reopened = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        assert_eq!(reopened.unit(7, &Metric::Cpu), Some("%"));
+    }
+
+    #[test]
+    fn names_too_long_for_a_block_are_refused() {
+        let dir = TempDir::new("long-name");
+        let mut store = Store::open(&dir.0, StoreConfig::default()).unwrap();
+
+        let metric = Metric::Other("m".repeat(300));
+        store.append(0, &metric, at("2025-03-17T01:00:00Z"), 1.0).unwrap();
+        assert!(store.flush().is_err());
+    }
+
+    #[test]
+    fn corrupt_bytes_in_a_segment_only_lose_their_block() {
+        let dir = TempDir::new("corrupt");
+        let config = StoreConfig {
+            block_points: 4,
+            ..Default::default()
+        };
+        let start = at("2025-03-17T01:00:00Z");
+        let points: Vec<(DateTime<Utc>, f64)> =
+            (0..16).map(|i| (start + chrono::Duration::seconds(i), i as f64)).collect();
+
+        let mut store = Store::open(&dir.0, config).unwrap();
+        for (timestamp, value) in &points[..12] {
+            store.append(3, &Metric::Cpu, *timestamp, *value).unwrap();
+        }
+        store.flush().unwrap();
+        drop(store);
+
+        // Break the magic of the first block and leave garbage at the end
+        let segment = fs::read_dir(&dir.0).unwrap().next().unwrap().unwrap().path();
+        let mut bytes = fs::read(&segment).unwrap();
+        bytes[..2].copy_from_slice(b"XX");
+        bytes.extend_from_slice(&[0xAB; 64]);
+        fs::write(&segment, bytes).unwrap();
+
+        let mut store = Store::open(&dir.0, config).unwrap();
+        let end = points.last().unwrap().0;
+        assert_eq!(store.query(3, &Metric::Cpu, start, end).unwrap(), points[4..12]);
+
+        // The garbage at the end is dropped before the next block is written
+        for (timestamp, value) in &points[12..] {
+            store.append(3, &Metric::Cpu, *timestamp, *value).unwrap();
+        }
+        store.flush().unwrap();
+        let reopened = Store::open(&dir.0, config).unwrap();
+        assert_eq!(reopened.query(3, &Metric::Cpu, start, end).unwrap(), points[4..]);
+    }
+
+    #[test]
+    fn rollups_merge_late_points_and_buckets_split_by_a_restart() {
+        let dir = TempDir::new("rollups");
+        let start = at("2025-03-17T01:00:00Z");
+        let second = |i: i64| start + chrono::Duration::seconds(i);
+
+        let mut store = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        for i in 0..=20 {
+            store.append(2, &Metric::Cpu, second(i), i as f64).unwrap();
+        }
+        store.append(2, &Metric::Cpu, second(5), 100.0).unwrap();
+        store.close().unwrap();
+
+        // The bucket still filling at shutdown is completed after the restart
+        let mut store = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        store.append(2, &Metric::Cpu, second(25), 25.0).unwrap();
+        store.close().unwrap();
+
+        let store = Store::open(&dir.0, StoreConfig::default()).unwrap();
+        let rollups = store.query_rollups(2, &Metric::Cpu, Tier::TenSeconds, start, second(30)).unwrap();
+        let summary: Vec<(DateTime<Utc>, f64, f64, u32)> =
+            rollups.iter().map(|r| (r.start, r.min, r.max, r.count)).collect();
+        assert_eq!(
+            summary,
+            [(second(0), 0.0, 100.0, 11), (second(10), 10.0, 19.0, 10), (second(20), 20.0, 25.0, 2)]
+        );
+        assert_eq!(store.query_rollups(2, &Metric::Cpu, Tier::TenSeconds, second(10), second(10)).unwrap().len(), 1);
+        assert_eq!(store.query_rollups(2, &Metric::Memory, Tier::Minute, start, second(30)).unwrap(), []);
+    }
+
+    #[test]
+    fn slow_series_are_written_once_their_hour_is_over() {
+        let dir = TempDir::new("hour");
+        let config = StoreConfig {
+            flush_after: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let mut writer = Store::open(&dir.0, config).unwrap();
+        let mut reader = Store::open(&dir.0, config).unwrap();
+        let start = at("2025-03-17T01:59:50Z");
+        let end = start + chrono::Duration::seconds(20);
+
+        for i in 0..10 {
+            writer.append(1, &Metric::Cpu, start + chrono::Duration::seconds(i), i as f64).unwrap();
+        }
+        writer.flush_due().unwrap();
+
+        // Still in memory, which only the appending store can see
+        assert_eq!(writer.query(1, &Metric::Cpu, start, end).unwrap().len(), 10);
+        reader.refresh().unwrap();
+        assert_eq!(reader.query(1, &Metric::Cpu, start, end).unwrap().len(), 0);
+
+        // A point of the next hour completes the block of this one
+        writer.append(1, &Metric::Cpu, at("2025-03-17T02:00:00Z"), 10.0).unwrap();
+        reader.refresh().unwrap();
+        assert_eq!(reader.query(1, &Metric::Cpu, start, end).unwrap().len(), 10);
+        assert_eq!(writer.query(1, &Metric::Cpu, start, end).unwrap().len(), 11);
+    }
+
+    #[test]
+    fn points_that_waited_too_long_are_flushed() {
+        let dir = TempDir::new("flush-after");
+        let config = StoreConfig {
+            flush_after: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut writer = Store::open(&dir.0, config).unwrap();
+        let start = at("2025-03-17T01:00:00Z");
+
+        writer.append(1, &Metric::Cpu, start, 1.0).unwrap();
+        writer.flush_due().unwrap();
+
+        let reader = Store::open(&dir.0, config).unwrap();
+        assert_eq!(reader.query(1, &Metric::Cpu, start, start).unwrap(), [(start, 1.0)]);
+    }
+}
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use super::SeriesKey;
+
+// Longest span drawn from raw points
+pub const RAW_SPAN: Duration = Duration::minutes(5);
+// Most buckets a rollup tier may put on one chart before the next one is used
+const MAX_BUCKETS: i64 = 1500;
+
+// The statistics kept per bucket, each stored as its own series
+const STATS: [&str; 5] = ["min", "max", "avg", "p95", "count"];
+
+/// Resolution the points of a chart come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tier {
+    Raw,
+    TenSeconds,
+    Minute,
+    Hour,
+}
+
+impl Tier {
+    pub const ROLLUPS: [Tier; 3] = [Tier::TenSeconds, Tier::Minute, Tier::Hour];
+
+    /// Width of one bucket, None for raw points.
+    pub fn bucket(&self) -> Option<Duration> {
+        match self {
+            Tier::Raw => None,
+            Tier::TenSeconds => Some(Duration::seconds(10)),
+            Tier::Minute => Some(Duration::minutes(1)),
+            Tier::Hour => Some(Duration::hours(1)),
+        }
+    }
+
+    /// The finest tier that keeps a chart of this span readable.
+    pub fn for_span(span: Duration) -> Tier {
+        if span <= RAW_SPAN {
+            return Tier::Raw;
+        }
+
+        Tier::ROLLUPS
+            .into_iter()
+            .find(|tier| span.num_milliseconds() / tier.bucket_ms() <= MAX_BUCKETS)
+            .unwrap_or(Tier::Hour)
+    }
+
+    fn bucket_ms(&self) -> i64 {
+        self.bucket().map_or(1, |bucket| bucket.num_milliseconds())
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Tier::Raw => "raw",
+            Tier::TenSeconds => "10s",
+            Tier::Minute => "1m",
+            Tier::Hour => "1h",
+        }
+    }
+}
+
+/// Summary of the points of one series in one bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rollup {
+    pub start: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p95: f64,
+    pub count: u32,
+}
+
+impl Rollup {
+    fn from_values(start: DateTime<Utc>, values: &mut [f64]) -> Self {
+        values.sort_by(f64::total_cmp);
+        let count = values.len();
+        // Nearest rank
+        let p95 = values[(count * 95).div_ceil(100) - 1];
+
+        Self {
+            start,
+            min: values[0],
+            max: values[count - 1],
+            avg: values.iter().sum::<f64>() / count as f64,
+            p95,
+            count: count as u32,
+        }
+    }
+
+    // Combine two rollups of the same bucket, e.g. a partial one written at
+    // shutdown and the rest after a restart. The p95 can only be bounded.
+    pub(super) fn merge(&mut self, other: &Rollup) {
+        let total = (self.count + other.count).max(1) as f64;
+        self.avg = (self.avg * self.count as f64 + other.avg * other.count as f64) / total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.p95 = self.p95.max(other.p95);
+        self.count += other.count;
+    }
+
+    pub(super) fn stats(&self) -> [f64; 5] {
+        [self.min, self.max, self.avg, self.p95, self.count as f64]
+    }
+
+    pub(super) fn from_stats(start: DateTime<Utc>, stats: [f64; 5]) -> Self {
+        Self {
+            start,
+            min: stats[0],
+            max: stats[1],
+            avg: stats[2],
+            p95: stats[3],
+            count: stats[4] as u32,
+        }
+    }
+}
+
+/// Series a statistic of a tier is stored under, e.g. `cpu@10s.p95`.
+pub(super) fn stat_series(series: &SeriesKey, tier: Tier) -> [SeriesKey; 5] {
+    STATS.map(|stat| SeriesKey {
+        server_id: series.server_id,
+        metric: format!("{}@{}.{}", series.metric, tier.suffix(), stat),
+    })
+}
+
+pub(super) fn is_rollup(series: &SeriesKey) -> bool {
+    series.metric.contains('@')
+}
+
+/// Accumulates the open bucket of every series and tier as points arrive.
+///
+/// Points behind the open bucket, e.g. replayed from an agent's spool, are
+/// collected into a late bucket that is written as a partial rollup, which
+/// `Store::query_rollups` merges with what was written before.
+#[derive(Default)]
+pub(super) struct RollupBuilder {
+    open: HashMap<(SeriesKey, Tier), (i64, Vec<f64>)>,
+    late: HashMap<(SeriesKey, Tier), (i64, Vec<f64>)>,
+}
+
+impl RollupBuilder {
+    /// Add a point, returning the buckets it closed.
+    pub fn push(&mut self, series: &SeriesKey, ts: i64, value: f64) -> Vec<(SeriesKey, Tier, Rollup)> {
+        let mut closed = Vec::new();
+
+        for tier in Tier::ROLLUPS {
+            let start = ts.div_euclid(tier.bucket_ms()) * tier.bucket_ms();
+            let key = (series.clone(), tier);
+            let (open_start, values) = self.open.entry(key.clone()).or_insert_with(|| (start, Vec::new()));
+
+            if start < *open_start {
+                let (late_start, late_values) = self.late.entry(key).or_insert_with(|| (start, Vec::new()));
+                if start != *late_start {
+                    if let Some(rollup) = close(*late_start, late_values) {
+                        closed.push((series.clone(), tier, rollup));
+                    }
+                    *late_start = start;
+                }
+                late_values.push(value);
+                continue;
+            }
+            if start > *open_start {
+                if let Some(rollup) = close(*open_start, values) {
+                    closed.push((series.clone(), tier, rollup));
+                }
+                *open_start = start;
+
+                // Late points wait at most one bucket
+                if let Some((late_start, mut late_values)) = self.late.remove(&key) {
+                    closed.extend(close(late_start, &mut late_values).map(|rollup| (series.clone(), tier, rollup)));
+                }
+            }
+            values.push(value);
+        }
+
+        closed
+    }
+
+    /// Close every bucket, even the ones still filling, e.g. at shutdown.
+    pub fn drain(&mut self) -> Vec<(SeriesKey, Tier, Rollup)> {
+        self.open
+            .drain()
+            .chain(self.late.drain())
+            .filter_map(|((series, tier), (start, mut values))| Some((series, tier, close(start, &mut values)?)))
+            .collect()
+    }
+}
+
+fn close(start: i64, values: &mut Vec<f64>) -> Option<Rollup> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let rollup = Rollup::from_values(DateTime::from_timestamp_millis(start)?, values);
+    values.clear();
+    Some(rollup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: i64 = 1000;
+
+    fn series() -> SeriesKey {
+        SeriesKey {
+            server_id: 1,
+            metric: String::from("cpu"),
+        }
+    }
+
+    // Closed buckets of one tier, oldest first
+    fn of_tier(closed: Vec<(SeriesKey, Tier, Rollup)>, tier: Tier) -> Vec<Rollup> {
+        let mut rollups: Vec<Rollup> = closed.into_iter().filter(|c| c.1 == tier).map(|c| c.2).collect();
+        rollups.sort_by_key(|rollup| rollup.start);
+        rollups
+    }
+
+    #[test]
+    fn spans_pick_the_finest_readable_tier() {
+        assert_eq!(Tier::for_span(Duration::minutes(1)), Tier::Raw);
+        assert_eq!(Tier::for_span(RAW_SPAN), Tier::Raw);
+        assert_eq!(Tier::for_span(RAW_SPAN + Duration::seconds(1)), Tier::TenSeconds);
+        assert_eq!(Tier::for_span(Duration::seconds(10 * MAX_BUCKETS)), Tier::TenSeconds);
+        assert_eq!(Tier::for_span(Duration::seconds(10 * MAX_BUCKETS + 10)), Tier::Minute);
+        assert_eq!(Tier::for_span(Duration::minutes(MAX_BUCKETS + 1)), Tier::Hour);
+        assert_eq!(Tier::for_span(Duration::days(10_000)), Tier::Hour);
+    }
+
+    #[test]
+    fn buckets_close_when_a_point_of_the_next_one_arrives() {
+        let mut builder = RollupBuilder::default();
+        let mut closed = Vec::new();
+        for i in 0..20 {
+            closed.extend(builder.push(&series(), i * SECOND, i as f64));
+        }
+
+        // Only the first 10 s bucket is over
+        let rollups = of_tier(closed, Tier::TenSeconds);
+        assert_eq!(rollups.len(), 1);
+        let rollup = rollups[0];
+        assert_eq!(rollup.start, DateTime::from_timestamp_millis(0).unwrap());
+        assert_eq!((rollup.min, rollup.max, rollup.avg, rollup.p95, rollup.count), (0.0, 9.0, 4.5, 9.0, 10));
+
+        let drained = builder.drain();
+        assert_eq!(of_tier(drained.clone(), Tier::TenSeconds)[0].count, 10);
+        assert_eq!(of_tier(drained.clone(), Tier::Minute)[0].count, 20);
+        assert_eq!(of_tier(drained, Tier::Hour)[0].count, 20);
+    }
+
+    #[test]
+    fn late_points_become_a_partial_rollup_of_their_bucket() {
+        let mut builder = RollupBuilder::default();
+        let mut closed = Vec::new();
+        for ts in [0, 10 * SECOND, 3 * SECOND, 4 * SECOND, 20 * SECOND] {
+            closed.extend(builder.push(&series(), ts, ts as f64 / 1000.0));
+        }
+
+        // The late points of the first bucket are written once the second closes
+        let rollups = of_tier(closed, Tier::TenSeconds);
+        let first: Vec<(f64, u32)> = rollups.iter().filter(|r| r.start.timestamp() == 0).map(|r| (r.max, r.count)).collect();
+        assert_eq!(first, [(0.0, 1), (4.0, 2)]);
+
+        let mut merged = rollups[0];
+        merged.merge(&rollups[1]);
+        assert_eq!((merged.min, merged.max, merged.count), (0.0, 4.0, 3));
+        assert!((merged.avg - 7.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::codec;
+use super::SeriesKey;
+
+// Marks the start of every block, used to find the next one past corrupt bytes
+const BLOCK_MAGIC: [u8; 2] = *b"SB";
+// magic, server id, metric name length, point count, first/min/max ts, payload length
+const FIXED_HEADER: usize = 2 + 4 + 1 + 4 + 8 + 8 + 8 + 4;
+
+/// Where one compressed block of a series lives in its segment.
+#[derive(Debug, Clone, Copy)]
+struct BlockRef {
+    offset: u64, // start of the payload
+    length: u32,
+    count: u32,
+    first_ts: i64,
+    min_ts: i64,
+    max_ts: i64,
+}
+
+/// One append-only file holding the blocks of every series for a time span.
+///
+/// The file is a plain sequence of `header + payload` blocks. The per-series
+/// index is rebuilt from the headers when the segment is opened and extended
+/// by `scan` as blocks are appended, by this process or another. Corrupt
+/// bytes are skipped up to the next block that looks whole.
+pub struct Segment {
+    path: PathBuf,
+    scanned: u64,
+    index: HashMap<SeriesKey, Vec<BlockRef>>,
+    // Where corrupt bytes with no block after them start, reported once
+    corrupt_at: Option<u64>,
+}
+
+impl Segment {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut segment = Self {
+            path: path.to_path_buf(),
+            scanned: 0,
+            index: HashMap::new(),
+            corrupt_at: None,
+        };
+        segment.scan()?;
+        Ok(segment)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn series(&self) -> impl Iterator<Item = &SeriesKey> {
+        self.index.keys()
+    }
+
+    /// Index the blocks added since the last scan. A block that is only
+    /// partly on disk is left for the next scan.
+    pub fn scan(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let length = file.metadata()?.len();
+        file.seek(SeekFrom::Start(self.scanned))?;
+
+        let mut header = [0u8; FIXED_HEADER];
+        while self.scanned + FIXED_HEADER as u64 <= length {
+            file.read_exact(&mut header)?;
+            let Some(parsed) = Header::parse(&header) else {
+                match self.resync(&mut file, length)? {
+                    Some(next) => {
+                        eprintln!(
+                            "{}: skipped {} corrupt bytes at offset {}",
+                            self.path.display(),
+                            next - self.scanned,
+                            self.scanned
+                        );
+                        self.scanned = next;
+                        file.seek(SeekFrom::Start(next))?;
+                        continue;
+                    }
+                    None => {
+                        // Possibly a block still being written, looked at again on the next scan
+                        if self.corrupt_at != Some(self.scanned) {
+                            eprintln!(
+                                "{}: corrupt data at offset {}, no block found after it",
+                                self.path.display(),
+                                self.scanned
+                            );
+                            self.corrupt_at = Some(self.scanned);
+                        }
+                        break;
+                    }
+                }
+            };
+            let Header {
+                server_id,
+                name_length,
+                count,
+                first_ts,
+                min_ts,
+                max_ts,
+                payload_length,
+            } = parsed;
+
+            let offset = self.scanned + FIXED_HEADER as u64 + name_length as u64;
+            if offset + payload_length as u64 > length {
+                break;
+            }
+
+            let mut name = vec![0u8; name_length];
+            file.read_exact(&mut name)?;
+            file.seek(SeekFrom::Current(payload_length as i64))?;
+
+            let key = SeriesKey {
+                server_id,
+                metric: String::from_utf8_lossy(&name).into_owned(),
+            };
+            self.index.entry(key).or_default().push(BlockRef {
+                offset,
+                length: payload_length,
+                count,
+                first_ts,
+                min_ts,
+                max_ts,
+            });
+            self.scanned = offset + payload_length as u64;
+        }
+
+        Ok(())
+    }
+
+    // Offset of the next block after corrupt bytes at `scanned`: one whose
+    // header makes sense and that fits in the file
+    fn resync(&self, file: &mut File, length: u64) -> io::Result<Option<u64>> {
+        let mut rest = Vec::new();
+        file.seek(SeekFrom::Start(self.scanned + 1))?;
+        file.read_to_end(&mut rest)?;
+
+        let found = rest.windows(BLOCK_MAGIC.len()).enumerate().find_map(|(position, window)| {
+            if window != BLOCK_MAGIC {
+                return None;
+            }
+            let header = Header::parse(rest.get(position..position + FIXED_HEADER)?)?;
+            let start = self.scanned + 1 + position as u64;
+            let end = start + FIXED_HEADER as u64 + header.name_length as u64 + header.payload_length as u64;
+            (end <= length).then_some(start)
+        });
+        Ok(found)
+    }
+
+    /// Compress the points into one block at the end of the file.
+    pub fn append(&mut self, key: &SeriesKey, points: &[(i64, f64)]) -> io::Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        // Truncating would store the series under another name
+        let name = key.metric.as_bytes();
+        if name.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("metric name of {} bytes is too long to store", name.len()),
+            ));
+        }
+        let payload = codec::encode(points);
+        let min_ts = points.iter().map(|p| p.0).min().unwrap();
+        let max_ts = points.iter().map(|p| p.0).max().unwrap();
+
+        let mut block = Vec::with_capacity(FIXED_HEADER + name.len() + payload.len());
+        block.extend_from_slice(&BLOCK_MAGIC);
+        block.extend_from_slice(&key.server_id.to_le_bytes());
+        block.push(name.len() as u8);
+        block.extend_from_slice(&(points.len() as u32).to_le_bytes());
+        block.extend_from_slice(&points[0].0.to_le_bytes());
+        block.extend_from_slice(&min_ts.to_le_bytes());
+        block.extend_from_slice(&max_ts.to_le_bytes());
+        block.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        block.extend_from_slice(name);
+        block.extend_from_slice(&payload);
+
+        // Drop a partly written block left by a crash before appending after it
+        self.scan()?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        if file.metadata()?.len() > self.scanned {
+            file.set_len(self.scanned)?;
+        }
+
+        // One write per block, a crash leaves at most one torn block at the end
+        file.write_all(&block)?;
+
+        self.scan()
+    }
+
+    /// Points of a series between two timestamps (ms, inclusive), unsorted.
+    pub fn read(&self, key: &SeriesKey, from: i64, to: i64) -> io::Result<Vec<(i64, f64)>> {
+        let Some(blocks) = self.index.get(key) else {
+            return Ok(Vec::new());
+        };
+
+        let mut file = File::open(&self.path)?;
+        let mut points = Vec::new();
+        let mut payload = Vec::new();
+
+        for block in blocks.iter().filter(|b| b.max_ts >= from && b.min_ts <= to) {
+            payload.resize(block.length as usize, 0);
+            file.seek(SeekFrom::Start(block.offset))?;
+            file.read_exact(&mut payload)?;
+
+            // A corrupt block loses its own points, not the rest of the query
+            let Some(decoded) = codec::decode(&payload, block.first_ts, block.count as usize) else {
+                eprintln!("{}: undecodable block at offset {}", self.path.display(), block.offset);
+                continue;
+            };
+            points.extend(decoded.into_iter().filter(|(ts, _)| (from..=to).contains(ts)));
+        }
+
+        Ok(points)
+    }
+}
+
+// The fixed part of a block header
+struct Header {
+    server_id: u32,
+    name_length: usize,
+    count: u32,
+    first_ts: i64,
+    min_ts: i64,
+    max_ts: i64,
+    payload_length: u32,
+}
+
+impl Header {
+    // None unless the magic matches and the fields are consistent
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FIXED_HEADER || bytes[..2] != BLOCK_MAGIC {
+            return None;
+        }
+
+        let header = Self {
+            server_id: u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            name_length: bytes[6] as usize,
+            count: u32::from_le_bytes(bytes[7..11].try_into().unwrap()),
+            first_ts: i64::from_le_bytes(bytes[11..19].try_into().unwrap()),
+            min_ts: i64::from_le_bytes(bytes[19..27].try_into().unwrap()),
+            max_ts: i64::from_le_bytes(bytes[27..35].try_into().unwrap()),
+            payload_length: u32::from_le_bytes(bytes[35..39].try_into().unwrap()),
+        };
+        let consistent = header.name_length > 0
+            && header.count > 0
+            && header.payload_length > 0
+            && (header.min_ts..=header.max_ts).contains(&header.first_ts);
+        consistent.then_some(header)
+    }
+}
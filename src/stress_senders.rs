@@ -1,7 +1,9 @@
 // SERVER CODE (server.rs)
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
+use server_remote_dash::tls::ServerTls;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
+use tokio_rustls::TlsAcceptor;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -50,10 +52,18 @@ async fn main() {
 	// Create a broadcast channel for system stats
 	let (tx, _) = broadcast::channel::<(String, SystemStats)>(100);
 
+	// TLS when SRD_TLS_CERT and SRD_TLS_KEY are set, plain TCP otherwise
+	let tls = ServerTls::from_env().unwrap_or_else(|e| panic!("Invalid TLS settings: {}", e));
+	let acceptor = tls.map(|tls| match tls.config() {
+		Ok(config) => TlsAcceptor::from(config),
+		Err(e) => panic!("Failed to load TLS certificate and key: {}", e),
+	});
+
 	// Start TCP server
 	let listener = TcpListener::bind("0.0.0.0:7800").await.expect("Failed to bind to address");
-	println!("Server listening on 0.0.0.0:7800");
-	log::info!("Server listening on 0.0.0.0:7800");
+	let scheme = if acceptor.is_some() { "TLS" } else { "plain TCP" };
+	println!("Server listening on 0.0.0.0:7800 ({})", scheme);
+	log::info!("Server listening on 0.0.0.0:7800 ({})", scheme);
 
 	// Accept connections
 	loop {
@@ -65,10 +75,20 @@ async fn main() {
 				// Clone the shared state for this connection
 				let client_map = Arc::clone(&clients);
 				let tx_clone = tx.clone();
+				let acceptor = acceptor.clone();
 
 				// Spawn a task to handle this connection
 				tokio::spawn(async move {
-					handle_connection(socket, addr.to_string(), client_map, tx_clone).await;
+					match acceptor {
+						Some(acceptor) => match acceptor.accept(socket).await {
+							Ok(stream) => handle_connection(stream, addr.to_string(), client_map, tx_clone).await,
+							Err(e) => {
+								println!("TLS handshake with {} failed: {}", addr, e);
+								log::warn!("TLS handshake with {} failed: {}", addr, e);
+							}
+						},
+						None => handle_connection(socket, addr.to_string(), client_map, tx_clone).await,
+					}
 				});
 			}
 			Err(e) => {
@@ -79,13 +99,15 @@ async fn main() {
 	}
 }
 
-async fn handle_connection(
-	socket: TcpStream,
+async fn handle_connection<S>(
+	socket: S,
 	client_id: String,
 	clients: Arc<Mutex<HashMap<String, ClientInfo>>>,
 	tx: broadcast::Sender<(String, SystemStats)>
-) {
-	let (reader, mut writer) = socket.into_split();
+) where
+	S: AsyncRead + AsyncWrite + Send + 'static,
+{
+	let (reader, mut writer) = tokio::io::split(socket);
 	let mut reader = BufReader::new(reader);
 	let mut line = String::new();
 
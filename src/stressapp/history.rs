@@ -0,0 +1,102 @@
+//! Store queries for the charts, run on their own thread so reading segments
+//! never holds up the dashboard.
+
+use super::util_chart::History;
+use crate::protocol::Metric;
+use crate::store::rollup::Tier;
+use crate::store::SharedStore;
+use chrono::{DateTime, Utc};
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// The points one chart needs: raw ones for `Tier::Raw`, rollups otherwise.
+#[derive(Debug, Clone)]
+pub struct HistoryQuery {
+    pub server_id: u32,
+    pub metric: Metric,
+    pub tier: Tier,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+type Answer = io::Result<Vec<(HistoryQuery, History)>>;
+
+/// Answers one batch of queries at a time against a store, which may be the
+/// one the collector is appending to.
+pub struct HistoryLoader {
+    requests: Sender<Vec<HistoryQuery>>,
+    answers: Receiver<Answer>,
+    busy: bool,
+}
+
+impl HistoryLoader {
+    pub fn spawn(store: SharedStore) -> Self {
+        let (requests, queries) = mpsc::channel::<Vec<HistoryQuery>>();
+        let (answer, answers) = mpsc::channel();
+
+        // Stops once the loader is dropped
+        thread::spawn(move || {
+            for batch in queries {
+                if answer.send(load(&store, batch)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            answers,
+            busy: false,
+        }
+    }
+
+    /// Whether a batch is still being answered.
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    pub fn request(&mut self, queries: Vec<HistoryQuery>) {
+        self.busy = self.requests.send(queries).is_ok();
+    }
+
+    /// The answer to the last request, once it is there.
+    pub fn poll(&mut self) -> Option<Answer> {
+        if !self.busy {
+            return None;
+        }
+
+        match self.answers.try_recv() {
+            Ok(answer) => {
+                self.busy = false;
+                Some(answer)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.busy = false;
+                Some(Err(io::Error::other("history thread stopped")))
+            }
+        }
+    }
+}
+
+fn load(store: &SharedStore, queries: Vec<HistoryQuery>) -> Answer {
+    // Blocks written by the collector since the last batch
+    store.lock().unwrap().refresh()?;
+
+    let mut answers = Vec::with_capacity(queries.len());
+    for query in queries {
+        // Locked per query so a collector appending to the store isn't held up for the batch
+        let store = store.lock().unwrap();
+        let history = match query.tier {
+            Tier::Raw => {
+                let points = store.query(query.server_id, &query.metric, query.from, query.to)?;
+                History::Raw(points.into_iter().map(|(t, v)| (t, v as f32)).collect())
+            }
+            tier => History::Rollups(store.query_rollups(query.server_id, &query.metric, tier, query.from, query.to)?),
+        };
+        answers.push((query, history));
+    }
+
+    Ok(answers)
+}
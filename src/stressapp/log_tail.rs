@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Saved next to the logs so a restarted dashboard resumes where it stopped
+pub const CURSOR_FILE: &str = ".monitor_cursor.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileCursor {
+    inode: u64,
+    offset: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CursorFile {
+    #[serde(default)]
+    files: HashMap<String, FileCursor>,
+}
+
+/// Follows every `.log` file in a directory without modifying them.
+///
+/// Each file has a byte offset of how far it has been read. Only complete
+/// lines are consumed, so a line the collector is still writing is picked
+/// up on the next poll. A file whose inode changed or that shrank was
+/// rotated or replaced and is read again from the start.
+///
+/// Without a saved cursor the files already there are skipped, their
+/// contents are history the charts load from the store.
+pub struct LogTailer {
+    directory: PathBuf,
+    cursors: HashMap<String, FileCursor>,
+    // Until the first poll, files without a cursor start at their end
+    skip_existing: bool,
+}
+
+impl LogTailer {
+    pub fn new(directory: &Path) -> Self {
+        let cursors = fs::read_to_string(directory.join(CURSOR_FILE))
+            .ok()
+            .and_then(|contents| toml::from_str::<CursorFile>(&contents).ok())
+            .map(|file| file.files);
+
+        Self {
+            directory: directory.to_path_buf(),
+            skip_existing: cursors.is_none(),
+            cursors: cursors.unwrap_or_default(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Read the lines appended to any log file since the last poll.
+    pub fn poll(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let mut seen = Vec::new();
+        let mut changed = false;
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && path.extension() == Some("log".as_ref()))
+            .collect();
+        // Oldest day first, so replays stay in order
+        paths.sort();
+
+        for path in paths {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()).map(str::to_string) else {
+                continue;
+            };
+
+            match self.read_new_lines(&path, &name, &mut lines) {
+                Ok(advanced) => changed |= advanced,
+                Err(e) => eprintln!("Error reading {}: {}", path.display(), e),
+            }
+            seen.push(name);
+        }
+
+        // Files appearing from now on are read from the start
+        self.skip_existing = false;
+
+        // Forget files that were deleted
+        let before = self.cursors.len();
+        self.cursors.retain(|name, _| seen.contains(name));
+        changed |= self.cursors.len() != before;
+
+        if changed && let Err(e) = self.save() {
+            eprintln!("Failed to save log cursor: {}", e);
+        }
+
+        Ok(lines)
+    }
+
+    fn read_new_lines(&mut self, path: &Path, name: &str, lines: &mut Vec<String>) -> io::Result<bool> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        let mut cursor = match self.cursors.get(name) {
+            Some(cursor) if cursor.inode == metadata.ino() && cursor.offset <= metadata.len() => *cursor,
+            None if self.skip_existing => FileCursor {
+                inode: metadata.ino(),
+                offset: end_of_last_line(&mut file, metadata.len())?,
+            },
+            // New, rotated or truncated file
+            _ => FileCursor {
+                inode: metadata.ino(),
+                offset: 0,
+            },
+        };
+        if cursor.offset == metadata.len() && self.cursors.get(name) == Some(&cursor) {
+            return Ok(false);
+        }
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(cursor.offset))?;
+
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            let read = reader.read_until(b'\n', &mut buffer)?;
+            // Stop at end of file or at a line that is still being written
+            if read == 0 || buffer.last() != Some(&b'\n') {
+                break;
+            }
+
+            cursor.offset += read as u64;
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+
+        self.cursors.insert(name.to_string(), cursor);
+        Ok(true)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let file = CursorFile {
+            files: self.cursors.clone(),
+        };
+        let contents = toml::to_string(&file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        // Write then rename so a crash never leaves a half written cursor
+        let path = self.directory.join(CURSOR_FILE);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(temp, path)
+    }
+}
+
+// Offset just past the last complete line, so a line still being written
+// is read whole once it is finished
+fn end_of_last_line(file: &mut File, length: u64) -> io::Result<u64> {
+    const CHUNK: u64 = 4096;
+    let mut end = length;
+    let mut buffer = Vec::new();
+
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        buffer.resize((end - start) as usize, 0);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buffer)?;
+        if let Some(position) = buffer.iter().rposition(|b| *b == b'\n') {
+            return Ok(start + position as u64 + 1);
+        }
+        end = start;
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-tail-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn append(&self, name: &str, contents: &str) {
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(self.0.join(name)).unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn without_a_cursor_only_new_lines_are_read() {
+        let dir = TempDir::new("no-cursor");
+        dir.append("a.log", "old 1\nold 2\npart");
+
+        let mut tailer = LogTailer::new(&dir.0);
+        assert_eq!(tailer.poll().unwrap(), Vec::<String>::new());
+
+        // The line being written when the tailer started is read whole
+        dir.append("a.log", "ial\nnew\n");
+        dir.append("b.log", "first\n");
+        assert_eq!(tailer.poll().unwrap(), ["partial", "new", "first"]);
+        assert_eq!(tailer.poll().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn a_restarted_tailer_resumes_from_its_cursor() {
+        let dir = TempDir::new("restore");
+        let mut tailer = LogTailer::new(&dir.0);
+        tailer.poll().unwrap();
+        dir.append("a.log", "one\n");
+        assert_eq!(tailer.poll().unwrap(), ["one"]);
+        drop(tailer);
+
+        // Written while the dashboard was closed
+        dir.append("a.log", "two\nthree\n");
+        let mut tailer = LogTailer::new(&dir.0);
+        assert_eq!(tailer.poll().unwrap(), ["two", "three"]);
+    }
+
+    #[test]
+    fn truncated_files_are_read_from_the_start() {
+        let dir = TempDir::new("truncate");
+        let mut tailer = LogTailer::new(&dir.0);
+        tailer.poll().unwrap();
+        dir.append("a.log", "one\ntwo\n");
+        assert_eq!(tailer.poll().unwrap(), ["one", "two"]);
+
+        fs::write(dir.0.join("a.log"), "3\n").unwrap();
+        assert_eq!(tailer.poll().unwrap(), ["3"]);
+    }
+
+    #[test]
+    fn rotated_files_are_read_from_the_start() {
+        let dir = TempDir::new("rotate");
+        let mut tailer = LogTailer::new(&dir.0);
+        tailer.poll().unwrap();
+        dir.append("a.log", "one\n");
+        assert_eq!(tailer.poll().unwrap(), ["one"]);
+
+        // Moved out of the directory and replaced by a longer file
+        fs::rename(dir.0.join("a.log"), dir.0.join("a.old")).unwrap();
+        dir.append("a.log", "two\nthree\n");
+        assert_eq!(tailer.poll().unwrap(), ["two", "three"]);
+    }
+}
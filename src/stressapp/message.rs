@@ -1,40 +1,48 @@
-use chrono::{DateTime, NaiveTime, Utc};
+use super::replay::ReplayMessage;
+use super::util_chart::ChartMessage;
+use crate::gui_connection::liveness::Liveness;
+use crate::protocol::{parse_frame, Frame, Metric, MetricRecord};
+use chrono::{DateTime, Utc};
 
 #[derive(Debug, Clone)]
 pub struct BasicMessage {
-    pub server_id: u8,
-    pub stress_tester: u8,
-    pub percentage: f32,
+    pub server_id: u32,
+    pub metric: Metric,
+    pub value: f32,
+    pub unit: String,
     pub timestamp: DateTime<Utc>,
 }
 
-pub fn parse_message(input: &str) -> Option<BasicMessage> {
-    // Parse format: [0-2]-[0-4]-[float<100]-hh:mm:ss
-    let parts: Vec<&str> = input.split('-').collect();
-    if parts.len() != 4 {
-        return None;
+impl From<MetricRecord> for BasicMessage {
+    fn from(record: MetricRecord) -> Self {
+        Self {
+            server_id: record.server_id,
+            metric: record.metric,
+            value: record.value as f32,
+            unit: record.unit,
+            timestamp: record.timestamp,
+        }
     }
+}
 
-    let server_id = parts[0].parse::<u8>().ok()?;
-    let stress_tester = parts[1].parse::<u8>().ok()?;
-    let percentage = parts[2].parse::<f32>().ok()?;
-    let timestamp = NaiveTime::parse_from_str(parts[3], "%H:%M:%S").ok()?;
-
-    // Validate ranges
-    if server_id > 2 || stress_tester > 4 || percentage < 0.0 || percentage > 100.0 {
-        return None;
+pub fn parse_message(input: &str) -> Option<BasicMessage> {
+    // Accepts both v2 METRIC records and legacy server-metric-value-hh:mm:ss lines
+    match parse_frame(input)? {
+        Frame::Metric(record) => Some(record.into()),
+        Frame::Hello(_) | Frame::Heartbeat(_) => None,
     }
-
-    Some(BasicMessage {
-        server_id,
-        stress_tester,
-        percentage,
-        timestamp: Utc::now().date_naive().and_time(timestamp).and_utc(),
-    })
 }
 
 #[derive(Debug, Clone)]
 pub enum AppMessage {
     NewDataPoint(BasicMessage),
+    ServerIdentified(u32, String), // server_id and its label
+    ServerLiveness(u32, Liveness), // reported by the collector
+    ServerDropped(u32, u64), // records the collector dropped from a server so far
+    Replay(ReplayMessage),
+    Chart(u32, Metric, ChartMessage), // one chart of a server
+    AllCharts(ChartMessage),
+    CustomWindowChanged(String), // minutes typed for a custom window
     Tick,
+    CloseRequested, // the window is closing, stop the collector first
 }
@@ -1,4 +1,7 @@
+pub mod history;
+pub mod log_tail;
 pub mod message;
 pub mod monitor_chart;
+pub mod replay;
 pub mod server_chart;
 pub mod util_chart;
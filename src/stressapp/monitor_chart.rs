@@ -1,43 +1,255 @@
 use std::time::{Duration, Instant};
 
 use iced::{
-    alignment::{Horizontal, Vertical}, widget::{Column, Space, Text}, Alignment,
+    alignment::{Horizontal, Vertical}, widget::{container, Column, Container, Row, Space, Text}, Alignment,
+    Color,
     Element,
     Length,
 };
 
 use super::{
+    history::{HistoryLoader, HistoryQuery},
     message::{AppMessage, BasicMessage},
     server_chart::ServerChart,
+    util_chart::{ChartMessage, ChartSettings, History},
 };
-use crate::stressapp::message::parse_message;
+use crate::alerts::rules::AlertRule;
+use crate::alerts::{AlertEngine, AlertEvent, AlertState};
+use crate::gui_connection::liveness::{Liveness, LivenessConfig, LivenessTracker};
+use crate::gui_connection::log_format::parse_log_line;
+use crate::gui_connection::registry::{ServerRegistry, REGISTRY_FILE};
+use crate::protocol::Metric;
+use crate::store::rollup::Tier;
+use crate::store::{SharedStore, Store};
+use crate::stressapp::log_tail::LogTailer;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
 use std::path::Path;
-use std::{fs, fs::{File, OpenOptions}, io::{self, BufRead, Write}};
+use std::time::SystemTime;
 
 const SAMPLE_EVERY: Duration = Duration::from_millis(1000);
+// History loaded from the store when the dashboard starts
+const STARTUP_HISTORY: chrono::Duration = chrono::Duration::seconds(60);
 
 pub struct MonitorChart {
     //holds the server charts
-    servers: Vec<(u8, ServerChart)>,
+    servers: Vec<(u32, ServerChart)>,
+    //server labels announced by the collector
+    labels: HashMap<u32, String>,
     last_sample_time: Instant,
-    directory: String,
+    //follows the log directory, None when fed live by the collector
+    tailer: Option<LogTailer>,
+    //queries the metrics kept by the collector, for history
+    history: Option<HistoryLoader>,
+    //set when the visible ranges changed or a rollup bucket closed
+    history_stale: bool,
+    //when history was last asked for
+    history_requested: DateTime<Utc>,
+    //modification time of the collector's registry when its labels were read
+    registry_modified: Option<SystemTime>,
+    //settings new charts start with
+    settings: ChartSettings,
+    alerts: AlertEngine,
+    //alerts currently firing, oldest first
+    firing: Vec<AlertEvent>,
+    //up/stale/down per server, None where it means nothing (replays)
+    liveness: Option<LivenessTracker>,
+    //records the collector dropped per server, over rate limits or while behind
+    dropped: HashMap<u32, u64>,
 }
 
 impl Default for MonitorChart {
     fn default() -> Self {
-        let mut test = Self {
+        Self {
             last_sample_time: Instant::now(),
             servers: Default::default(),
-            directory: String::new() + "tcp_logs",
+            labels: Default::default(),
+            tailer: None,
+            history: None,
+            history_stale: false,
+            history_requested: Utc::now(),
+            registry_modified: None,
+            settings: ChartSettings::default(),
+            alerts: AlertEngine::default(),
+            firing: Vec::new(),
+            liveness: None,
+            dropped: HashMap::new(),
+        }
+    }
+}
+
+impl MonitorChart {
+    pub fn with_directory(directory: &str) -> Self {
+        let mut chart = Self {
+            tailer: Some(LogTailer::new(Path::new(directory))),
+            ..Default::default()
         };
 
-        test.update();
+        chart.update();
 
-        return test;
+        chart
+    }
+
+    /// Evaluate these alert rules on every message the charts receive.
+    pub fn with_alerts(mut self, rules: Vec<AlertRule>) -> Self {
+        self.alerts = AlertEngine::new(rules);
+        self
+    }
+
+    /// Show whether each server is still sending, going by when its data
+    /// arrives and the states reported by the collector.
+    pub fn with_liveness(mut self, config: LivenessConfig) -> Self {
+        self.liveness = Some(LivenessTracker::new(config));
+        self
+    }
+
+    pub fn set_liveness(&mut self, server_id: u32, state: Liveness) {
+        if let Some(tracker) = &mut self.liveness {
+            tracker.set(server_id, state, Utc::now());
+        }
+        if state == Liveness::Down {
+            self.server_down(server_id);
+        }
+    }
+
+    // No more samples will resolve what was firing for the server
+    fn server_down(&mut self, server_id: u32) {
+        for event in self.alerts.server_down(server_id, Utc::now()) {
+            self.handle_alert(event);
+        }
+    }
+
+    pub fn set_dropped(&mut self, server_id: u32, dropped: u64) {
+        self.dropped.insert(server_id, dropped);
+    }
+
+    pub fn alert_rules(&self) -> &[AlertRule] {
+        self.alerts.rules()
+    }
+
+    /// Query history from the store, starting with what the charts show.
+    pub fn with_store(mut self, store: SharedStore) -> Self {
+        let now = Utc::now();
+        let loaded = self.load_range(&mut store.lock().unwrap(), now - STARTUP_HISTORY, now);
+        if let Err(e) = loaded {
+            eprintln!("Error loading history from store: {}", e);
+        }
+
+        self.history = Some(HistoryLoader::spawn(store));
+        self
+    }
+
+    // Feed every series the store has between two times to the charts
+    fn load_range(&mut self, store: &mut Store, from: DateTime<Utc>, to: DateTime<Utc>) -> io::Result<()> {
+        // Blocks written by the collector since the last query
+        store.refresh()?;
+
+        let mut messages = Vec::new();
+        for series in store.series() {
+            let metric = series.metric();
+            let unit = store.unit(series.server_id, &metric).unwrap_or_default().to_string();
+            for (timestamp, value) in store.query(series.server_id, &metric, from, to)? {
+                messages.push(BasicMessage {
+                    server_id: series.server_id,
+                    metric: metric.clone(),
+                    value: value as f32,
+                    unit: unit.clone(),
+                    timestamp,
+                });
+            }
+        }
+
+        for msg in messages {
+            self.send_message(msg);
+        }
+
+        Ok(())
+    }
+
+    // Charts whose window goes beyond the raw points in memory get the rest
+    // from the store: rollups for long windows, raw points for past views
+    fn request_history(&mut self) {
+        let Some(loader) = self.history.as_mut() else {
+            return;
+        };
+
+        let mut queries = Vec::new();
+        for (server_id, server) in &mut self.servers {
+            for (metric, chart) in server.charts_mut() {
+                if chart.tier() == Tier::Raw && chart.is_following() {
+                    // Back to the points in memory
+                    if chart.has_history() {
+                        chart.set_history(None);
+                    }
+                    continue;
+                }
+
+                let (from, to) = chart.visible_range();
+                queries.push(HistoryQuery {
+                    server_id: *server_id,
+                    metric: metric.clone(),
+                    tier: chart.tier(),
+                    from,
+                    to,
+                });
+            }
+        }
+
+        if !queries.is_empty() {
+            loader.request(queries);
+        }
+        self.history_requested = Utc::now();
+    }
+
+    // Whether a live chart showing rollups has a bucket that closed since
+    // history was last asked for
+    fn rollup_closed(&self, now: DateTime<Utc>) -> bool {
+        let bucket_index = |time: DateTime<Utc>, bucket: chrono::Duration| time.timestamp_millis().div_euclid(bucket.num_milliseconds());
+
+        self.servers
+            .iter()
+            .flat_map(|(_, server)| server.charts())
+            .filter(|(_, chart)| chart.is_following())
+            .filter_map(|(_, chart)| chart.tier().bucket())
+            .any(|bucket| bucket_index(now, bucket) != bucket_index(self.history_requested, bucket))
+    }
+
+    fn apply_history(&mut self, answers: Vec<(HistoryQuery, History)>) {
+        for (query, history) in answers {
+            let Some((_, server)) = self.servers.iter_mut().find(|(id, _)| *id == query.server_id) else {
+                continue;
+            };
+            // Skipped if the chart went back to live data in the meantime
+            if let Some((_, chart)) = server.charts_mut().find(|(metric, _)| **metric == query.metric)
+                && !(chart.tier() == Tier::Raw && chart.is_following())
+            {
+                chart.set_history(Some(history));
+            }
+        }
+    }
+
+    pub fn settings(&self) -> ChartSettings {
+        self.settings
+    }
+
+    pub fn handle_chart_message(&mut self, server_id: u32, metric: &Metric, message: ChartMessage) {
+        if let Some((_, server)) = self.servers.iter_mut().find(|(id, _)| *id == server_id) {
+            server.handle_message(metric, message);
+        }
+        self.history_stale = true;
+    }
+
+    /// Apply a message to every chart, e.g. from the global window selection.
+    pub fn handle_message_all(&mut self, message: ChartMessage) {
+        self.settings.apply(&message);
+        for (_, server) in &mut self.servers {
+            server.handle_message_all(message.clone());
+        }
+        self.history_stale = true;
     }
-}
 
-impl MonitorChart {
     #[inline]
     fn is_initialized(&self) -> bool {
         !self.servers.is_empty()
@@ -50,27 +262,138 @@ impl MonitorChart {
 
     pub fn send_message(&mut self, msg: BasicMessage) {
         // Add any new server or update existing servers
-        if !self.servers.iter().any(|e| e.0 == msg.stress_tester) {
-            let new_server = ServerChart::default();
+        if !self.servers.iter().any(|e| e.0 == msg.server_id) {
+            let new_server = ServerChart::with_settings(self.settings, self.label(msg.server_id));
             self.servers.push((msg.server_id, new_server));
         }
 
+        // Aged against the local clock, so the agent's clock can't make it
+        // look stale
+        if let Some(tracker) = &mut self.liveness {
+            tracker.seen(msg.server_id, Utc::now());
+        }
+
+        let label = self.label(msg.server_id);
+        for event in self.alerts.evaluate(msg.server_id, &label, &msg.metric, msg.value as f64, msg.timestamp) {
+            self.handle_alert(event);
+        }
+
         for (server_id, server) in &mut self.servers {
             if *server_id == msg.server_id {
+                // A new chart may need history for its window
+                self.history_stale |= !server.charts().any(|(metric, _)| *metric == msg.metric);
                 server.add_message(msg.clone());
             }
         }
     }
 
+    fn handle_alert(&mut self, event: AlertEvent) {
+        let same_alert = |e: &AlertEvent| e.rule == event.rule && e.server_id == event.server_id;
+        match event.state {
+            AlertState::Firing => {
+                self.firing.retain(|e| !same_alert(e));
+                self.firing.push(event.clone());
+            }
+            AlertState::Resolved | AlertState::Stale => self.firing.retain(|e| !same_alert(e)),
+        }
+
+        // A chart stays highlighted while any rule on it fires
+        let alerting = self
+            .firing
+            .iter()
+            .any(|e| e.server_id == event.server_id && e.metric == event.metric);
+        if let Some((_, server)) = self.servers.iter_mut().find(|(id, _)| *id == event.server_id) {
+            server.set_alert(&event.metric, alerting);
+        }
+    }
+
+    // Banner listing the alerts that are firing
+    fn alert_banner(&self) -> Option<Element<'_, AppMessage>> {
+        if self.firing.is_empty() {
+            return None;
+        }
+
+        let mut col = Column::new().spacing(4);
+        for event in &self.firing {
+            col = col.push(Text::new(format!(
+                "{}: {} {} at {:.1} ({} {}) since {}",
+                event.rule,
+                event.server,
+                event.metric,
+                event.value,
+                event.comparator,
+                event.threshold,
+                event.since.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+            )));
+        }
+
+        let banner = Container::new(col)
+            .padding(10)
+            .width(Length::Fill)
+            .style(|_| container::Style {
+                background: Some(Color::from_rgb(0.8, 0.15, 0.15).into()),
+                text_color: Some(Color::WHITE),
+                ..Default::default()
+            });
+        Some(banner.into())
+    }
+
+    pub fn set_label(&mut self, server_id: u32, label: String) {
+        if self.labels.get(&server_id) == Some(&label) {
+            return;
+        }
+        if let Some((_, server)) = self.servers.iter_mut().find(|(id, _)| *id == server_id) {
+            server.set_label(label.clone());
+        }
+        self.labels.insert(server_id, label);
+    }
+
+    fn label(&self, server_id: u32) -> String {
+        match self.labels.get(&server_id) {
+            Some(label) => label.clone(),
+            None => format!("Server {}", server_id),
+        }
+    }
+
     pub fn update(&mut self) {
-        println!("Running");
+        if self.should_update() {
+            // Process files in the directory when not fed live
+            if self.tailer.is_some()
+                && let Err(e) = self.read_files_in_directory()
+            {
+                eprintln!("Error reading files from directory: {}", e);
+            }
 
-        // Process files in the directory
-        if let Err(e) = self.read_files_in_directory() {
-            eprintln!("Error reading files from directory: {}", e);
+            self.history_stale |= self.rollup_closed(Utc::now());
+            self.last_sample_time = Instant::now();
         }
 
-        self.last_sample_time = Instant::now();
+        let went_down: Vec<u32> = match &mut self.liveness {
+            Some(tracker) => tracker
+                .check(Utc::now())
+                .into_iter()
+                .filter(|(_, state)| *state == Liveness::Down)
+                .map(|(server_id, _)| server_id)
+                .collect(),
+            None => Vec::new(),
+        };
+        for server_id in went_down {
+            self.server_down(server_id);
+        }
+
+        if let Some(answer) = self.history.as_mut().and_then(HistoryLoader::poll) {
+            match answer {
+                Ok(answers) => self.apply_history(answers),
+                Err(e) => eprintln!("Error loading history from store: {}", e),
+            }
+        }
+
+        // Right after a zoom or pan as well, so the view doesn't lag behind.
+        // Asked again once the store has answered the batch before.
+        if self.history_stale && !self.history.as_ref().is_some_and(HistoryLoader::is_busy) {
+            self.request_history();
+            self.history_stale = false;
+        }
 
         for (_, server) in &mut self.servers {
             server.update();
@@ -87,85 +410,105 @@ impl MonitorChart {
                 .height(Length::Shrink)
                 .align_x(Alignment::Center);
 
+            if let Some(banner) = self.alert_banner() {
+                col = col.push(banner);
+            }
+
             for (id, server) in &self.servers {
-                col = col.push(Text::new(format!("Server {}", id)));
-                col = col.push(server.view());
+                let mut title = Row::new().spacing(10).align_y(Alignment::Center).push(Text::new(self.label(*id)));
+                if let Some(state) = self.liveness.as_ref().and_then(|tracker| tracker.state(*id)) {
+                    title = title.push(liveness_badge(state));
+                }
+                if let Some(dropped) = self.dropped.get(id).filter(|dropped| **dropped > 0) {
+                    title = title.push(Text::new(format!("{} records dropped", dropped)).size(12).color(Color::from_rgb(0.8, 0.15, 0.15)));
+                }
+                col = col.push(title);
+                let server_id = *id;
+                col = col.push(server.view().map(move |(metric, message)| AppMessage::Chart(server_id, metric, message)));
                 col = col.push(Space::new(Length::Fixed(50.0), Length::Fill));
             }
 
-            Element::new(col).into()
+            Element::new(col)
         }
     }
 
-    pub fn read_and_process_file(&mut self, file_path: &str) -> io::Result<()> {
-        // Open the file for reading and writing
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(file_path)?;
-        let mut lines = io::BufReader::new(file).lines();
+    fn read_files_in_directory(&mut self) -> io::Result<()> {
+        let Some(tailer) = self.tailer.as_mut() else {
+            return Ok(());
+        };
 
-        // Check if there is a line to read
-        if let Some(Ok(line)) = lines.next() {
-            // Parse the message from the line
-            if let Some(msg) = parse_message(&line) {
-                // Send the parsed message
-                self.send_message(msg);
+        // Pick up the labels of servers the collector has registered, when
+        // it changed the registry
+        let registry_path = tailer.directory().join(REGISTRY_FILE);
+        let modified = fs::metadata(&registry_path).and_then(|metadata| metadata.modified()).ok();
+        let registry = (modified != self.registry_modified).then(|| ServerRegistry::load_read_only(&registry_path));
 
-                // Get the file's content and remove the first line
-                let remaining_lines: Vec<String> = lines.filter_map(Result::ok).collect();
+        // Only the lines appended since the last poll, the logs are left untouched
+        let lines = tailer.poll()?;
 
-                // Rewrite the file with the remaining lines (removes the first line)
-                let mut file = File::create(file_path)?;
-                for remaining_line in remaining_lines {
-                    writeln!(file, "{}", remaining_line)?;
-                }
+        if let Some(registry) = registry {
+            for server in registry.servers() {
+                self.set_label(server.id, server.label.clone());
+            }
+            self.registry_modified = modified;
+        }
+        for line in lines {
+            if let Some(record) = parse_log_line(&line) {
+                self.send_message(record.into());
             }
         }
 
         Ok(())
     }
+}
 
-    fn read_file(&mut self, path: &Path) -> io::Result<()> {
-        let file = File::open(path)?;
-        let reader = io::BufReader::new(file);
+// Colored label of a server's state, shown next to its name
+fn liveness_badge<'a>(state: Liveness) -> Element<'a, AppMessage> {
+    let color = match state {
+        Liveness::Up => Color::from_rgb(0.2, 0.6, 0.25),
+        Liveness::Stale => Color::from_rgb(0.85, 0.6, 0.1),
+        Liveness::Down => Color::from_rgb(0.8, 0.15, 0.15),
+    };
 
-        for line in reader.lines() {
-            if let Ok(message) = line {
-                if let Some(parsed_message) = parse_message(&message) {
-                    self.send_message(parsed_message);
-                }
-            }
-        }
+    Container::new(Text::new(state.to_string().to_uppercase()).size(12))
+        .padding([2, 8])
+        .style(move |_| container::Style {
+            background: Some(color.into()),
+            text_color: Some(Color::WHITE),
+            border: iced::Border::default().rounded(4),
+            ..Default::default()
+        })
+        .into()
+}
 
-        Ok(())
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stressapp::util_chart::TimeWindow;
 
-    fn clear_file(&self, path: &Path) -> io::Result<()> {
-        // Clear the contents of the file after processing
-        std::fs::write(path, "")?;
-        Ok(())
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
     }
 
-    fn read_files_in_directory(&mut self) -> io::Result<()> {
-        // Read all files in the directory
-        let entries = fs::read_dir(&self.directory)?;
-        println!("Reading files from directory: {}", self.directory);
-
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-
-            println!("Loading {}", path.display());
-            // Check if it's a file and has a .txt extension
-            if path.is_file() && path.extension() == Some("log".as_ref()) {
-                // Process the file
-                self.read_file(&path)?;
-                // Clear the file after reading
-                self.clear_file(&path)?;
-            }
-        }
+    #[test]
+    fn live_rollup_charts_need_history_once_a_bucket_closes() {
+        let mut chart = MonitorChart::default();
+        chart.send_message(BasicMessage {
+            server_id: 1,
+            metric: Metric::Cpu,
+            value: 10.0,
+            unit: String::from("%"),
+            timestamp: Utc::now(),
+        });
+        chart.update();
+        chart.history_requested = at("2025-03-17T12:00:01Z");
 
-        Ok(())
+        // Raw points come from memory
+        assert!(!chart.rollup_closed(at("2025-03-17T12:05:00Z")));
+
+        // Ten second buckets
+        chart.handle_message_all(ChartMessage::SetWindow(TimeWindow::OneHour));
+        assert!(!chart.rollup_closed(at("2025-03-17T12:00:09Z")));
+        assert!(chart.rollup_closed(at("2025-03-17T12:00:10Z")));
     }
 }
@@ -0,0 +1,397 @@
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::gui_connection::log_format::{parse_log_line, LogRecord};
+use crate::protocol::{MetricRecord, MAX_CLOCK_SKEW, MAX_RECORD_AGE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaySpeed {
+    X1,
+    X10,
+    X100,
+}
+
+impl ReplaySpeed {
+    pub const ALL: [ReplaySpeed; 3] = [ReplaySpeed::X1, ReplaySpeed::X10, ReplaySpeed::X100];
+
+    pub fn factor(&self) -> i32 {
+        match self {
+            ReplaySpeed::X1 => 1,
+            ReplaySpeed::X10 => 10,
+            ReplaySpeed::X100 => 100,
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x", self.factor())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplayMessage {
+    FromChanged(String),
+    ToChanged(String),
+    Load,
+    TogglePause,
+    SetSpeed(ReplaySpeed),
+    Seek(f64), // seconds from the start of the recording
+    Step,
+    Exit,
+}
+
+/// Plays back the records of the collector logs between two days.
+///
+/// Replay time only moves forward while playing, by the wall clock time
+/// since the last `advance` times the speed. Every record up to the replay
+/// time is handed out once, in timestamp order.
+///
+/// Loading only indexes where each record is, records are read from the
+/// files as they are played.
+pub struct Replay {
+    files: Vec<LogFile>,
+    index: Vec<IndexEntry>, // in timestamp order
+    labels: HashMap<u32, String>,
+    position: usize,
+    clock: DateTime<Utc>,
+    speed: ReplaySpeed,
+    paused: bool,
+    last_advance: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IndexEntry {
+    timestamp: DateTime<Utc>,
+    file: u32,
+    offset: u64,
+}
+
+struct LogFile {
+    path: PathBuf,
+    // Opened on the first read, with the offset it is at
+    reader: Option<(BufReader<File>, u64)>,
+}
+
+impl LogFile {
+    fn read(&mut self, offset: u64) -> io::Result<Option<MetricRecord>> {
+        let (reader, position) = match &mut self.reader {
+            Some(reader) => reader,
+            None => self.reader.insert((BufReader::new(File::open(&self.path)?), 0)),
+        };
+        // Records of a file are mostly played in the order they were written
+        if *position != offset {
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        let mut line = String::new();
+        *position = offset + reader.read_line(&mut line)? as u64;
+        Ok(parse_log_line(&line))
+    }
+}
+
+impl Replay {
+    /// Index every record timestamped `from` to `to`, both inclusive.
+    ///
+    /// Files are dated by when the collector received their records, which
+    /// an agent replaying its spool may do up to `MAX_RECORD_AGE` later.
+    pub fn load(directory: &Path, from: NaiveDate, to: NaiveDate) -> io::Result<Self> {
+        let start = from.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let end = to.and_hms_opt(0, 0, 0).unwrap().and_utc() + ChronoDuration::days(1);
+        let first_day = (start - MAX_CLOCK_SKEW).date_naive();
+        let last_day = (end + MAX_RECORD_AGE).date_naive();
+
+        let mut files = Vec::new();
+        let mut index = Vec::new();
+        let mut labels = HashMap::new();
+
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension() != Some("log".as_ref()) {
+                continue;
+            }
+
+            // Skip whole files that can't hold a record of the range, files
+            // without a date are filtered by line
+            if let Some(date) = file_date(&path)
+                && (date < first_day || date > last_day)
+            {
+                continue;
+            }
+
+            let file = files.len() as u32;
+            let mut reader = BufReader::new(File::open(&path)?);
+            let mut offset = 0;
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+
+                let text = String::from_utf8_lossy(&line);
+                if let Some(record) = LogRecord::parse(&text) {
+                    labels.insert(record.server_id, record.label);
+                }
+                if let Some(record) = parse_log_line(&text)
+                    && record.timestamp >= start
+                    && record.timestamp < end
+                {
+                    index.push(IndexEntry {
+                        timestamp: record.timestamp,
+                        file,
+                        offset,
+                    });
+                }
+                offset += read as u64;
+            }
+
+            files.push(LogFile { path, reader: None });
+        }
+
+        index.sort_by_key(|entry| entry.timestamp);
+        let clock = index.first().map(|entry| entry.timestamp).unwrap_or(start);
+
+        Ok(Self {
+            files,
+            index,
+            labels,
+            position: 0,
+            clock,
+            speed: ReplaySpeed::X1,
+            paused: true,
+            last_advance: Instant::now(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn labels(&self) -> &HashMap<u32, String> {
+        &self.labels
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn speed(&self) -> ReplaySpeed {
+        self.speed
+    }
+
+    pub fn clock(&self) -> DateTime<Utc> {
+        self.clock
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.index.first().map(|entry| entry.timestamp).unwrap_or(self.clock)
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.index.last().map(|entry| entry.timestamp).unwrap_or(self.clock)
+    }
+
+    /// Seconds from the start of the recording, for the seek bar.
+    pub fn elapsed_seconds(&self) -> f64 {
+        (self.clock - self.start()).num_milliseconds() as f64 / 1000.0
+    }
+
+    pub fn length_seconds(&self) -> f64 {
+        (self.end() - self.start()).num_milliseconds() as f64 / 1000.0
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.last_advance = Instant::now();
+    }
+
+    pub fn set_speed(&mut self, speed: ReplaySpeed) {
+        self.speed = speed;
+    }
+
+    /// Move replay time forward and return the records it passed.
+    pub fn advance(&mut self) -> Vec<MetricRecord> {
+        let elapsed = self.last_advance.elapsed();
+        self.last_advance = Instant::now();
+
+        if self.paused || self.position >= self.index.len() {
+            return Vec::new();
+        }
+
+        let elapsed = ChronoDuration::from_std(elapsed).unwrap_or_default() * self.speed.factor();
+        self.clock = (self.clock + elapsed).min(self.end());
+        self.take_until(self.clock)
+    }
+
+    /// Hand out the next record regardless of its time, while paused.
+    pub fn step(&mut self) -> Option<MetricRecord> {
+        while let Some(entry) = self.index.get(self.position).copied() {
+            self.position += 1;
+            self.clock = entry.timestamp;
+            if let Some(record) = self.read(entry) {
+                return Some(record);
+            }
+        }
+        None
+    }
+
+    /// Jump to a point of the recording and return the records up to
+    /// `history` before it, so the caller can redraw the charts from scratch.
+    pub fn seek(&mut self, seconds: f64, history: ChronoDuration) -> Vec<MetricRecord> {
+        let target = self.start() + ChronoDuration::milliseconds((seconds * 1000.0) as i64);
+        self.clock = target.clamp(self.start(), self.end());
+
+        self.position = self.index.partition_point(|entry| entry.timestamp < self.clock - history);
+        self.take_until(self.clock)
+    }
+
+    fn take_until(&mut self, time: DateTime<Utc>) -> Vec<MetricRecord> {
+        let end = self.position + self.index[self.position..].partition_point(|entry| entry.timestamp <= time);
+        let entries = self.index[self.position..end].to_vec();
+        self.position = end;
+        entries.into_iter().filter_map(|entry| self.read(entry)).collect()
+    }
+
+    // None if the file changed since it was indexed
+    fn read(&mut self, entry: IndexEntry) -> Option<MetricRecord> {
+        let file = &mut self.files[entry.file as usize];
+        match file.read(entry.offset) {
+            Ok(record) => record.filter(|record| record.timestamp == entry.timestamp),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", file.path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+// The day in a `{prefix}_{YYYYMMDD}_{label}.log` name
+fn file_date(path: &Path) -> Option<NaiveDate> {
+    path.file_stem()?
+        .to_str()?
+        .split('_')
+        .find_map(|part| NaiveDate::parse_from_str(part, "%Y%m%d").ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("srd-replay-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn line(received: &str, ts: &str, value: f64) -> String {
+        format!(
+            "{}\t10.0.0.4:51234\t1\t0\tweb-1\tMETRIC ts={} server=0 metric=cpu value={} unit=%\n",
+            received, ts, value
+        )
+    }
+
+    fn values(records: &[MetricRecord]) -> Vec<f64> {
+        records.iter().map(|record| record.value).collect()
+    }
+
+    // 2025-03-17 between 01:00:00 and 01:00:30, with a record spooled by the
+    // agent and only received two days later
+    fn recording(dir: &TempDir) -> Replay {
+        let files = [
+            ("data_20250316_web-1.log", vec![line("2025-03-16T12:00:00Z", "2025-03-16T12:00:00Z", 0.0)]),
+            (
+                "data_20250317_web-1.log",
+                vec![
+                    line("2025-03-17T01:00:00Z", "2025-03-17T01:00:00Z", 1.0),
+                    line("2025-03-17T01:00:10Z", "2025-03-17T01:00:10Z", 2.0),
+                    line("2025-03-17T01:00:20Z", "2025-03-17T01:00:20Z", 3.0),
+                    line("2025-03-17T01:00:21Z", "2025-03-17T01:00:05Z", 1.5),
+                ],
+            ),
+            (
+                "data_20250319_web-1.log",
+                vec![
+                    line("2025-03-19T08:00:00Z", "2025-03-17T01:00:30Z", 4.0),
+                    line("2025-03-19T08:00:00Z", "2025-03-19T08:00:00Z", 5.0),
+                ],
+            ),
+            ("data_20250401_web-1.log", vec![line("2025-04-01T00:00:00Z", "2025-03-17T01:00:40Z", 6.0)]),
+        ];
+        for (name, lines) in files {
+            fs::write(dir.0.join(name), lines.concat()).unwrap();
+        }
+
+        let day = NaiveDate::from_ymd_opt(2025, 3, 17).unwrap();
+        Replay::load(&dir.0, day, day).unwrap()
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn steps_through_the_records_of_the_range_in_time_order() {
+        let dir = TempDir::new("step");
+        let mut replay = recording(&dir);
+        assert_eq!(replay.labels().get(&0).map(String::as_str), Some("web-1"));
+        assert_eq!((replay.start(), replay.end()), (at("2025-03-17T01:00:00Z"), at("2025-03-17T01:00:30Z")));
+
+        let mut stepped = Vec::new();
+        while let Some(record) = replay.step() {
+            stepped.push(record);
+        }
+        assert_eq!(values(&stepped), [1.0, 1.5, 2.0, 3.0, 4.0]);
+        assert_eq!(replay.clock(), at("2025-03-17T01:00:30Z"));
+    }
+
+    #[test]
+    fn seeking_returns_the_history_before_the_new_position() {
+        let dir = TempDir::new("seek");
+        let mut replay = recording(&dir);
+
+        let records = replay.seek(20.0, ChronoDuration::seconds(10));
+        assert_eq!(values(&records), [2.0, 3.0]);
+        assert_eq!(replay.clock(), at("2025-03-17T01:00:20Z"));
+        assert_eq!(replay.step().map(|record| record.value), Some(4.0));
+
+        // Backwards too, and clamped to the recording
+        assert_eq!(values(&replay.seek(-5.0, ChronoDuration::zero())), [1.0]);
+        assert_eq!(values(&replay.seek(3600.0, ChronoDuration::zero())), [4.0]);
+    }
+
+    #[test]
+    fn advancing_plays_at_the_chosen_speed() {
+        let dir = TempDir::new("advance");
+        let mut replay = recording(&dir);
+        replay.last_advance = Instant::now() - std::time::Duration::from_secs(1);
+        assert_eq!(replay.advance(), []);
+
+        replay.toggle_pause();
+        replay.set_speed(ReplaySpeed::X10);
+        replay.last_advance = Instant::now() - std::time::Duration::from_secs(1);
+        assert_eq!(values(&replay.advance()), [1.0, 1.5, 2.0]);
+
+        // Stops at the end of the recording
+        replay.last_advance = Instant::now() - std::time::Duration::from_secs(60);
+        assert_eq!(values(&replay.advance()), [3.0, 4.0]);
+        assert_eq!(replay.clock(), replay.end());
+    }
+}
@@ -3,17 +3,23 @@ use iced::{
     Element,
     Length,
 };
-use crate::stressapp::message::parse_message;
 use super::{
-    message::{AppMessage, BasicMessage},
-    util_chart::UtilChart,
+    message::BasicMessage,
+    util_chart::{ChartMessage, ChartSettings, UtilChart},
 };
+use crate::protocol::Metric;
 
 pub struct ServerChart {
     //holds the various charts
-    util_charts: Vec<(u8, UtilChart)>,
+    util_charts: Vec<(Metric, UtilChart)>,
     chart_height: f32,
     pending_messages: Vec<BasicMessage>,
+    //settings new charts start with
+    settings: ChartSettings,
+    //server label shown in tooltips
+    label: String,
+    //metrics with a firing alert, kept for charts that don't exist yet
+    alerting: Vec<Metric>,
 }
 
 impl Default for ServerChart {
@@ -22,6 +28,9 @@ impl Default for ServerChart {
             util_charts: Default::default(),
             chart_height: 300.0,
             pending_messages: Default::default(),
+            settings: ChartSettings::default(),
+            label: String::new(),
+            alerting: Vec::new(),
         }
 
     }
@@ -38,6 +47,54 @@ impl ServerChart {
         !self.is_initialized() || !self.pending_messages.is_empty()
     }
 
+    pub fn charts(&self) -> impl Iterator<Item = (&Metric, &UtilChart)> {
+        self.util_charts.iter().map(|(metric, chart)| (metric, chart))
+    }
+
+    pub fn charts_mut(&mut self) -> impl Iterator<Item = (&Metric, &mut UtilChart)> {
+        self.util_charts.iter_mut().map(|(metric, chart)| (&*metric, chart))
+    }
+
+    pub fn with_settings(settings: ChartSettings, label: String) -> Self {
+        Self {
+            settings,
+            label,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_alert(&mut self, metric: &Metric, alerting: bool) {
+        self.alerting.retain(|m| m != metric);
+        if alerting {
+            self.alerting.push(metric.clone());
+        }
+
+        if let Some((_, chart)) = self.util_charts.iter_mut().find(|(m, _)| m == metric) {
+            chart.set_alert(alerting);
+        }
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        for (_, chart) in &mut self.util_charts {
+            chart.set_server(label.clone());
+        }
+        self.label = label;
+    }
+
+    pub fn handle_message(&mut self, metric: &Metric, message: ChartMessage) {
+        if let Some((_, chart)) = self.util_charts.iter_mut().find(|(m, _)| m == metric) {
+            chart.handle_message(message);
+        }
+    }
+
+    /// Apply a message to every chart, settings also become the default for new ones.
+    pub fn handle_message_all(&mut self, message: ChartMessage) {
+        self.settings.apply(&message);
+        for (_, chart) in &mut self.util_charts {
+            chart.handle_message(message.clone());
+        }
+    }
+
     pub fn add_message(&mut self, basic_msg: BasicMessage) {
         self.pending_messages.push(basic_msg);
     }
@@ -48,19 +105,22 @@ impl ServerChart {
         }
 
         for msg in &self.pending_messages {
-            if !self.util_charts.iter().any(|e| e.0 == msg.stress_tester) {
+            if !self.util_charts.iter().any(|e| e.0 == msg.metric) {
                 //Add Missing chart
-                let new_chart = UtilChart::new((msg.timestamp, msg.percentage));
+                let mut new_chart = UtilChart::new((msg.timestamp, msg.value)).with_settings(self.settings);
+                new_chart.set_server(self.label.clone());
+                new_chart.set_alert(self.alerting.contains(&msg.metric));
                 self.util_charts
-                    .append(&mut vec![(msg.stress_tester, new_chart)]);
+                    .append(&mut vec![(msg.metric.clone(), new_chart)]);
             }
         }
 
         //Updates each utility chart based on message
-        for (stress, chart) in &mut self.util_charts {
+        for (metric, chart) in &mut self.util_charts {
             for msg in &self.pending_messages {
-                if stress == &msg.stress_tester {
-                    chart.push_data(msg.timestamp, msg.percentage);
+                if metric == &msg.metric {
+                    chart.set_unit(&msg.unit);
+                    chart.push_data(msg.timestamp, msg.value);
                 }
             }
         }
@@ -68,7 +128,7 @@ impl ServerChart {
         self.pending_messages.clear();
     }
 
-    pub fn view(&self) -> Element<AppMessage> {
+    pub fn view(&self) -> Element<'_, (Metric, ChartMessage)> {
         if !self.is_initialized() {
             Text::new("Loading...")
                 .align_x(Horizontal::Center)
@@ -76,7 +136,6 @@ impl ServerChart {
                 .into()
         } else {
             let chart_height = self.chart_height;
-            let mut title: &str;
 
             let mut row = Row::new()
                 .spacing(15)
@@ -86,20 +145,13 @@ impl ServerChart {
                 .align_y(Alignment::Center);
 
             //Add the UtilChart
-            for (stressor, chart) in &self.util_charts {
-                match stressor {
-                    0 => title = "cpu",
-                    1 => title = "ip",
-                    2 => title = "network",
-                    3 => title = "fs",
-                    4 => title = "memory",
-                    _ => title = "",
-                }
-                row = row.push(chart.view(title.to_string(), chart_height));
+            for (metric, chart) in &self.util_charts {
+                let view = chart.view(metric.to_string(), chart_height);
+                row = row.push(view.map(move |message| (metric.clone(), message)));
                 row = row.push(Space::new(Length::Fill, Length::Fixed(50.0)));
             }
 
-            Element::new(row).into()
+            Element::new(row)
         }
     }
 }
@@ -1,63 +1,324 @@
-use std::{collections::VecDeque, time::Duration};
+use std::{collections::VecDeque, fmt, ops::Range, time::Duration};
 
-use super::message::AppMessage;
-use chrono::{DateTime, Utc};
+use crate::store::rollup::{Rollup, Tier, RAW_SPAN};
+use chrono::{DateTime, Local, Utc};
 use iced::{
-    Alignment, Element, Length, Size,
+    Alignment, Element, Length, Point, Rectangle, Size,
+    event::Status,
+    mouse::{self, Cursor, ScrollDelta},
     widget::{
-        Column, Text,
-        canvas::{Cache, Frame, Geometry},
+        button, Column, Row, Text,
+        canvas::{Cache, Event, Frame, Geometry},
     },
 };
 use plotters_iced::{Chart, ChartBuilder, ChartWidget, DrawingBackend, Renderer};
 
 const PLOT_SECONDS: usize = 60; //min
 
+// Layout of the plot inside the widget, needed to map the cursor to a time
+const MARGIN: f32 = 20.0;
+const Y_LABEL_AREA: f32 = 28.0;
+const X_LABEL_AREA: f32 = 24.0;
+
+// Limits of zooming
+const MIN_WINDOW: chrono::Duration = chrono::Duration::seconds(10);
+const MAX_WINDOW: chrono::Duration = chrono::Duration::days(30);
+// Window change per wheel notch
+const ZOOM_STEP: f32 = 0.8;
+
+/// Time span a chart shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeWindow {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
+    Custom(chrono::Duration),
+}
+
+impl TimeWindow {
+    pub const PRESETS: [TimeWindow; 4] = [
+        TimeWindow::OneMinute,
+        TimeWindow::FiveMinutes,
+        TimeWindow::OneHour,
+        TimeWindow::OneDay,
+    ];
+
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            TimeWindow::OneMinute => chrono::Duration::minutes(1),
+            TimeWindow::FiveMinutes => chrono::Duration::minutes(5),
+            TimeWindow::OneHour => chrono::Duration::hours(1),
+            TimeWindow::OneDay => chrono::Duration::days(1),
+            TimeWindow::Custom(duration) => *duration,
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeWindow::OneMinute => write!(f, "1 min"),
+            TimeWindow::FiveMinutes => write!(f, "5 min"),
+            TimeWindow::OneHour => write!(f, "1 h"),
+            TimeWindow::OneDay => write!(f, "24 h"),
+            TimeWindow::Custom(duration) => write!(f, "{} min", duration.num_minutes()),
+        }
+    }
+}
+
+/// Time zone the time axis and tooltip are shown in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplayZone {
+    #[default]
+    Local,
+    Utc,
+}
+
+impl DisplayZone {
+    pub const ALL: [DisplayZone; 2] = [DisplayZone::Local, DisplayZone::Utc];
+
+    pub fn format(&self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            DisplayZone::Local => time.with_timezone(&Local).format(format).to_string(),
+            DisplayZone::Utc => time.format(format).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DisplayZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayZone::Local => write!(f, "Local"),
+            DisplayZone::Utc => write!(f, "UTC"),
+        }
+    }
+}
+
+/// Settings set for all charts at once, which new charts start with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChartSettings {
+    pub window: TimeWindow,
+    pub zone: DisplayZone,
+}
+
+impl Default for ChartSettings {
+    fn default() -> Self {
+        Self {
+            window: TimeWindow::OneMinute,
+            zone: DisplayZone::default(),
+        }
+    }
+}
+
+impl ChartSettings {
+    pub fn apply(&mut self, message: &ChartMessage) {
+        match message {
+            ChartMessage::SetWindow(window) => self.window = *window,
+            ChartMessage::SetZone(zone) => self.zone = *zone,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ChartMessage {
+    SetWindow(TimeWindow),
+    SetZone(DisplayZone),
+    // Scale the window by factor, keeping the time at anchor (0..1 across the plot) in place
+    Zoom { factor: f32, anchor: f32 },
+    // Move the view back by a fraction of the window, negative moves forward
+    Pan(f32),
+    FollowLive,
+}
+
+/// Data from the store for the parts of the window the raw points don't cover.
+pub enum History {
+    Raw(Vec<(DateTime<Utc>, f32)>),
+    Rollups(Vec<Rollup>),
+}
+
+// Mouse interaction in progress on one chart
+#[derive(Default)]
+pub struct ChartState {
+    drag_from: Option<Point>,
+    //cursor position over the chart, for the crosshair
+    hover: Option<Point>,
+}
+
 pub struct UtilChart {
     cache: Cache,
     data_points: VecDeque<(DateTime<Utc>, f32)>,
     limit: Duration,
+    //time span the chart shows
+    window: chrono::Duration,
+    //end of the view, None follows the newest data
+    end: Option<DateTime<Utc>>,
+    history: Option<History>,
+    zone: DisplayZone,
+    //server label for the tooltip
+    server: String,
+    //unit of the values, "%" keeps the axis at 0..100
+    unit: String,
+    //an alert rule fires on this chart
+    alerting: bool,
 }
 
 impl UtilChart {
     pub fn new(data: (DateTime<Utc>, f32)) -> Self {
+        let window = chrono::Duration::seconds(PLOT_SECONDS as i64);
+
         Self {
             cache: Cache::new(),
             data_points: VecDeque::from([data]),
-            limit: Duration::from_secs(PLOT_SECONDS as u64),
+            limit: raw_limit(window),
+            window,
+            end: None,
+            history: None,
+            zone: DisplayZone::default(),
+            server: String::new(),
+            unit: String::new(),
+            alerting: false,
         }
     }
 
-    pub fn push_data(&mut self, time: DateTime<Utc>, percentage: f32) {
-        let cur_ms = time.timestamp_millis();
-        self.data_points.push_front((time, percentage));
-        loop {
-            if let Some((time, _)) = self.data_points.back() {
-                let diff = Duration::from_millis((cur_ms - time.timestamp_millis()) as u64);
-                if diff > self.limit {
-                    self.data_points.pop_back();
-                    continue;
+    pub fn with_settings(mut self, settings: ChartSettings) -> Self {
+        self.set_window(settings.window.duration());
+        self.zone = settings.zone;
+        self
+    }
+
+    pub fn set_alert(&mut self, alerting: bool) {
+        if self.alerting != alerting {
+            self.alerting = alerting;
+            self.cache.clear();
+        }
+    }
+
+    pub fn set_server(&mut self, server: String) {
+        self.server = server;
+        self.cache.clear();
+    }
+
+    pub fn set_unit(&mut self, unit: &str) {
+        if self.unit != unit {
+            self.unit = unit.to_string();
+            self.cache.clear();
+        }
+    }
+
+    /// Rollup tier the window needs, raw points are enough for short ones.
+    pub fn tier(&self) -> Tier {
+        Tier::for_span(self.window)
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.end.is_none()
+    }
+
+    /// Time range currently shown.
+    pub fn visible_range(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let end = self.end.unwrap_or_else(|| self.newest_time());
+        (end - self.window, end)
+    }
+
+    pub fn set_history(&mut self, history: Option<History>) {
+        self.history = history;
+        self.cache.clear();
+    }
+
+    pub fn has_history(&self) -> bool {
+        self.history.is_some()
+    }
+
+    pub fn handle_message(&mut self, message: ChartMessage) {
+        match message {
+            ChartMessage::SetWindow(window) => self.set_window(window.duration()),
+            ChartMessage::SetZone(zone) => self.zone = zone,
+            ChartMessage::Zoom { factor, anchor } => {
+                let (start, end) = self.visible_range();
+                let window = self.scaled_window(factor);
+
+                // Following zooms around the live edge, otherwise around the cursor
+                if !self.is_following() {
+                    let anchor_time = start + scale(end - start, anchor);
+                    self.end = Some(anchor_time + scale(window, 1.0 - anchor));
                 }
+                self.set_window(window);
             }
-            break;
+            ChartMessage::Pan(fraction) => {
+                let (_, end) = self.visible_range();
+                let end = end - scale(self.window, fraction);
+
+                // Dragging past the newest data goes back to following it
+                self.end = (end < self.newest_time()).then_some(end);
+            }
+            ChartMessage::FollowLive => self.end = None,
         }
         self.cache.clear();
     }
 
-    pub fn view(&self, title: String, chart_height: f32) -> Element<AppMessage> {
+    fn set_window(&mut self, window: chrono::Duration) {
+        self.window = window.clamp(MIN_WINDOW, MAX_WINDOW);
+        self.limit = raw_limit(self.window);
+    }
+
+    fn scaled_window(&self, factor: f32) -> chrono::Duration {
+        scale(self.window, factor).clamp(MIN_WINDOW, MAX_WINDOW)
+    }
+
+    fn newest_time(&self) -> DateTime<Utc> {
+        self.data_points
+            .front()
+            .map(|point| point.0)
+            .unwrap_or(DateTime::from_timestamp(0, 0).unwrap())
+    }
+
+    pub fn push_data(&mut self, time: DateTime<Utc>, percentage: f32) {
+        // Newest first. Spooled samples replayed after an outage arrive
+        // behind live ones, so they go where their time puts them.
+        let index = self.data_points.partition_point(|(t, _)| *t > time);
+        self.data_points.insert(index, (time, percentage));
+
+        let newest_ms = self.newest_time().timestamp_millis();
+        while let Some((time, _)) = self.data_points.back() {
+            let age = Duration::from_millis(newest_ms.saturating_sub(time.timestamp_millis()).max(0) as u64);
+            if age <= self.limit {
+                break;
+            }
+            self.data_points.pop_back();
+        }
+        self.cache.clear();
+    }
+
+    pub fn view(&self, title: String, chart_height: f32) -> Element<'_, ChartMessage> {
+        // Window of this chart only, the toolbar above sets all of them
+        let mut controls = Row::new().spacing(5).align_y(Alignment::Center);
+        for window in TimeWindow::PRESETS {
+            let selected = self.window == window.duration();
+            controls = controls.push(
+                button(Text::new(window.to_string()).size(12))
+                    .on_press_maybe((!selected).then_some(ChartMessage::SetWindow(window))),
+            );
+        }
+        controls = controls.push(
+            button(Text::new("Live").size(12)).on_press_maybe((!self.is_following()).then_some(ChartMessage::FollowLive)),
+        );
+
         Column::new()
             .width(Length::Fill)
             .height(Length::Fill)
             .spacing(5)
             .align_x(Alignment::Center)
-            .push(Text::new(title))
+            .push(Text::new(if self.alerting { format!("{} (alert)", title) } else { title }))
             .push(ChartWidget::new(self).height(Length::Fixed(chart_height)))
+            .push(controls)
             .into()
     }
 }
 
-impl Chart<AppMessage> for UtilChart {
-    type State = ();
+impl Chart<ChartMessage> for UtilChart {
+    type State = ChartState;
 
     #[inline]
     fn draw<R: Renderer, F: Fn(&mut Frame)>(
@@ -69,25 +330,104 @@ impl Chart<AppMessage> for UtilChart {
         renderer.draw_cache(&self.cache, bounds, draw_fn)
     }
 
-    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut chart: ChartBuilder<DB>) {
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (Status, Option<ChartMessage>) {
+        let plot_width = (bounds.width - 2.0 * MARGIN - Y_LABEL_AREA).max(1.0);
+
+        let Event::Mouse(event) = event else {
+            return (Status::Ignored, None);
+        };
+
+        match event {
+            mouse::Event::WheelScrolled { delta } => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (Status::Ignored, None);
+                };
+                let lines = match delta {
+                    ScrollDelta::Lines { y, .. } => y,
+                    ScrollDelta::Pixels { y, .. } => y / 50.0,
+                };
+                let anchor = ((position.x - MARGIN - Y_LABEL_AREA) / plot_width).clamp(0.0, 1.0);
+
+                // Scrolling up zooms in
+                let factor = ZOOM_STEP.powf(lines);
+                (Status::Captured, Some(ChartMessage::Zoom { factor, anchor }))
+            }
+            mouse::Event::ButtonPressed(mouse::Button::Left) => match cursor.position_in(bounds) {
+                Some(position) => {
+                    state.drag_from = Some(position);
+                    (Status::Captured, None)
+                }
+                None => (Status::Ignored, None),
+            },
+            mouse::Event::CursorMoved { .. } => {
+                // Crosshair, redrawn on every move
+                let hover = cursor.position_in(bounds);
+                if hover != state.hover {
+                    state.hover = hover;
+                    self.cache.clear();
+                }
+
+                let (Some(from), Some(position)) = (state.drag_from, cursor.position_from(bounds.position())) else {
+                    return (Status::Ignored, None);
+                };
+                state.drag_from = Some(position);
+
+                // Dragging right shows older data
+                let fraction = (position.x - from.x) / plot_width;
+                (Status::Captured, Some(ChartMessage::Pan(fraction)))
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Left) if state.drag_from.is_some() => {
+                state.drag_from = None;
+                (Status::Captured, None)
+            }
+            mouse::Event::CursorLeft if state.hover.is_some() => {
+                state.hover = None;
+                self.cache.clear();
+                (Status::Ignored, None)
+            }
+            _ => (Status::Ignored, None),
+        }
+    }
+
+    fn mouse_interaction(&self, state: &Self::State, bounds: Rectangle, cursor: Cursor) -> mouse::Interaction {
+        if state.drag_from.is_some() {
+            mouse::Interaction::Grabbing
+        } else if cursor.is_over(bounds) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::Idle
+        }
+    }
+
+    fn build_chart<DB: DrawingBackend>(&self, state: &Self::State, mut chart: ChartBuilder<DB>) {
         use plotters::prelude::*;
 
         const PLOT_LINE_COLOR: RGBColor = RGBColor(0, 175, 255);
 
         // Acquire time range
-        let newest_time = self
-            .data_points
-            .front()
-            .unwrap_or(&(DateTime::from_timestamp(0, 0).unwrap(), 0.0))
-            .0;
-        let oldest_time = newest_time - chrono::Duration::seconds(PLOT_SECONDS as i64);
+        let (oldest_time, newest_time) = self.visible_range();
+        let values = self.value_range();
+        let time_format = axis_format(self.window);
         let mut chart = chart
-            .x_label_area_size(0)
-            .y_label_area_size(28)
-            .margin(20)
-            .build_cartesian_2d(oldest_time..newest_time, 0.0..100.0 as f32)
+            .x_label_area_size(X_LABEL_AREA as u32)
+            .y_label_area_size(Y_LABEL_AREA as u32)
+            .margin(MARGIN as u32)
+            .build_cartesian_2d(oldest_time..newest_time, values.clone())
             .expect("failed to build chart");
 
+        if self.alerting {
+            chart
+                .plotting_area()
+                .fill(&plotters::style::colors::RED.mix(0.08))
+                .expect("failed to draw alert highlight");
+        }
+
         chart
             .configure_mesh()
             .bold_line_style(plotters::style::colors::BLUE.mix(0.1))
@@ -100,19 +440,308 @@ impl Chart<AppMessage> for UtilChart {
                     .color(&plotters::style::colors::BLUE.mix(0.65))
                     .transform(FontTransform::Rotate90),
             )
-            .y_label_formatter(&|y: &f32| format!("{}%", y))
+            .y_label_formatter(&|y: &f32| format!("{}{}", y, self.unit))
+            .x_labels(6)
+            .x_label_style(("sans-serif", 13).into_font().color(&plotters::style::colors::BLUE.mix(0.65)))
+            .x_label_formatter(&|x: &DateTime<Utc>| self.zone.format(*x, time_format))
             .draw()
             .expect("failed to draw chart mesh");
 
+        match &self.history {
+            // Min/max band around the average of the rollups, the raw points
+            // are drawn over the part they still cover
+            Some(History::Rollups(history)) if !history.is_empty() => {
+                let band: Vec<(DateTime<Utc>, f32)> = history
+                    .iter()
+                    .map(|r| (r.start, r.min as f32))
+                    .chain(history.iter().rev().map(|r| (r.start, r.max as f32)))
+                    .collect();
+
+                chart
+                    .draw_series(std::iter::once(Polygon::new(band, PLOT_LINE_COLOR.mix(0.12))))
+                    .expect("failed to draw chart band");
+                chart
+                    .draw_series(LineSeries::new(
+                        history.iter().map(|r| (r.start, r.avg as f32)),
+                        ShapeStyle::from(PLOT_LINE_COLOR.mix(0.6)).stroke_width(2),
+                    ))
+                    .expect("failed to draw chart average");
+            }
+            // Raw points of a view that is older than what is kept in memory
+            Some(History::Raw(points)) => {
+                chart
+                    .draw_series(
+                        AreaSeries::new(points.iter().copied(), values.start, PLOT_LINE_COLOR.mix(0.175))
+                            .border_style(ShapeStyle::from(PLOT_LINE_COLOR).stroke_width(2)),
+                    )
+                    .expect("failed to draw chart history");
+            }
+            _ => {}
+        }
+
         chart
             .draw_series(
                 AreaSeries::new(
                     self.data_points.iter().map(|x| (x.0, x.1)),
-                    0 as f32,
+                    values.start,
                     PLOT_LINE_COLOR.mix(0.175),
                 )
                 .border_style(ShapeStyle::from(PLOT_LINE_COLOR).stroke_width(2)),
             )
             .expect("failed to draw chart data");
+
+        // Crosshair at the cursor and the values of the nearest point
+        let Some(hover) = state.hover else {
+            return;
+        };
+        let (x_range, y_range) = chart.plotting_area().get_pixel_range();
+        let (x, y) = (hover.x as i32, hover.y as i32);
+        if !x_range.contains(&x) || !y_range.contains(&y) {
+            return;
+        }
+
+        let fraction_x = (x - x_range.start) as f32 / (x_range.end - x_range.start).max(1) as f32;
+        let fraction_y = (y - y_range.start) as f32 / (y_range.end - y_range.start).max(1) as f32;
+        let time = oldest_time + scale(newest_time - oldest_time, fraction_x);
+        let value = values.start + (values.end - values.start) * (1.0 - fraction_y);
+
+        let crosshair = ShapeStyle::from(plotters::style::colors::BLACK.mix(0.35)).stroke_width(1);
+        chart
+            .draw_series([
+                PathElement::new(vec![(time, values.start), (time, values.end)], crosshair),
+                PathElement::new(vec![(oldest_time, value), (newest_time, value)], crosshair),
+            ])
+            .expect("failed to draw crosshair");
+
+        let Some((point_time, point_value, detail)) = self.nearest(time) else {
+            return;
+        };
+        let mut lines = vec![
+            self.zone.format(point_time, "%Y-%m-%d %H:%M:%S"),
+            match detail {
+                Some(rollup) => format!(
+                    "{:.1}{} (min {:.1} max {:.1} p95 {:.1})",
+                    point_value, self.unit, rollup.min, rollup.max, rollup.p95
+                ),
+                None => format!("{:.1}{}", point_value, self.unit),
+            },
+        ];
+        if !self.server.is_empty() {
+            lines.push(self.server.clone());
+        }
+
+        // Keep the box inside the plot, left of the point on the right half
+        const LINE_HEIGHT: i32 = 16;
+        let width = 8 + 7 * lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+        let height = 6 + LINE_HEIGHT * lines.len() as i32;
+        let dx = if fraction_x > 0.5 { -width - 8 } else { 8 };
+        let dy = if point_value > (values.start + values.end) / 2.0 { 8 } else { -height - 8 };
+
+        let font = ("sans-serif", 13).into_font().color(&plotters::style::colors::BLACK);
+        let anchor = (point_time, point_value);
+        chart
+            .draw_series(std::iter::once(
+                EmptyElement::at(anchor)
+                    + Circle::new((0, 0), 4, PLOT_LINE_COLOR.filled())
+                    + Rectangle::new([(dx, dy), (dx + width, dy + height)], plotters::style::colors::WHITE.mix(0.9).filled())
+                    + Rectangle::new([(dx, dy), (dx + width, dy + height)], PLOT_LINE_COLOR.stroke_width(1)),
+            ))
+            .expect("failed to draw tooltip");
+        chart
+            .draw_series(lines.into_iter().enumerate().map(|(index, line)| {
+                EmptyElement::at(anchor) + Text::new(line, (dx + 4, dy + 4 + LINE_HEIGHT * index as i32), font.clone())
+            }))
+            .expect("failed to draw tooltip text");
+    }
+}
+
+impl UtilChart {
+    // Value axis: 0..100 for percentages unless a value goes past it,
+    // otherwise fitted to the visible values with some headroom
+    fn value_range(&self) -> Range<f32> {
+        let (from, to) = self.visible_range();
+        let visible = |time: &DateTime<Utc>| (from..=to).contains(time);
+
+        let raw = self.data_points.iter().filter(|(t, _)| visible(t)).map(|(_, v)| *v);
+        let history: Vec<f32> = match &self.history {
+            Some(History::Raw(points)) => points.iter().filter(|(t, _)| visible(t)).map(|(_, v)| *v).collect(),
+            Some(History::Rollups(rollups)) => rollups
+                .iter()
+                .filter(|r| visible(&r.start))
+                .flat_map(|r| [r.min as f32, r.max as f32])
+                .collect(),
+            None => Vec::new(),
+        };
+        let (min, max) = raw
+            .chain(history)
+            .filter(|v| v.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+
+        if self.unit == "%" {
+            return min.min(0.0)..max.max(100.0);
+        }
+        if min > max {
+            return 0.0..1.0;
+        }
+
+        let low = min.min(0.0);
+        let span = max - low;
+        if span <= 0.0 {
+            return low..low + 1.0;
+        }
+        let headroom = span * 0.05;
+        if min < 0.0 { low - headroom..max + headroom } else { low..max + headroom }
+    }
+
+    // Point closest in time, from memory or the store, with its rollup if it is one
+    fn nearest(&self, time: DateTime<Utc>) -> Option<(DateTime<Utc>, f32, Option<Rollup>)> {
+        let distance = |t: DateTime<Utc>| (t - time).num_milliseconds().abs();
+
+        let raw = self.data_points.iter().copied().chain(match &self.history {
+            Some(History::Raw(points)) => points.clone(),
+            _ => Vec::new(),
+        });
+        let nearest_raw = raw.min_by_key(|(t, _)| distance(*t)).map(|(t, v)| (t, v, None));
+
+        let nearest_rollup = match &self.history {
+            Some(History::Rollups(rollups)) => rollups
+                .iter()
+                .min_by_key(|r| distance(r.start))
+                .map(|r| (r.start, r.avg as f32, Some(*r))),
+            _ => None,
+        };
+
+        [nearest_raw, nearest_rollup]
+            .into_iter()
+            .flatten()
+            .min_by_key(|(t, _, _)| distance(*t))
+    }
+}
+
+// Coarser labels as the window grows
+fn axis_format(window: chrono::Duration) -> &'static str {
+    if window <= chrono::Duration::minutes(10) {
+        "%H:%M:%S"
+    } else if window <= chrono::Duration::days(1) {
+        "%H:%M"
+    } else {
+        "%m-%d %H:%M"
+    }
+}
+
+// Raw points are kept for the window, or only its newest part when rollups cover the rest
+fn raw_limit(window: chrono::Duration) -> Duration {
+    window.min(RAW_SPAN).to_std().unwrap_or_default()
+}
+
+fn scale(duration: chrono::Duration, factor: f32) -> chrono::Duration {
+    chrono::Duration::milliseconds((duration.num_milliseconds() as f64 * factor as f64) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_points_are_kept_in_time_order() {
+        let now = Utc::now();
+        let mut chart = UtilChart::new((now, 1.0));
+
+        // Replayed from the spool after newer live points
+        chart.push_data(now - chrono::Duration::seconds(5), 2.0);
+        chart.push_data(now + chrono::Duration::seconds(1), 3.0);
+        chart.push_data(now - chrono::Duration::seconds(2), 4.0);
+
+        let values: Vec<f32> = chart.data_points.iter().map(|(_, v)| *v).collect();
+        assert_eq!(values, [3.0, 1.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn points_older_than_the_limit_are_dropped() {
+        let now = Utc::now();
+        let mut chart = UtilChart::new((now, 1.0));
+        let limit = chrono::Duration::from_std(chart.limit).unwrap();
+
+        chart.push_data(now - limit - chrono::Duration::seconds(1), 2.0);
+
+        assert_eq!(chart.data_points.len(), 1);
+        assert_eq!(chart.newest_time(), now);
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    // A minute of points every second up to 12:10:00, following live
+    fn minute_chart() -> UtilChart {
+        let end = at("2025-03-17T12:10:00Z");
+        let mut chart = UtilChart::new((end, 60.0));
+        for i in 1..=60 {
+            chart.push_data(end - chrono::Duration::seconds(i), (60 - i) as f32);
+        }
+        chart
+    }
+
+    #[test]
+    fn zooming_keeps_the_live_edge_or_the_anchor_in_place() {
+        let mut chart = minute_chart();
+        chart.handle_message(ChartMessage::Zoom { factor: 0.5, anchor: 0.25 });
+        assert!(chart.is_following());
+        assert_eq!(chart.visible_range(), (at("2025-03-17T12:09:30Z"), at("2025-03-17T12:10:00Z")));
+
+        // Away from live data the time under the cursor stays put
+        chart.handle_message(ChartMessage::Pan(1.0));
+        assert_eq!(chart.visible_range(), (at("2025-03-17T12:09:00Z"), at("2025-03-17T12:09:30Z")));
+        chart.handle_message(ChartMessage::Zoom { factor: 2.0, anchor: 0.5 });
+        assert_eq!(chart.visible_range(), (at("2025-03-17T12:08:45Z"), at("2025-03-17T12:09:45Z")));
+
+        // Within the limits
+        chart.handle_message(ChartMessage::Zoom { factor: 0.01, anchor: 0.5 });
+        assert_eq!(chart.window, MIN_WINDOW);
+    }
+
+    #[test]
+    fn panning_past_the_newest_point_follows_live_again() {
+        let mut chart = minute_chart();
+        chart.handle_message(ChartMessage::Pan(0.5));
+        assert!(!chart.is_following());
+        assert_eq!(chart.visible_range(), (at("2025-03-17T12:08:30Z"), at("2025-03-17T12:09:30Z")));
+
+        chart.handle_message(ChartMessage::Pan(-0.25));
+        assert_eq!(chart.visible_range().1, at("2025-03-17T12:09:45Z"));
+        chart.handle_message(ChartMessage::Pan(-1.0));
+        assert!(chart.is_following());
+    }
+
+    #[test]
+    fn the_nearest_point_comes_from_memory_or_the_rollups() {
+        let mut chart = minute_chart();
+        let (time, value, rollup) = chart.nearest(at("2025-03-17T12:09:40.400Z")).unwrap();
+        assert_eq!((time, value, rollup), (at("2025-03-17T12:09:40Z"), 40.0, None));
+
+        let rollup = Rollup {
+            start: at("2025-03-17T12:00:00Z"),
+            min: 1.0,
+            max: 9.0,
+            avg: 5.0,
+            p95: 8.0,
+            count: 10,
+        };
+        chart.set_history(Some(History::Rollups(vec![rollup])));
+        let nearest = chart.nearest(at("2025-03-17T12:01:00Z")).unwrap();
+        assert_eq!(nearest, (rollup.start, 5.0, Some(rollup)));
+    }
+
+    #[test]
+    fn the_value_axis_fits_the_unit_and_the_data() {
+        let mut chart = minute_chart();
+        chart.set_unit("%");
+        assert_eq!(chart.value_range(), 0.0..100.0);
+
+        chart.set_unit("MB/s");
+        assert_eq!(chart.value_range(), 0.0..63.0);
+
+        chart.push_data(at("2025-03-17T12:10:01Z"), -20.0);
+        assert_eq!(chart.value_range(), -24.0..64.0);
     }
 }
@@ -0,0 +1,252 @@
+//! Optional TLS on the connections between agents and the collector.
+//!
+//! The collector presents a certificate and key from PEM files, the agent
+//! checks it against a CA it is given, so a private CA works without
+//! touching the system trust store. For a local setup:
+//!
+//! ```text
+//! openssl req -x509 -newkey rsa:2048 -nodes -days 365 -subj /CN=srd-ca \
+//!     -keyout ca.key -out ca.pem
+//! openssl req -newkey rsa:2048 -nodes -subj /CN=localhost \
+//!     -keyout collector.key -out collector.csr
+//! openssl x509 -req -in collector.csr -CA ca.pem -CAkey ca.key -days 365 \
+//!     -extfile <(printf "subjectAltName=DNS:localhost,IP:127.0.0.1") -out collector.pem
+//! ```
+//!
+//! Agents can prove who they are with a client certificate from a CA the
+//! collector is given, made the same way:
+//!
+//! ```text
+//! openssl req -newkey rsa:2048 -nodes -subj /CN=web-1 -keyout web-1.key -out web-1.csr
+//! openssl x509 -req -in web-1.csr -CA ca.pem -CAkey ca.key -days 365 -out web-1.pem
+//! openssl x509 -in web-1.pem -noout -fingerprint -sha256
+//! ```
+
+use rustls::crypto::ring;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Environment variables naming the collector's certificate and key.
+pub const CERT_ENV: &str = "SRD_TLS_CERT";
+pub const KEY_ENV: &str = "SRD_TLS_KEY";
+pub const CLIENT_CA_ENV: &str = "SRD_TLS_CLIENT_CA";
+
+/// Certificate chain and key the collector presents.
+#[derive(Debug, Clone)]
+pub struct ServerTls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // CA agents' client certificates are checked against. Agents without
+    // one still complete the handshake, the collector decides what they may do.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl ServerTls {
+    /// From `SRD_TLS_CERT`, `SRD_TLS_KEY` and `SRD_TLS_CLIENT_CA`, None when
+    /// none is set. Setting only some is an error rather than a silent fall
+    /// back to plain TCP.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        Self::from_vars(|name| std::env::var_os(name))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<OsString>) -> io::Result<Option<Self>> {
+        let client_ca = var(CLIENT_CA_ENV).map(PathBuf::from);
+        match (var(CERT_ENV), var(KEY_ENV)) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca,
+            })),
+            (None, None) if client_ca.is_none() => Ok(None),
+            (None, None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} requires {} and {}", CLIENT_CA_ENV, CERT_ENV, KEY_ENV),
+            )),
+            (Some(_), None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is set without {}", CERT_ENV, KEY_ENV),
+            )),
+            (None, Some(_)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is set without {}", KEY_ENV, CERT_ENV),
+            )),
+        }
+    }
+
+    pub fn config(&self) -> io::Result<Arc<ServerConfig>> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_key(&self.key)?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(root_store(ca)?), provider)
+                    .allow_unauthenticated()
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", ca.display(), e)))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+        Ok(Arc::new(config))
+    }
+}
+
+/// How the agent checks the collector's certificate.
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    // CA the collector's certificate has to be signed by
+    pub ca: PathBuf,
+    // Name the certificate has to be issued to, the address' host by default
+    pub server_name: Option<String>,
+    // Certificate chain and key to present to a collector that checks agents
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl ClientTls {
+    pub fn config(&self) -> io::Result<Arc<ClientConfig>> {
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_root_certificates(root_store(&self.ca)?);
+
+        let config = match &self.client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+
+    /// Name to verify, `server_name` or else the host the agent connects to.
+    pub fn server_name(&self, host: &str) -> io::Result<ServerName<'static>> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        ServerName::try_from(name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid server name {}: {}", name, e)))
+    }
+}
+
+fn root_store(ca: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| pem_error(path, e))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| pem_error(path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(path, e))?;
+
+    if certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: no certificates", path.display())));
+    }
+    Ok(certs)
+}
+
+fn pem_error(path: &Path, e: rustls_pki_types::pem::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+pub fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{ClientConnection, ServerConnection};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // Made as in the module docs, valid for 100 years
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/tls").join(name)
+    }
+
+    fn vars(set: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<OsString> {
+        move |name| set.iter().find(|(n, _)| *n == name).map(|(_, value)| value.into())
+    }
+
+    // Complete a handshake over loopback, returning the client's result
+    fn handshake(ca: &str) -> io::Result<()> {
+        let server = ServerTls {
+            cert: testdata("collector.pem"),
+            key: testdata("collector.key"),
+            client_ca: None,
+        }
+        .config()?;
+        let client = ClientTls {
+            ca: testdata(ca),
+            server_name: None,
+            client_cert: None,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let accepted = thread::spawn(move || -> io::Result<()> {
+            let (mut socket, _) = listener.accept()?;
+            let mut connection = ServerConnection::new(server).map_err(tls_error)?;
+            while connection.is_handshaking() {
+                connection.complete_io(&mut socket)?;
+            }
+            Ok(())
+        });
+
+        let mut socket = TcpStream::connect(address)?;
+        let mut connection = ClientConnection::new(client.config()?, client.server_name("localhost")?).map_err(tls_error)?;
+        let result = (|| {
+            while connection.is_handshaking() {
+                connection.complete_io(&mut socket)?;
+            }
+            Ok(())
+        })();
+
+        // The server fails too once the client gives up
+        drop(socket);
+        let _ = accepted.join();
+        result
+    }
+
+    #[test]
+    fn tls_needs_both_certificate_and_key() {
+        assert!(ServerTls::from_vars(vars(&[])).unwrap().is_none());
+
+        let tls = ServerTls::from_vars(vars(&[(CERT_ENV, "c.pem"), (KEY_ENV, "c.key")])).unwrap().unwrap();
+        assert_eq!((tls.cert, tls.key, tls.client_ca), ("c.pem".into(), "c.key".into(), None));
+
+        assert!(ServerTls::from_vars(vars(&[(CERT_ENV, "c.pem")])).is_err());
+        assert!(ServerTls::from_vars(vars(&[(KEY_ENV, "c.key")])).is_err());
+        assert!(ServerTls::from_vars(vars(&[(CLIENT_CA_ENV, "ca.pem")])).is_err());
+    }
+
+    #[test]
+    fn agents_trusting_the_collector_ca_complete_the_handshake() {
+        handshake("ca.pem").unwrap();
+    }
+
+    #[test]
+    fn agents_with_another_ca_refuse_the_collector() {
+        let error = handshake("other-ca.pem").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("UnknownIssuer"), "{}", error);
+    }
+}